@@ -0,0 +1,27 @@
+#ifndef JSONATR_H
+#define JSONATR_H
+
+/* C interface of Jsonatr, available when the crate is built as a shared library with the "ffi" feature:
+ *   cargo rustc --release --lib --features ffi --crate-type cdylib
+ * and linked with the resulting libjsonatr shared library. */
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* Transforms the input JSON (or NULL for no input) by the spec JSON.
+ * Returns the output, to be freed with jsonatr_free, or NULL on failure. */
+char *jsonatr_transform(const char *spec, const char *input);
+
+/* Returns the error of the last failed call on the current thread, or NULL if it succeeded;
+ * the string stays valid until the next call on the same thread, and should not be freed. */
+const char *jsonatr_last_error(void);
+
+/* Frees a string returned by jsonatr_transform. */
+void jsonatr_free(char *s);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
@@ -0,0 +1,215 @@
+use crate::error::JsonatrError;
+use crate::helpers::parse_string;
+#[cfg(feature = "toml")]
+use crate::helpers::toml_to_json;
+use serde_json::Value;
+use simple_error::*;
+
+// A value representation the transformation can be applied to directly, e.g. for YAML→YAML or TOML→TOML
+// pipelines; the backend converts its values to and from the JSON values the engine evaluates,
+// keeping the details specific to its format where JSON can represent them
+pub trait ValueBackend {
+    type Value;
+
+    fn to_json(&self, value: Self::Value) -> Result<Value, JsonatrError>;
+
+    fn to_value(&self, value: Value) -> Result<Self::Value, JsonatrError>;
+
+    fn parse(&self, text: &str) -> Result<Self::Value, JsonatrError>;
+
+    fn render(&self, value: &Self::Value) -> Result<String, JsonatrError>;
+}
+
+// The native representation of the engine
+#[derive(Debug, Clone, Copy, Default)]
+pub struct JsonBackend;
+
+impl ValueBackend for JsonBackend {
+    type Value = Value;
+
+    fn to_json(&self, value: Value) -> Result<Value, JsonatrError> {
+        Ok(value)
+    }
+
+    fn to_value(&self, value: Value) -> Result<Value, JsonatrError> {
+        Ok(value)
+    }
+
+    fn parse(&self, text: &str) -> Result<Value, JsonatrError> {
+        parse_string(text)
+    }
+
+    fn render(&self, value: &Value) -> Result<String, JsonatrError> {
+        Ok(try_with!(
+            serde_json::to_string_pretty(value),
+            "failed to produce JSON output"
+        ))
+    }
+}
+
+// TOML values; datetimes are seen by the engine as strings, and any string in the TOML datetime format
+// becomes a datetime again in the output. The output has no representation for null
+#[cfg(feature = "toml")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TomlBackend;
+
+#[cfg(feature = "toml")]
+impl ValueBackend for TomlBackend {
+    type Value = toml::Value;
+
+    fn to_json(&self, value: toml::Value) -> Result<Value, JsonatrError> {
+        Ok(toml_to_json(value))
+    }
+
+    fn to_value(&self, value: Value) -> Result<toml::Value, JsonatrError> {
+        let value = match value {
+            Value::Null => bail!("null can't be represented in TOML"),
+            Value::Bool(b) => toml::Value::Boolean(b),
+            Value::Number(n) => match n.as_i64() {
+                Some(i) => toml::Value::Integer(i),
+                None => toml::Value::Float(require_with!(
+                    n.as_f64(),
+                    "number {} can't be represented in TOML",
+                    n
+                )),
+            },
+            Value::String(s) => match s.parse::<toml::value::Datetime>() {
+                Ok(datetime) => toml::Value::Datetime(datetime),
+                Err(_) => toml::Value::String(s),
+            },
+            Value::Array(arr) => toml::Value::Array(
+                arr.into_iter()
+                    .map(|v| self.to_value(v))
+                    .collect::<Result<_, _>>()?,
+            ),
+            Value::Object(obj) => {
+                let mut table = toml::map::Map::new();
+                for (k, v) in obj {
+                    table.insert(k, self.to_value(v)?);
+                }
+                toml::Value::Table(table)
+            }
+        };
+        Ok(value)
+    }
+
+    fn parse(&self, text: &str) -> Result<toml::Value, JsonatrError> {
+        text.parse().map_err(|e| JsonatrError::parse("TOML", e))
+    }
+
+    fn render(&self, value: &toml::Value) -> Result<String, JsonatrError> {
+        Ok(try_with!(
+            toml::to_string_pretty(value),
+            "failed to produce TOML output"
+        ))
+    }
+}
+
+// YAML values; a tagged value `!tag value` is seen by the engine as the object {"!tag": value},
+// and such single-key objects become tagged values again in the output.
+// Keys that are not strings are seen as their YAML representation, and stay strings in the output
+#[cfg(feature = "yaml")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct YamlBackend;
+
+#[cfg(feature = "yaml")]
+impl ValueBackend for YamlBackend {
+    type Value = serde_yaml::Value;
+
+    fn to_json(&self, value: serde_yaml::Value) -> Result<Value, JsonatrError> {
+        use serde_yaml::Value as Yaml;
+        let value = match value {
+            Yaml::Null => Value::Null,
+            Yaml::Bool(b) => Value::Bool(b),
+            Yaml::Number(n) => {
+                if let Some(i) = n.as_i64() {
+                    Value::from(i)
+                } else if let Some(u) = n.as_u64() {
+                    Value::from(u)
+                } else {
+                    let f = n.as_f64().unwrap_or(f64::NAN);
+                    Value::Number(require_with!(
+                        serde_json::Number::from_f64(f),
+                        "YAML number {} can't be represented in JSON",
+                        n
+                    ))
+                }
+            }
+            Yaml::String(s) => Value::String(s),
+            Yaml::Sequence(seq) => Value::Array(
+                seq.into_iter()
+                    .map(|v| self.to_json(v))
+                    .collect::<Result<_, _>>()?,
+            ),
+            Yaml::Mapping(mapping) => {
+                let mut obj = serde_json::Map::new();
+                for (k, v) in mapping {
+                    let key = match k {
+                        Yaml::String(s) => s,
+                        k => try_with!(serde_yaml::to_string(&k), "failed to convert YAML key")
+                            .trim_end()
+                            .to_string(),
+                    };
+                    obj.insert(key, self.to_json(v)?);
+                }
+                Value::Object(obj)
+            }
+            Yaml::Tagged(tagged) => {
+                let mut obj = serde_json::Map::new();
+                obj.insert(tagged.tag.to_string(), self.to_json(tagged.value)?);
+                Value::Object(obj)
+            }
+        };
+        Ok(value)
+    }
+
+    fn to_value(&self, value: Value) -> Result<serde_yaml::Value, JsonatrError> {
+        use serde_yaml::value::{Tag, TaggedValue};
+        use serde_yaml::Value as Yaml;
+        let value = match value {
+            Value::Null => Yaml::Null,
+            Value::Bool(b) => Yaml::Bool(b),
+            Value::Number(n) => {
+                if let Some(i) = n.as_i64() {
+                    Yaml::Number(i.into())
+                } else if let Some(u) = n.as_u64() {
+                    Yaml::Number(u.into())
+                } else {
+                    Yaml::Number(n.as_f64().unwrap_or(f64::NAN).into())
+                }
+            }
+            Value::String(s) => Yaml::String(s),
+            Value::Array(arr) => Yaml::Sequence(
+                arr.into_iter()
+                    .map(|v| self.to_value(v))
+                    .collect::<Result<_, _>>()?,
+            ),
+            Value::Object(obj) if obj.len() == 1 && obj.keys().all(|k| k.starts_with('!')) => {
+                let (tag, value) = obj.into_iter().next().unwrap();
+                Yaml::Tagged(Box::new(TaggedValue {
+                    tag: Tag::new(tag),
+                    value: self.to_value(value)?,
+                }))
+            }
+            Value::Object(obj) => {
+                let mut mapping = serde_yaml::Mapping::new();
+                for (k, v) in obj {
+                    mapping.insert(Yaml::String(k), self.to_value(v)?);
+                }
+                Yaml::Mapping(mapping)
+            }
+        };
+        Ok(value)
+    }
+
+    fn parse(&self, text: &str) -> Result<serde_yaml::Value, JsonatrError> {
+        serde_yaml::from_str(text).map_err(|e| JsonatrError::parse("YAML", e))
+    }
+
+    fn render(&self, value: &serde_yaml::Value) -> Result<String, JsonatrError> {
+        Ok(try_with!(
+            serde_yaml::to_string(value),
+            "failed to produce YAML output"
+        ))
+    }
+}
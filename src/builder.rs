@@ -0,0 +1,185 @@
+use crate::error::JsonatrError;
+use crate::output::{NamedOutput, OutputFormat};
+use crate::path::PathEngine;
+use crate::resolver::Resolver;
+use crate::transformer::{ErrorHandler, Input, Limits, Sandbox, Transformer};
+use serde_json::Value;
+use std::fmt;
+use std::sync::Arc;
+
+// Builds a Transformer programmatically; any configuration errors are reported by build()
+#[derive(Clone)]
+pub struct TransformerBuilder {
+    path: String,
+    uses: Vec<String>,
+    inputs: Vec<Input>,
+    output: Option<Value>,
+    outputs: Vec<NamedOutput>,
+    format: OutputFormat,
+    strict: bool,
+    resolver: Option<Arc<dyn Resolver>>,
+    path_engine: Option<Arc<dyn PathEngine>>,
+    include_path: Vec<String>,
+    error_handler: Option<ErrorHandler>,
+    sandbox: Option<Sandbox>,
+    limits: Limits,
+    seed: Option<u64>,
+}
+
+impl fmt::Debug for TransformerBuilder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TransformerBuilder")
+            .field("path", &self.path)
+            .field("uses", &self.uses)
+            .field("inputs", &self.inputs)
+            .field("output", &self.output)
+            .field("outputs", &self.outputs)
+            .field("format", &self.format)
+            .field("strict", &self.strict)
+            .field("include_path", &self.include_path)
+            .field("sandbox", &self.sandbox)
+            .field("limits", &self.limits)
+            .field("seed", &self.seed)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Default for TransformerBuilder {
+    fn default() -> Self {
+        TransformerBuilder {
+            path: ".".to_string(),
+            uses: vec![],
+            inputs: vec![],
+            output: None,
+            outputs: vec![],
+            format: Default::default(),
+            strict: false,
+            resolver: None,
+            path_engine: None,
+            include_path: vec![],
+            error_handler: None,
+            sandbox: None,
+            limits: Default::default(),
+            seed: None,
+        }
+    }
+}
+
+impl TransformerBuilder {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    // the path relative to which the used spec files are resolved; the current directory by default
+    pub fn path(mut self, path: &str) -> Self {
+        self.path = path.to_string();
+        self
+    }
+
+    pub fn use_file(mut self, path: &str) -> Self {
+        self.uses.push(path.to_string());
+        self
+    }
+
+    pub fn input(mut self, input: Input) -> Self {
+        self.inputs.push(input);
+        self
+    }
+
+    pub fn output(mut self, output: Value) -> Self {
+        self.output = Some(output);
+        self
+    }
+
+    pub fn named_output(mut self, output: NamedOutput) -> Self {
+        self.outputs.push(output);
+        self
+    }
+
+    pub fn format(mut self, format: OutputFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    // the resolver for loading the used specs and the files of inputs; the local disk by default
+    pub fn resolver(mut self, resolver: Arc<dyn Resolver>) -> Self {
+        self.resolver = Some(resolver);
+        self
+    }
+
+    // the engine for the paths in expressions; JSONPath by default
+    pub fn path_engine(mut self, engine: Arc<dyn PathEngine>) -> Self {
+        self.path_engine = Some(engine);
+        self
+    }
+
+    // a directory searched for the used specs and the files of inputs not found at their relative paths (repeatable)
+    pub fn include_path(mut self, dir: &str) -> Self {
+        self.include_path.push(dir.to_string());
+        self
+    }
+
+    // decides what becomes of the expressions that fail to evaluate; they are kept unchanged by default
+    pub fn error_handler(mut self, handler: ErrorHandler) -> Self {
+        self.error_handler = Some(handler);
+        self
+    }
+
+    // the inputs and outputs are restricted to the sandbox; build() fails on anything not allowed there
+    pub fn sandbox(mut self, sandbox: Sandbox) -> Self {
+        self.sandbox = Some(sandbox);
+        self
+    }
+
+    pub fn limits(mut self, limits: Limits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    // seeds the pseudo-random builtins, so that each transformation produces the same values
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+
+    pub fn build(self) -> Result<Transformer, JsonatrError> {
+        let mut spec = Transformer::empty(&self.path);
+        if let Some(resolver) = self.resolver {
+            spec.set_resolver(resolver);
+        }
+        if let Some(engine) = self.path_engine {
+            spec.set_path_engine(engine);
+        }
+        if let Some(handler) = self.error_handler {
+            spec.set_error_handler(handler);
+        }
+        for dir in &self.include_path {
+            spec.add_include_path(dir);
+        }
+        spec.set_format(self.format);
+        spec.set_strict(self.strict);
+        spec.set_limits(self.limits);
+        spec.set_seed(self.seed);
+        if let Some(sandbox) = self.sandbox {
+            spec.set_sandbox(sandbox)?;
+        }
+        for path in self.uses {
+            spec.add_use(path)?;
+        }
+        for input in self.inputs {
+            spec.add_input(input)?;
+        }
+        if let Some(output) = self.output {
+            spec.add_output(output)?;
+        }
+        for output in self.outputs {
+            spec.add_named_output(output)?;
+        }
+        Ok(spec)
+    }
+}
@@ -0,0 +1,182 @@
+use simple_error::SimpleError;
+use std::error::Error;
+use std::fmt;
+
+// The errors returned by the public API of Jsonatr
+#[derive(Debug)]
+pub enum JsonatrError {
+    // failed to parse a spec or an input in the given format, e.g. "JSON" or "TOML"
+    ParseError {
+        format: String,
+        source: Box<dyn Error + Send + Sync>,
+        // where the text comes from, e.g. the file of a used spec, if known
+        origin: Option<String>,
+    },
+    // reference to an input that is not defined
+    UnknownInput(String),
+    // malformed JSONPath expression
+    JsonPathError {
+        path: String,
+        reason: String,
+    },
+    // an external command failed to run, or terminated unsuccessfully
+    CommandFailed {
+        command: String,
+        reason: String,
+    },
+    // failed to read or write a file
+    IoError {
+        path: String,
+        source: std::io::Error,
+    },
+    // an expression failed to evaluate, and this fails the transformation, e.g. in strict mode
+    EvaluationError {
+        message: String,
+        // where the expression is in the output, if the failure happened there
+        location: Option<ErrorLocation>,
+    },
+    // the transformation was aborted through its cancellation token
+    Cancelled,
+    // any other error, e.g. an invalid spec
+    Other(String),
+}
+
+impl JsonatrError {
+    pub fn parse<E>(format: &str, source: E) -> JsonatrError
+    where
+        E: Into<Box<dyn Error + Send + Sync>>,
+    {
+        JsonatrError::ParseError {
+            format: format.to_string(),
+            source: source.into(),
+            origin: None,
+        }
+    }
+
+    // records where the text that failed to parse comes from, unless it is known already,
+    // e.g. when the error comes from a spec used by the one given
+    pub fn with_origin(self, origin: &str) -> JsonatrError {
+        match self {
+            JsonatrError::ParseError {
+                format,
+                source,
+                origin: None,
+            } => JsonatrError::ParseError {
+                format,
+                source,
+                origin: Some(origin.to_string()),
+            },
+            other => other,
+        }
+    }
+
+    pub fn io(path: &str, source: std::io::Error) -> JsonatrError {
+        JsonatrError::IoError {
+            path: path.to_string(),
+            source,
+        }
+    }
+
+    #[cfg(feature = "jsonpath")]
+    pub fn jsonpath(path: &str, e: jsonpath::JsonPathError) -> JsonatrError {
+        JsonatrError::JsonPathError {
+            path: path.to_string(),
+            reason: format!("{:?}", e),
+        }
+    }
+
+    pub fn command(command: &str, reason: impl fmt::Display) -> JsonatrError {
+        JsonatrError::CommandFailed {
+            command: command.to_string(),
+            reason: reason.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for JsonatrError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            JsonatrError::ParseError { format, source, .. } => {
+                write!(f, "failed to parse {}, {}", format, source)
+            }
+            JsonatrError::UnknownInput(name) => {
+                write!(f, "found reference to unknown input '{}'", name)
+            }
+            JsonatrError::JsonPathError { path, reason } => {
+                write!(f, "failed to apply JSONPath '{}'; reason: {}", path, reason)
+            }
+            JsonatrError::CommandFailed { command, reason } => {
+                write!(f, "command '{}' failed; reason: {}", command, reason)
+            }
+            JsonatrError::IoError { path, source } => {
+                write!(f, "failed to access file '{}', {}", path, source)
+            }
+            JsonatrError::EvaluationError { message, location } => match location {
+                Some(location) => write!(
+                    f,
+                    "{} at '{}' in expression '{}'",
+                    message, location.pointer, location.expression
+                ),
+                None => write!(f, "{}", message),
+            },
+            JsonatrError::Cancelled => write!(f, "transformation cancelled"),
+            JsonatrError::Other(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl Error for JsonatrError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            JsonatrError::ParseError { source, .. } => Some(source.as_ref()),
+            JsonatrError::IoError { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+// allows the simple_error macros to be used in functions returning JsonatrError
+impl From<SimpleError> for JsonatrError {
+    fn from(e: SimpleError) -> Self {
+        JsonatrError::Other(e.to_string())
+    }
+}
+
+impl From<&str> for JsonatrError {
+    fn from(message: &str) -> Self {
+        JsonatrError::Other(message.to_string())
+    }
+}
+
+impl From<String> for JsonatrError {
+    fn from(message: String) -> Self {
+        JsonatrError::Other(message)
+    }
+}
+
+// The place of a failed expression in the output of the spec
+#[derive(Debug, Clone, PartialEq)]
+pub struct ErrorLocation {
+    // the JSON pointer of the output node, e.g. "/validators/3/address"
+    pub pointer: String,
+    // the expression of the output node
+    pub expression: String,
+    // the builtin being applied when the failure happened, if any
+    pub builtin: Option<String>,
+    // the input that failed, if any, e.g. an unknown one
+    pub input: Option<String>,
+}
+
+// A problem in the spec, found by Transformer::validate
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    // the part of the spec with the problem, e.g. "output", "outputs.report", or "input.version.let.height"
+    pub location: String,
+    pub message: String,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.location, self.message)
+    }
+}
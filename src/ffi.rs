@@ -0,0 +1,89 @@
+use crate::error::JsonatrError;
+use crate::transformer::Transformer;
+use serde_json::Value;
+use std::cell::RefCell;
+use std::ffi::{CStr, CString};
+use std::os::raw::c_char;
+use std::ptr;
+
+// C interface of Jsonatr; see include/jsonatr.h for the declarations.
+// All strings are NUL-terminated UTF-8; the strings returned by Jsonatr should be freed with jsonatr_free.
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+fn set_last_error(error: Option<String>) {
+    let error = error.map(|e| CString::new(e.replace('\0', " ")).unwrap());
+    LAST_ERROR.with(|last| *last.borrow_mut() = error);
+}
+
+unsafe fn read_str<'a>(s: *const c_char, name: &str) -> Result<&'a str, JsonatrError> {
+    if s.is_null() {
+        return Err(JsonatrError::Other(format!("{} is NULL", name)));
+    }
+    CStr::from_ptr(s)
+        .to_str()
+        .map_err(|e| JsonatrError::Other(format!("{} is not valid UTF-8: {}", name, e)))
+}
+
+unsafe fn transform(spec: *const c_char, input: *const c_char) -> Result<String, JsonatrError> {
+    let spec = Transformer::new(read_str(spec, "spec")?, ".")?;
+    let input = if input.is_null() {
+        Value::Null
+    } else {
+        serde_json::from_str(read_str(input, "input")?)
+            .map_err(|e| JsonatrError::parse("JSON", e))?
+    };
+    spec.transform(&input)
+}
+
+/// Transforms the input JSON (or NULL for no input) by the spec JSON; used specs are resolved
+/// relative to the current directory. Returns the output, or NULL on failure, with the reason
+/// available from `jsonatr_last_error`.
+///
+/// # Safety
+/// `spec` should be a valid NUL-terminated string, and `input` either such a string or NULL.
+#[no_mangle]
+pub unsafe extern "C" fn jsonatr_transform(
+    spec: *const c_char,
+    input: *const c_char,
+) -> *mut c_char {
+    match transform(spec, input) {
+        Ok(output) => {
+            set_last_error(None);
+            match CString::new(output) {
+                Ok(output) => output.into_raw(),
+                Err(_) => {
+                    set_last_error(Some("output contains a NUL character".to_string()));
+                    ptr::null_mut()
+                }
+            }
+        }
+        Err(e) => {
+            set_last_error(Some(e.to_string()));
+            ptr::null_mut()
+        }
+    }
+}
+
+/// Returns the error of the last failed call on the current thread, or NULL if it succeeded.
+/// The string is owned by Jsonatr, and stays valid until the next call on the same thread.
+#[no_mangle]
+pub extern "C" fn jsonatr_last_error() -> *const c_char {
+    LAST_ERROR.with(|last| match &*last.borrow() {
+        Some(error) => error.as_ptr(),
+        None => ptr::null(),
+    })
+}
+
+/// Frees a string returned by Jsonatr.
+///
+/// # Safety
+/// `s` should be NULL, or a string returned by `jsonatr_transform` and not freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn jsonatr_free(s: *mut c_char) {
+    if !s.is_null() {
+        drop(CString::from_raw(s));
+    }
+}
@@ -1,29 +1,454 @@
+use crate::error::JsonatrError;
 use serde_json::Value;
 use simple_error::*;
+#[cfg(feature = "cbor")]
+use std::convert::TryFrom;
 use std::io::{self, Read};
 
-pub fn read_file(path: &str) -> Result<String, SimpleError> {
-    let file = try_with!(std::fs::read_to_string(path), "failed to read file");
-    Ok(file)
+pub fn read_file(path: &str) -> Result<String, JsonatrError> {
+    std::fs::read_to_string(path).map_err(|e| JsonatrError::io(path, e))
 }
 
-pub fn parse_string(string: &str) -> Result<Value, SimpleError> {
-    let value: Value = try_with!(serde_json::from_str(&string), "failed to parse JSON");
+pub fn parse_string(string: &str) -> Result<Value, JsonatrError> {
+    serde_json::from_str(string).map_err(|e| JsonatrError::parse("JSON", e))
+}
+
+// the compact JSON of the value for logging, shortened to a single line of reasonable length
+pub fn summarize(value: &Value) -> String {
+    const MAX_LEN: usize = 100;
+    let text = value.to_string();
+    match text.char_indices().nth(MAX_LEN) {
+        Some((end, _)) => format!("{}... ({} bytes)", &text[..end], text.len()),
+        None => text,
+    }
+}
+
+// converts a TOML value into JSON; datetimes are represented as strings
+#[cfg(feature = "toml")]
+pub(crate) fn toml_to_json(value: toml::Value) -> Value {
+    match value {
+        toml::Value::String(s) => Value::String(s),
+        toml::Value::Integer(i) => Value::from(i),
+        toml::Value::Float(f) => Value::from(f),
+        toml::Value::Boolean(b) => Value::Bool(b),
+        toml::Value::Datetime(d) => Value::String(d.to_string()),
+        toml::Value::Array(arr) => Value::Array(arr.into_iter().map(toml_to_json).collect()),
+        toml::Value::Table(table) => Value::Object(
+            table
+                .into_iter()
+                .map(|(k, v)| (k, toml_to_json(v)))
+                .collect(),
+        ),
+    }
+}
+
+#[cfg(feature = "toml")]
+pub fn parse_toml(string: &str) -> Result<Value, JsonatrError> {
+    let value: toml::Value = string.parse().map_err(|e| JsonatrError::parse("TOML", e))?;
+    Ok(toml_to_json(value))
+}
+
+#[cfg(not(feature = "toml"))]
+pub fn parse_toml(_: &str) -> Result<Value, JsonatrError> {
+    bail!("TOML inputs are not supported: jsonatr is built without the 'toml' feature")
+}
+
+// converts a CSV field into a value of the given type: "string", "number", "boolean", or "json"
+#[cfg(feature = "csv")]
+fn csv_field_to_json(field: &str, typ: &str) -> Result<Value, SimpleError> {
+    let value = match typ {
+        "string" => Value::String(field.to_string()),
+        "number" | "json" => match serde_json::from_str(field.trim()) {
+            Ok(Value::Number(n)) => Value::Number(n),
+            Ok(value) if typ == "json" => value,
+            _ if field.trim().is_empty() => Value::Null,
+            _ => bail!("failed to parse CSV field '{}' as {}", field, typ),
+        },
+        "boolean" => match field.trim() {
+            "true" => Value::Bool(true),
+            "false" => Value::Bool(false),
+            "" => Value::Null,
+            _ => bail!("failed to parse CSV field '{}' as boolean", field),
+        },
+        _ => bail!("unknown CSV column type '{}'", typ),
+    };
+    Ok(value)
+}
+
+// parses CSV with a header row into an array of objects, keyed by column names;
+// all fields are strings, unless the column type is given in `types`
+#[cfg(feature = "csv")]
+pub fn parse_csv(
+    string: &str,
+    delimiter: u8,
+    types: &serde_json::Map<String, Value>,
+) -> Result<Value, JsonatrError> {
+    let mut reader = csv::ReaderBuilder::new()
+        .delimiter(delimiter)
+        .from_reader(string.as_bytes());
+    let headers = try_with!(reader.headers(), "failed to parse CSV").clone();
+    let mut rows = Vec::new();
+    for record in reader.records() {
+        let record = try_with!(record, "failed to parse CSV");
+        let mut row = serde_json::Map::new();
+        for (name, field) in headers.iter().zip(record.iter()) {
+            let typ = types.get(name).and_then(|t| t.as_str()).unwrap_or("string");
+            row.insert(name.to_string(), csv_field_to_json(field, typ)?);
+        }
+        rows.push(Value::Object(row));
+    }
+    Ok(Value::Array(rows))
+}
+
+#[cfg(not(feature = "csv"))]
+pub fn parse_csv(
+    _: &str,
+    _: u8,
+    _: &serde_json::Map<String, Value>,
+) -> Result<Value, JsonatrError> {
+    bail!("CSV inputs are not supported: jsonatr is built without the 'csv' feature")
+}
+
+// represents binary data as an array of byte values
+#[cfg(any(feature = "cbor", feature = "msgpack"))]
+fn bytes_to_json(bytes: Vec<u8>) -> Value {
+    Value::Array(bytes.into_iter().map(Value::from).collect())
+}
+
+// converts a map key of a binary format into a JSON object key; non-string keys are encoded as JSON
+#[cfg(any(feature = "cbor", feature = "msgpack"))]
+fn key_to_json(key: Value) -> String {
+    match key {
+        Value::String(s) => s,
+        _ => key.to_string(),
+    }
+}
+
+// converts a CBOR value into JSON; byte strings are represented as arrays of bytes, and tags are dropped
+#[cfg(feature = "cbor")]
+fn cbor_to_json(value: ciborium::value::Value) -> Result<Value, SimpleError> {
+    use ciborium::value::Value as Cbor;
+    let value = match value {
+        Cbor::Null => Value::Null,
+        Cbor::Bool(b) => Value::Bool(b),
+        Cbor::Integer(i) => match i64::try_from(i) {
+            Ok(i) => Value::from(i),
+            Err(_) => match u64::try_from(i) {
+                Ok(u) => Value::from(u),
+                Err(_) => bail!("CBOR integer is out of range"),
+            },
+        },
+        Cbor::Float(f) => Value::from(f),
+        Cbor::Text(s) => Value::String(s),
+        Cbor::Bytes(b) => bytes_to_json(b),
+        Cbor::Tag(_, value) => cbor_to_json(*value)?,
+        Cbor::Array(arr) => Value::Array(
+            arr.into_iter()
+                .map(cbor_to_json)
+                .collect::<Result<_, _>>()?,
+        ),
+        Cbor::Map(map) => {
+            let mut obj = serde_json::Map::new();
+            for (k, v) in map {
+                obj.insert(key_to_json(cbor_to_json(k)?), cbor_to_json(v)?);
+            }
+            Value::Object(obj)
+        }
+        _ => bail!("unsupported CBOR value"),
+    };
+    Ok(value)
+}
+
+#[cfg(feature = "cbor")]
+pub fn parse_cbor(bytes: &[u8]) -> Result<Value, JsonatrError> {
+    let value = ciborium::de::from_reader(bytes).map_err(|e| JsonatrError::parse("CBOR", e))?;
+    Ok(cbor_to_json(value)?)
+}
+
+#[cfg(not(feature = "cbor"))]
+pub fn parse_cbor(_: &[u8]) -> Result<Value, JsonatrError> {
+    bail!("CBOR inputs are not supported: jsonatr is built without the 'cbor' feature")
+}
+
+// converts a MessagePack value into JSON; binary data is represented as arrays of bytes,
+// and extension types as objects with the type and data fields
+#[cfg(feature = "msgpack")]
+fn msgpack_to_json(value: rmpv::Value) -> Result<Value, SimpleError> {
+    use rmpv::Value as MsgPack;
+    let value = match value {
+        MsgPack::Nil => Value::Null,
+        MsgPack::Boolean(b) => Value::Bool(b),
+        MsgPack::Integer(i) => match (i.as_i64(), i.as_u64()) {
+            (Some(i), _) => Value::from(i),
+            (_, Some(u)) => Value::from(u),
+            _ => bail!("MessagePack integer is out of range"),
+        },
+        MsgPack::F32(f) => Value::from(f64::from(f)),
+        MsgPack::F64(f) => Value::from(f),
+        MsgPack::String(s) => match s.into_str() {
+            Some(s) => Value::String(s),
+            None => bail!("MessagePack string is not valid UTF-8"),
+        },
+        MsgPack::Binary(b) => bytes_to_json(b),
+        MsgPack::Array(arr) => Value::Array(
+            arr.into_iter()
+                .map(msgpack_to_json)
+                .collect::<Result<_, _>>()?,
+        ),
+        MsgPack::Map(map) => {
+            let mut obj = serde_json::Map::new();
+            for (k, v) in map {
+                obj.insert(key_to_json(msgpack_to_json(k)?), msgpack_to_json(v)?);
+            }
+            Value::Object(obj)
+        }
+        MsgPack::Ext(typ, data) => {
+            let mut obj = serde_json::Map::new();
+            obj.insert("type".to_string(), Value::from(typ));
+            obj.insert("data".to_string(), bytes_to_json(data));
+            Value::Object(obj)
+        }
+    };
     Ok(value)
 }
 
-pub fn parse_file(path: &str) -> Result<Value, SimpleError> {
+#[cfg(feature = "msgpack")]
+pub fn parse_msgpack(mut bytes: &[u8]) -> Result<Value, JsonatrError> {
+    let value =
+        rmpv::decode::read_value(&mut bytes).map_err(|e| JsonatrError::parse("MessagePack", e))?;
+    Ok(msgpack_to_json(value)?)
+}
+
+#[cfg(not(feature = "msgpack"))]
+pub fn parse_msgpack(_: &[u8]) -> Result<Value, JsonatrError> {
+    bail!("MessagePack inputs are not supported: jsonatr is built without the 'msgpack' feature")
+}
+
+// parses a duration given either as a number of seconds, or as a string with a unit: ms, s, m, or h
+pub fn parse_duration(value: &Value) -> Result<std::time::Duration, JsonatrError> {
+    let seconds = match value {
+        Value::Number(n) => n.as_f64(),
+        Value::String(s) => {
+            let s = s.trim();
+            let (number, scale) = if let Some(n) = s.strip_suffix("ms") {
+                (n, 0.001)
+            } else if let Some(n) = s.strip_suffix('s') {
+                (n, 1.0)
+            } else if let Some(n) = s.strip_suffix('m') {
+                (n, 60.0)
+            } else if let Some(n) = s.strip_suffix('h') {
+                (n, 3600.0)
+            } else {
+                (s, 1.0)
+            };
+            number.trim().parse::<f64>().ok().map(|n| n * scale)
+        }
+        _ => None,
+    };
+    match seconds.and_then(|seconds| std::time::Duration::try_from_secs_f64(seconds).ok()) {
+        Some(duration) => Ok(duration),
+        None => bail!("failed to parse duration '{}'", value),
+    }
+}
+
+// finds the position of the brace closing the one at the start of the text,
+// skipping nested braces and quoted strings
+pub fn find_closing_brace(text: &str) -> Option<usize> {
+    let mut depth = 0;
+    let mut quote: Option<char> = None;
+    let mut escaped = false;
+    for (i, c) in text.char_indices() {
+        if let Some(q) = quote {
+            if escaped {
+                escaped = false;
+            } else if c == '\\' && q == '"' {
+                escaped = true;
+            } else if c == q {
+                quote = None;
+            }
+            continue;
+        }
+        match c {
+            '\'' | '"' => quote = Some(c),
+            '{' => depth += 1,
+            '}' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(i);
+                }
+            }
+            _ => (),
+        }
+    }
+    None
+}
+
+// replaces each ${VAR} in the path with the value of the environment variable VAR;
+// fails if the variable is not set, rather than producing an unexpected path
+pub fn expand_env(path: &str) -> Result<String, JsonatrError> {
+    let mut result = String::new();
+    let mut rest = path;
+    while let Some(start) = rest.find("${") {
+        result.push_str(&rest[..start]);
+        let end = require_with!(
+            rest[start..].find('}'),
+            "unclosed environment variable in '{}'",
+            path
+        ) + start;
+        let name = &rest[start + 2..end];
+        match std::env::var(name) {
+            Ok(value) => result.push_str(&value),
+            Err(_) => bail!(
+                "environment variable '{}' used in '{}' is not set",
+                name,
+                path
+            ),
+        }
+        rest = &rest[end + 1..];
+    }
+    result.push_str(rest);
+    Ok(result)
+}
+
+// reads a single entry from the contents of a .zip, .tar, or .tar.gz (.tgz) archive;
+// the format is determined by the extension of the archive name
+#[cfg(feature = "archives")]
+pub fn read_archive_entry(
+    archive: &str,
+    bytes: Vec<u8>,
+    path: &str,
+) -> Result<String, JsonatrError> {
+    let file = io::Cursor::new(bytes);
+    let mut content = String::new();
+    if archive.ends_with(".zip") {
+        let mut zip = try_with!(zip::ZipArchive::new(file), "failed to read zip archive");
+        let mut entry = try_with!(zip.by_name(path), "failed to find '{}' in archive", path);
+        try_with!(entry.read_to_string(&mut content), "failed to read archive");
+        return Ok(content);
+    }
+    let reader: Box<dyn Read> = if archive.ends_with(".tar.gz") || archive.ends_with(".tgz") {
+        Box::new(flate2::read::GzDecoder::new(file))
+    } else if archive.ends_with(".tar") {
+        Box::new(file)
+    } else {
+        bail!("unknown archive format of '{}'", archive)
+    };
+    let mut tar = tar::Archive::new(reader);
+    for entry in try_with!(tar.entries(), "failed to read tar archive") {
+        let mut entry = try_with!(entry, "failed to read tar archive");
+        let entry_path = try_with!(entry.path(), "failed to read tar archive");
+        if entry_path == std::path::Path::new(path) {
+            try_with!(entry.read_to_string(&mut content), "failed to read archive");
+            return Ok(content);
+        }
+    }
+    bail!("failed to find '{}' in archive", path)
+}
+
+#[cfg(not(feature = "archives"))]
+pub fn read_archive_entry(_: &str, _: Vec<u8>, _: &str) -> Result<String, JsonatrError> {
+    bail!("ARCHIVE inputs are not supported: jsonatr is built without the 'archives' feature")
+}
+
+pub fn parse_file(path: &str) -> Result<Value, JsonatrError> {
     let file = read_file(path)?;
-    let value = parse_string(&file)?;
+    let value = parse_string(&file).map_err(|e| e.with_origin(path))?;
     Ok(value)
 }
 
-pub fn parse_stdin() -> Result<Value, SimpleError> {
+pub fn read_stdin() -> Result<String, JsonatrError> {
     let mut buffer = String::new();
     try_with!(
         io::stdin().read_to_string(&mut buffer),
         "failed to read from STDIN"
     );
+    Ok(buffer)
+}
+
+pub fn parse_stdin() -> Result<Value, JsonatrError> {
+    let buffer = read_stdin()?;
     let value = parse_string(&buffer)?;
     Ok(value)
 }
+
+// parses a sequence of JSON documents, separated either by the given separator,
+// or otherwise by whitespace and/or JSON-seq (RFC 7464) record separators
+pub fn parse_documents(string: &str, separator: Option<&str>) -> Result<Vec<Value>, JsonatrError> {
+    match separator {
+        Some(separator) => string
+            .split(separator)
+            .filter(|doc| !doc.trim().is_empty())
+            .map(parse_string)
+            .collect(),
+        None => {
+            let text = string.replace('\u{1e}', " ");
+            let mut documents = Vec::new();
+            for document in serde_json::Deserializer::from_str(&text).into_iter::<Value>() {
+                documents.push(try_with!(document, "failed to parse JSON"));
+            }
+            Ok(documents)
+        }
+    }
+}
+
+// returns a copy of the value with the keys of all (nested) objects sorted alphabetically
+pub fn sort_keys(v: &Value) -> Value {
+    match v {
+        Value::Array(arr) => Value::Array(arr.iter().map(sort_keys).collect()),
+        Value::Object(obj) => {
+            let mut entries: Vec<(&String, &Value)> = obj.iter().collect();
+            entries.sort_by(|a, b| a.0.cmp(b.0));
+            Value::Object(
+                entries
+                    .into_iter()
+                    .map(|(k, v)| (k.clone(), sort_keys(v)))
+                    .collect(),
+            )
+        }
+        _ => v.clone(),
+    }
+}
+
+// compares the values structurally; returns the differences, each located by its JSON pointer
+pub fn json_diff(expected: &Value, actual: &Value) -> Vec<String> {
+    let mut diff = Vec::new();
+    collect_diff("", expected, actual, &mut diff);
+    diff
+}
+
+fn collect_diff(pointer: &str, expected: &Value, actual: &Value, diff: &mut Vec<String>) {
+    let location = if pointer.is_empty() {
+        "(root)"
+    } else {
+        pointer
+    };
+    let child = |key: &str| format!("{}/{}", pointer, key.replace('~', "~0").replace('/', "~1"));
+    match (expected, actual) {
+        (Value::Object(e), Value::Object(a)) => {
+            for (key, value) in e {
+                match a.get(key) {
+                    Some(other) => collect_diff(&child(key), value, other, diff),
+                    None => diff.push(format!("{}: missing {}", child(key), value)),
+                }
+            }
+            for (key, value) in a {
+                if !e.contains_key(key) {
+                    diff.push(format!("{}: unexpected {}", child(key), value));
+                }
+            }
+        }
+        (Value::Array(e), Value::Array(a)) => {
+            for (i, value) in e.iter().enumerate() {
+                match a.get(i) {
+                    Some(other) => collect_diff(&child(&i.to_string()), value, other, diff),
+                    None => diff.push(format!("{}: missing {}", child(&i.to_string()), value)),
+                }
+            }
+            for (i, value) in a.iter().enumerate().skip(e.len()) {
+                diff.push(format!("{}: unexpected {}", child(&i.to_string()), value));
+            }
+        }
+        (e, a) if e != a => diff.push(format!("{}: expected {}, found {}", location, e, a)),
+        _ => (),
+    }
+}
@@ -1,7 +1,19 @@
+pub mod backend;
+pub mod builder;
+pub mod error;
+#[cfg(feature = "ffi")]
+pub mod ffi;
 pub mod helpers;
+pub mod observer;
+pub mod output;
+pub mod path;
+#[cfg(feature = "python")]
+pub mod python;
+pub mod resolver;
 pub mod transformer;
+#[cfg(feature = "wasm")]
+pub mod wasm;
 
-#[macro_use]
-extern crate lazy_static;
+#[cfg(feature = "jsonpath")]
 extern crate jsonpath_lib as jsonpath;
 extern crate simple_error;
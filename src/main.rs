@@ -1,9 +1,16 @@
+use jsonatr::error::*;
 use jsonatr::helpers::*;
+use jsonatr::observer::EvaluationObserver;
+use jsonatr::output::{check_file_name_part, OutputFormat, RenderedOutput};
+use jsonatr::resolver::ConfinedResolver;
 use jsonatr::transformer::*;
 
 use gumdrop::Options;
 use serde_json::Value;
 use simple_error::*;
+use std::io::Write;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 #[derive(Debug, Options)]
 struct CliOptions {
@@ -18,30 +25,420 @@ struct CliOptions {
         meta = "FILE"
     )]
     include: Vec<String>,
+    #[options(
+        no_short,
+        help = "read the complete spec from FILE, or from STDIN if FILE is -",
+        meta = "FILE"
+    )]
+    spec: Option<String>,
     #[options(no_short, help = "read main input from STDIN")]
     stdin: bool,
     #[options(
         no_short,
         long = "in",
-        help = "read main input from FILE",
-        meta = "FILE"
+        help = "read main input from FILE, or input NAME from FILE given as NAME=FILE (repeatable)",
+        meta = "[NAME=]FILE"
+    )]
+    input: Vec<String>,
+    #[options(
+        no_short,
+        help = "search DIR for used specs and input files not found at their relative paths (repeatable); \
+                the directories in JSONATR_PATH are searched after these",
+        meta = "DIR"
+    )]
+    include_path: Vec<String>,
+    #[options(
+        no_short,
+        help = "transform each file matching the glob PATTERN as the main input",
+        meta = "PATTERN"
+    )]
+    in_glob: Option<String>,
+    #[options(
+        no_short,
+        help = "with --in-glob, write the output for each file into TEMPLATE, where {stem}, {name}, and {dir} \
+                stand for the file stem, name, and directory of the input",
+        meta = "TEMPLATE"
+    )]
+    out_template: Option<String>,
+    #[options(
+        no_short,
+        help = "with --in-glob, transform up to N files at a time in parallel threads",
+        meta = "N"
+    )]
+    jobs: Option<usize>,
+    #[options(
+        no_short,
+        help = "read newline-delimited JSON from STDIN, and print the output for each document on its own line"
     )]
-    input: Option<String>,
+    stream: bool,
     #[options(
         no_short,
         long = "out",
-        help = "write generated output into FILE instead of STDOUT",
+        help = "write generated output into FILE instead of STDOUT; each {$EXPR} in FILE is replaced \
+                with the value of the expression, e.g. {$.height}, against the output, and each ${VAR} with \
+                the environment variable",
         meta = "FILE"
     )]
     output: Option<String>,
-    #[options(free, help = "provide output spec inline")]
+    #[options(
+        no_short,
+        help = "define INLINE input NAME with the JSON value (repeatable)",
+        meta = "NAME=JSON"
+    )]
+    set: Vec<String>,
+    #[options(
+        no_short,
+        help = "define INLINE input NAME with the string VALUE, as in jq (repeatable)",
+        meta = "NAME VALUE"
+    )]
+    arg: Vec<(String, String)>,
+    #[options(
+        no_short,
+        help = "define INLINE input NAME with the JSON VALUE, as in jq (repeatable)",
+        meta = "NAME JSON"
+    )]
+    argjson: Vec<(String, String)>,
+    #[options(
+        no_short,
+        help = "serialize the output as json, ndjson, yaml, toml, cbor, or msgpack",
+        meta = "FORMAT"
+    )]
+    format: Option<String>,
+    #[options(no_short, help = "produce compact JSON on a single line")]
+    compact: bool,
+    #[options(
+        no_short,
+        help = "indent pretty-printed JSON by N spaces per level",
+        meta = "N"
+    )]
+    indent: Option<usize>,
+    #[options(no_short, help = "sort the keys of all objects alphabetically")]
+    sort_keys: bool,
+    #[options(
+        short = "r",
+        help = "output a string result as is, without quotes and JSON escaping"
+    )]
+    raw_output: bool,
+    #[options(
+        no_short,
+        help = "fail on any expression that can't be evaluated, instead of leaving it unchanged"
+    )]
+    strict: bool,
+    #[options(
+        no_short,
+        help = "abort any COMMAND input running longer than SECS seconds",
+        meta = "SECS"
+    )]
+    timeout: Option<f64>,
+    #[options(
+        no_short,
+        help = "seed the pseudo-random builtins, e.g. uuid(), with N to make the output reproducible",
+        meta = "N"
+    )]
+    seed: Option<u64>,
+    #[options(
+        no_short,
+        help = "refuse to run commands: COMMAND and GIT inputs, and output pipes"
+    )]
+    no_commands: bool,
+    #[options(
+        no_short,
+        help = "refuse to read files outside of the spec directory, or the current one without --spec FILE"
+    )]
+    no_files: bool,
+    #[options(
+        no_short,
+        help = "color the error reports: auto (if STDERR is a terminal and NO_COLOR is not set), always, or never",
+        meta = "WHEN"
+    )]
+    color: Option<String>,
+    #[options(help = "log each input evaluation, command, and HTTP request to STDERR")]
+    verbose: bool,
+    #[options(
+        no_short,
+        help = "additionally log each evaluated expression with its result"
+    )]
+    trace: bool,
+    #[options(
+        no_short,
+        help = "report the time spent in each input and each top-level expression to STDERR after the run"
+    )]
+    profile: bool,
+    #[options(
+        no_short,
+        help = "print to STDERR how each output node is evaluated: its expression, the value it resolves to, \
+                and the intermediate value after each step of the pipeline"
+    )]
+    explain: bool,
+    #[options(
+        no_short,
+        help = "validate the spec, reporting unknown inputs and malformed expressions, without running it"
+    )]
+    check: bool,
+    #[options(no_short, help = "same as --check")]
+    validate: bool,
+    #[options(
+        no_short,
+        help = "print the value of the expression EXPR against the main input, instead of an output spec",
+        meta = "EXPR"
+    )]
+    query: Option<String>,
+    #[options(
+        no_short,
+        help = "compare the output with the JSON in FILE, printing the differences, instead of printing the output",
+        meta = "FILE"
+    )]
+    expect: Option<String>,
+    #[options(
+        no_short,
+        help = "read expressions from STDIN line by line, and print their values against the main input"
+    )]
+    repl: bool,
+    #[options(
+        free,
+        help = "provide output spec inline; the expression for the query command, or the spec file for fmt"
+    )]
     output_spec: Option<String>,
 }
 
-fn run() -> Result<(), SimpleError> {
-    let opts = CliOptions::parse_args_default_or_exit();
-    if opts.stdin && opts.input.is_some() {
-        bail!("both --stdin and --input are given, but only one main input can be accepted")
+// the subcommands of the CLI, given as the first argument; without one, the arguments are those of transform
+const COMMANDS: &[(&str, &str, &str)] = &[
+    (
+        "transform",
+        "[OUTPUT_SPEC]",
+        "transform the inputs according to the spec (the default)",
+    ),
+    (
+        "validate",
+        "[OUTPUT_SPEC]",
+        "validate the spec without running it, same as --check",
+    ),
+    (
+        "query",
+        "EXPR",
+        "print the value of the expression against the main input, same as --query",
+    ),
+    (
+        "fmt",
+        "[FILE]",
+        "pretty-print the JSON of the spec in FILE, or from STDIN if FILE is - or not given",
+    ),
+];
+
+// parses the options following the command, printing the usage and exiting on --help or wrong options
+fn parse_options(command: Option<&str>, args: &[String]) -> CliOptions {
+    let opts = CliOptions::parse_args_default(args).unwrap_or_else(|e| {
+        eprintln!("jsonatr: {}", e);
+        std::process::exit(2);
+    });
+    if opts.help_requested() {
+        match command.and_then(|name| COMMANDS.iter().find(|(c, _, _)| *c == name)) {
+            Some((name, free, help)) => {
+                eprintln!("Usage: jsonatr {} [OPTIONS] {}", name, free);
+                eprintln!();
+                eprintln!("{}", help);
+            }
+            None => {
+                eprintln!("Usage: jsonatr [COMMAND] [OPTIONS] [OUTPUT_SPEC]");
+                eprintln!();
+                eprintln!("Available commands:");
+                for (name, _, help) in COMMANDS {
+                    eprintln!("  {:<12}{}", name, help);
+                }
+            }
+        }
+        eprintln!();
+        eprintln!("{}", CliOptions::usage());
+        std::process::exit(0);
+    }
+    opts
+}
+
+// splits the NAME=VALUE argument of the option
+fn parse_assignment<'a>(option: &str, arg: &'a str) -> Result<(&'a str, &'a str), JsonatrError> {
+    match arg.find('=') {
+        Some(pos) if pos > 0 => Ok((&arg[..pos], &arg[pos + 1..])),
+        _ => bail!(
+            "argument '{}' of {} should be of the form NAME=VALUE",
+            arg,
+            option
+        ),
+    }
+}
+
+// an option of the CLI, as listed in the usage text
+struct CliOption {
+    short: Option<String>,
+    long: String,
+    takes_value: bool,
+    help: String,
+}
+
+// extracts the options from the usage text, so that the completions can't get out of sync with them
+fn cli_options() -> Vec<CliOption> {
+    let mut options = Vec::new();
+    for line in CliOptions::usage().lines() {
+        let line = line.trim();
+        let (spec, help) = match line.find("  ") {
+            Some(pos) => (&line[..pos], line[pos..].trim()),
+            None => (line, ""),
+        };
+        let mut option = CliOption {
+            short: None,
+            long: String::new(),
+            takes_value: false,
+            help: help.to_string(),
+        };
+        for token in spec.split_whitespace() {
+            let token = token.trim_end_matches(',');
+            if let Some(long) = token.strip_prefix("--") {
+                option.long = long.to_string();
+            } else if let Some(short) = token.strip_prefix('-') {
+                option.short = Some(short.to_string());
+            } else {
+                option.takes_value = true;
+            }
+        }
+        if !option.long.is_empty() {
+            options.push(option);
+        }
+    }
+    options
+}
+
+// the completion script of the shell: bash, zsh, or fish
+fn completions(shell: &str) -> Result<String, JsonatrError> {
+    let options = cli_options();
+    let script = match shell {
+        "bash" => {
+            let words: Vec<String> = COMMANDS
+                .iter()
+                .map(|(name, _, _)| name.to_string())
+                .chain(options.iter().flat_map(|o| {
+                    o.short
+                        .iter()
+                        .map(|s| format!("-{}", s))
+                        .chain(Some(format!("--{}", o.long)))
+                }))
+                .collect();
+            format!(
+                "_jsonatr() {{\n    local cur=\"${{COMP_WORDS[COMP_CWORD]}}\"\n    \
+                 COMPREPLY=($(compgen -W \"{}\" -- \"$cur\"))\n}}\n\
+                 complete -o default -F _jsonatr jsonatr\n",
+                words.join(" ")
+            )
+        }
+        "zsh" => {
+            let mut script = String::from("#compdef jsonatr\n\n_arguments \\\n");
+            for o in &options {
+                let help = o
+                    .help
+                    .replace('\'', "'\\''")
+                    .replace('[', "\\[")
+                    .replace(']', "\\]");
+                let value = if o.takes_value { ":value:_files" } else { "" };
+                script.push_str(&format!("  '--{}[{}]{}' \\\n", o.long, help, value));
+                if let Some(short) = &o.short {
+                    script.push_str(&format!("  '-{}[{}]{}' \\\n", short, help, value));
+                }
+            }
+            script.push_str("  '*:output spec:'\n");
+            script
+        }
+        "fish" => {
+            let mut script = String::new();
+            for (name, _, help) in COMMANDS {
+                script.push_str(&format!(
+                    "complete -c jsonatr -n __fish_use_subcommand -f -a {} -d '{}'\n",
+                    name,
+                    help.replace('\'', "\\'")
+                ));
+            }
+            for o in &options {
+                script.push_str(&format!("complete -c jsonatr -l {}", o.long));
+                if let Some(short) = &o.short {
+                    script.push_str(&format!(" -s {}", short));
+                }
+                if o.takes_value {
+                    script.push_str(" -r");
+                }
+                script.push_str(&format!(" -d '{}'\n", o.help.replace('\'', "\\'")));
+            }
+            script
+        }
+        _ => bail!("unknown shell '{}': should be bash, zsh, or fish", shell),
+    };
+    Ok(script)
+}
+
+fn run() -> Result<(), JsonatrError> {
+    // the hidden mode printing the completion script: jsonatr completions <shell>
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.len() == 2 && args[0] == "completions" {
+        print!("{}", completions(&args[1])?);
+        return Ok(());
+    }
+    let command = args
+        .first()
+        .filter(|arg| COMMANDS.iter().any(|(name, _, _)| name == arg))
+        .map(String::as_str);
+    let mut opts = match command {
+        Some(_) => parse_options(command, &args[1..]),
+        None => parse_options(None, &args),
+    };
+    match command {
+        Some("validate") => opts.check = true,
+        Some("query") => {
+            if opts.query.is_some() {
+                bail!("the query command takes the expression as its argument, so --query can't be given")
+            }
+            opts.query = Some(require_with!(
+                opts.output_spec.take(),
+                "the query command expects an expression"
+            ));
+        }
+        Some("fmt") => return fmt(&opts),
+        _ => (),
+    }
+    let color = match opts.color.as_deref() {
+        None | Some("auto") => {
+            use std::io::IsTerminal;
+            std::io::stderr().is_terminal() && std::env::var_os("NO_COLOR").is_none()
+        }
+        Some("always") => true,
+        Some("never") => false,
+        Some(other) => bail!(
+            "unknown --color '{}': should be auto, always, or never",
+            other
+        ),
+    };
+    COLOR.store(color, std::sync::atomic::Ordering::Relaxed);
+    if opts.trace {
+        log::set_max_level(log::LevelFilter::Trace);
+    } else if opts.verbose {
+        log::set_max_level(log::LevelFilter::Debug);
+    }
+    // the named inputs are given as NAME=FILE; anything else is the path of the main input
+    let (named, main_files): (Vec<&String>, Vec<&String>) =
+        opts.input.iter().partition(|arg| match arg.find('=') {
+            Some(pos) => pos > 0 && arg[..pos].chars().all(|c| c.is_alphanumeric() || c == '_'),
+            None => false,
+        });
+    if main_files.len() + opts.stdin as usize > 1 {
+        bail!("several main inputs are given with --stdin and --in, but only one can be accepted")
+    }
+    if opts.repl && (opts.stdin || opts.spec.as_deref() == Some("-")) {
+        bail!("--repl reads expressions from STDIN, so neither the spec nor the main input can be read from it")
+    }
+    if opts.stdin && opts.spec.as_deref() == Some("-") {
+        bail!("both the spec and the main input are to be read from STDIN, but only one can be")
+    }
+    if opts.stream && (opts.stdin || !main_files.is_empty() || opts.in_glob.is_some() || opts.repl)
+    {
+        bail!("--stream reads the main inputs from STDIN, so neither --stdin, --in, --in-glob, nor --repl can be given")
+    }
+    if opts.stream && (opts.spec.as_deref() == Some("-") || opts.output.is_some()) {
+        bail!("--stream reads from STDIN and writes to STDOUT, so neither --spec - nor --out can be given")
     }
 
     let current_dir = std::env::current_dir()
@@ -50,40 +447,916 @@ fn run() -> Result<(), SimpleError> {
         .unwrap()
         .to_owned();
     let mut spec = Transformer::empty(&current_dir);
+    // the restrictions are set up first, so that they also apply when loading the specs
+    if opts.no_commands {
+        // the environment is the user's own here, unlike in the sandbox of an untrusted spec
+        spec.set_sandbox(Sandbox {
+            deny_env: false,
+            ..Default::default()
+        })?;
+    }
+    if opts.no_files {
+        let root = match opts.spec.as_deref() {
+            Some(path) if path != "-" => match std::path::Path::new(path).parent() {
+                Some(dir) if !dir.as_os_str().is_empty() => dir.to_str().unwrap().to_owned(),
+                _ => current_dir.clone(),
+            },
+            _ => current_dir.clone(),
+        };
+        spec.set_resolver(Arc::new(ConfinedResolver::new(&root)?));
+    }
+    for dir in &opts.include_path {
+        spec.add_include_path(dir);
+    }
+    if let Some(paths) = std::env::var_os("JSONATR_PATH") {
+        for dir in std::env::split_paths(&paths) {
+            spec.add_include_path(&dir.to_string_lossy());
+        }
+    }
+    match opts.spec.as_deref() {
+        Some("-") => {
+            let text = read_stdin()?;
+            add_source("STDIN", "STDIN", &text);
+            spec.merge_str(&text).map_err(|e| e.with_origin("STDIN"))?
+        }
+        Some(path) => {
+            add_source_file(&current_dir, path);
+            spec.add_use(path.to_string())?
+        }
+        None => (),
+    }
     for path in &opts.include {
+        add_source_file(&current_dir, path);
         spec.add_use(path.to_string())?;
     }
+    for arg in &opts.set {
+        let (name, json) = parse_assignment("--set", arg)?;
+        let value = try_with!(
+            parse_string(json),
+            "value of --set {} is not valid JSON",
+            name
+        );
+        spec.add_input(Input::literal(name, value))?;
+    }
+    for arg in named {
+        let (name, path) = parse_assignment("--in", arg)?;
+        spec.add_input(Input::literal(name, parse_file(path)?))?;
+    }
+    for (name, value) in &opts.arg {
+        spec.add_input(Input::literal(name, Value::String(value.clone())))?;
+    }
+    for (name, json) in &opts.argjson {
+        let value = try_with!(
+            parse_string(json),
+            "value of --argjson {} is not valid JSON",
+            name
+        );
+        spec.add_input(Input::literal(name, value))?;
+    }
 
+    if opts.query.is_some() && opts.output_spec.is_some() {
+        bail!("both --query and an output spec are given, but only one can be evaluated")
+    }
     if let Some(output_spec) = opts.output_spec {
-        let output = parse_string(&output_spec)?;
+        add_source("output spec", "output spec", &output_spec);
+        let output = parse_string(&output_spec).map_err(|e| e.with_origin("output spec"))?;
         spec.add_output(output)?
     }
 
+    // the formatting flags override the format of the spec
+    let mut format = spec.format().clone();
+    format.compact |= opts.compact;
+    format.sort_keys |= opts.sort_keys;
+    format.raw |= opts.raw_output;
+    if opts.indent.is_some() {
+        format.indent = opts.indent;
+    }
+    if let Some(name) = &opts.format {
+        format.encoding = try_with!(
+            serde_json::from_value(Value::String(name.to_lowercase())),
+            "unknown output format '{}'",
+            name
+        );
+    }
+    spec.set_format(format);
+    if opts.strict {
+        spec.set_strict(true);
+    }
+    if let Some(secs) = opts.timeout {
+        let timeout = match std::time::Duration::try_from_secs_f64(secs) {
+            Ok(timeout) if !timeout.is_zero() => timeout,
+            _ => bail!(
+                "--timeout expects a positive number of seconds, got {}",
+                secs
+            ),
+        };
+        spec.set_limits(Limits {
+            command_timeout: Some(timeout),
+            ..Default::default()
+        });
+    }
+    if opts.seed.is_some() {
+        spec.set_seed(opts.seed);
+    }
+    if opts.profile && opts.explain {
+        bail!("both --profile and --explain are given, but only one can observe the evaluation")
+    }
+    if opts.profile {
+        let profiler = Arc::new(Profiler::default());
+        *PROFILER.lock().unwrap() = Some(profiler.clone());
+        spec.set_observer(profiler);
+    }
+    if opts.explain {
+        spec.set_observer(Arc::new(Explainer::default()));
+    }
+
+    if opts.check || opts.validate {
+        let diagnostics = spec.validate();
+        for diagnostic in &diagnostics {
+            eprintln!(
+                "{}: {}",
+                paint(LOCATION, &diagnostic.location),
+                diagnostic.message
+            );
+        }
+        if !diagnostics.is_empty() {
+            let message = format!("found {} problem(s)", diagnostics.len());
+            return Err(JsonatrError::parse("spec", message));
+        }
+        return Ok(());
+    }
+
     // The 'main' input, i.e. the one that can be addressed in the output spec with unnamed $
     let main: Value;
     if opts.stdin {
         main = parse_stdin()?
-    } else if let Some(input) = opts.input {
-        main = parse_file(&input)?
+    } else if let Some(input) = main_files.first() {
+        main = parse_file(input)?
     } else {
         main = Value::Null;
     }
+    // the output paths expand the environment variables, like the paths in the spec
+    let out = opts.output.as_deref().map(expand_env).transpose()?;
+    let out_template = opts.out_template.as_deref().map(expand_env).transpose()?;
 
-    let res = spec.transform(&main)?;
-    if let Some(path) = opts.output {
-        try_with!(std::fs::write(path, res), "failed to write output")
-    } else {
-        println!("{}", res);
+    if opts.repl {
+        return repl(&spec, &main);
+    }
+    if let Some(query) = &opts.query {
+        let value = spec.eval(&spec.compile(query)?, &main)?;
+        let file = match &out {
+            Some(template) => Some(expand_out_path(&spec, template, &value)?),
+            None => None,
+        };
+        let output = RenderedOutput {
+            name: None,
+            file,
+            content: spec.format().encode(&value)?,
+            format: spec.format().clone(),
+            value,
+        };
+        return match &output.file {
+            Some(file) => write_output(&spec, &output, file),
+            None => write_stdout(&output),
+        };
+    }
+    if opts.stream {
+        return stream(&spec);
+    }
+    if let Some(pattern) = &opts.in_glob {
+        if opts.stdin || !main_files.is_empty() {
+            bail!("--in-glob provides the main inputs, so neither --stdin nor the main --in can be given")
+        }
+        return match opts.jobs {
+            Some(0) => bail!("--jobs expects a positive number of threads"),
+            Some(jobs) if jobs > 1 => batch_parallel(&spec, pattern, out_template.as_deref(), jobs),
+            _ => batch(&spec, pattern, out_template.as_deref()),
+        };
+    }
+
+    if let Some(path) = &opts.expect {
+        let expected = parse_file(path)?;
+        let diff = json_diff(&expected, &spec.transform_value_root(&main)?);
+        for line in &diff {
+            println!("{}", line);
+        }
+        if !diff.is_empty() {
+            bail!("output differs from '{}' in {} place(s)", path, diff.len())
+        }
+        return Ok(());
+    }
+
+    for output in spec.transform_all(&main)? {
+        if let Some(path) = &output.file {
+            write_output(&spec, &output, path)?
+        } else if let Some(template) = &out {
+            write_output(
+                &spec,
+                &output,
+                &expand_out_path(&spec, template, &output.value)?,
+            )?
+        } else {
+            write_stdout(&output)?
+        }
+    }
+    Ok(())
+}
+
+// writes the output into the file, unless the file is outside of where the files are confined by --no-files
+fn write_output(
+    spec: &Transformer,
+    output: &RenderedOutput,
+    path: &str,
+) -> Result<(), JsonatrError> {
+    spec.resolver().check_write(path)?;
+    output.write_to(path)
+}
+
+// replaces each {$EXPR} placeholder of the --out path with the value of the expression against the output,
+// the same way as the placeholders in input sources, e.g. {$.chain_id}
+fn expand_out_path(
+    spec: &Transformer,
+    template: &str,
+    value: &Value,
+) -> Result<String, JsonatrError> {
+    let mut result = String::new();
+    let mut rest = template;
+    while let Some(start) = rest.find("{$") {
+        result.push_str(&rest[..start]);
+        let end = require_with!(
+            find_closing_brace(&rest[start..]),
+            "unclosed placeholder in --out path '{}'",
+            template
+        ) + start;
+        let text = rest[start + 1..end].trim();
+        let selected = spec.eval(&spec.compile(text)?, value)?;
+        // a path selects an array of values, of which a single one is expected
+        let selected = match selected {
+            Value::Array(mut values) if values.len() == 1 => values.remove(0),
+            other => other,
+        };
+        match selected {
+            Value::String(s) => result.push_str(check_file_name_part(&s)?),
+            v if !v.is_object() && !v.is_array() && !v.is_null() => result.push_str(&v.to_string()),
+            _ => bail!(
+                "expression '{}' of --out path doesn't evaluate to a single scalar",
+                text
+            ),
+        }
+        rest = &rest[end + 1..];
+    }
+    result.push_str(rest);
+    Ok(result)
+}
+
+// pretty-prints the JSON of the spec file, keeping the order of its keys unless --sort-keys is given
+fn fmt(opts: &CliOptions) -> Result<(), JsonatrError> {
+    let value = match opts.output_spec.as_deref() {
+        None | Some("-") => parse_stdin()?,
+        Some(path) => parse_file(path)?,
+    };
+    let format = OutputFormat {
+        compact: opts.compact,
+        indent: opts.indent,
+        sort_keys: opts.sort_keys,
+        ..Default::default()
+    };
+    let output = RenderedOutput {
+        name: None,
+        file: opts.output.as_deref().map(expand_env).transpose()?,
+        content: format.encode(&value)?,
+        format,
+        value,
+    };
+    match &output.file {
+        Some(file) => output.write_to(file),
+        None => write_stdout(&output),
+    }
+}
+
+// expands the {stem}, {name}, and {dir} placeholders of the output template for the input file
+fn expand_out_template(template: &str, input: &std::path::Path) -> String {
+    let part = |s: Option<&std::ffi::OsStr>| s.unwrap_or_default().to_string_lossy().into_owned();
+    template
+        .replace("{stem}", &part(input.file_stem()))
+        .replace("{name}", &part(input.file_name()))
+        .replace("{dir}", &part(input.parent().map(|dir| dir.as_os_str())))
+}
+
+// transforms every file matching the pattern with the same spec; the outputs are written according
+// to the template, or otherwise printed to STDOUT one after another
+fn batch_paths(pattern: &str) -> Result<Vec<std::path::PathBuf>, JsonatrError> {
+    Ok(
+        try_with!(glob::glob(pattern), "wrong glob pattern '{}'", pattern)
+            .filter_map(Result::ok)
+            .filter(|path| path.is_file())
+            .collect(),
+    )
+}
+
+fn batch_output(
+    spec: &Transformer,
+    path: &std::path::Path,
+    template: Option<&str>,
+    value: Value,
+) -> Result<RenderedOutput, JsonatrError> {
+    Ok(RenderedOutput {
+        name: None,
+        file: template.map(|template| expand_out_template(template, path)),
+        content: spec.format().encode(&value)?,
+        format: spec.format().clone(),
+        value,
+    })
+}
+
+fn batch(spec: &Transformer, pattern: &str, template: Option<&str>) -> Result<(), JsonatrError> {
+    let paths = batch_paths(pattern)?;
+    let mut error = None;
+    let inputs = paths
+        .iter()
+        .map_while(|path| match parse_file(&path.to_string_lossy()) {
+            Ok(value) => Some(value),
+            Err(e) => {
+                error = Some(e);
+                None
+            }
+        });
+    for (path, value) in paths.iter().zip(spec.transform_iter(inputs)?) {
+        let output = batch_output(spec, path, template, value?)?;
+        match &output.file {
+            Some(file) => write_output(spec, &output, file)?,
+            None => write_stdout(&output)?,
+        }
+    }
+    match error {
+        Some(e) => Err(e),
+        None => Ok(()),
+    }
+}
+
+// same as batch, but the files are transformed by several threads, each taking the next file when done
+// with the previous one; the output files are written by the threads, while the output to STDOUT is kept
+// in the order of the input files. No new files are taken after an error, and the first one is reported
+fn batch_parallel(
+    spec: &Transformer,
+    pattern: &str,
+    template: Option<&str>,
+    jobs: usize,
+) -> Result<(), JsonatrError> {
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use std::sync::Mutex;
+    let paths = batch_paths(pattern)?;
+    let next = AtomicUsize::new(0);
+    let failed = AtomicBool::new(false);
+    // the result for each file, if it was taken; the output is kept only when it goes to STDOUT
+    type Slot = Option<Result<Option<RenderedOutput>, JsonatrError>>;
+    let results: Vec<Mutex<Slot>> = paths.iter().map(|_| Mutex::new(None)).collect();
+    let finish = |index: usize, result: Result<Option<RenderedOutput>, JsonatrError>| {
+        if result.is_err() {
+            failed.store(true, Ordering::SeqCst);
+        }
+        *results[index].lock().unwrap() = Some(result);
+    };
+    std::thread::scope(|scope| {
+        let workers: Vec<_> = (0..jobs.min(paths.len()))
+            .map(|_| {
+                scope.spawn(|| -> Result<(), JsonatrError> {
+                    let current = std::cell::Cell::new(0);
+                    let inputs = std::iter::from_fn(|| {
+                        if failed.load(Ordering::SeqCst) {
+                            return None;
+                        }
+                        let index = next.fetch_add(1, Ordering::SeqCst);
+                        let path = paths.get(index)?;
+                        current.set(index);
+                        match parse_file(&path.to_string_lossy()) {
+                            Ok(value) => Some(value),
+                            Err(e) => {
+                                finish(index, Err(e));
+                                None
+                            }
+                        }
+                    });
+                    for value in spec.transform_iter(inputs)? {
+                        let index = current.get();
+                        let result = value
+                            .and_then(|value| batch_output(spec, &paths[index], template, value))
+                            .and_then(|output| match &output.file {
+                                Some(file) => write_output(spec, &output, file).map(|_| None),
+                                None => Ok(Some(output)),
+                            });
+                        finish(index, result);
+                    }
+                    Ok(())
+                })
+            })
+            .collect();
+        workers
+            .into_iter()
+            .try_for_each(|worker| worker.join().unwrap())
+    })?;
+    for result in results {
+        match result.into_inner().unwrap() {
+            Some(Ok(Some(output))) => write_stdout(&output)?,
+            Some(Ok(None)) => (),
+            Some(Err(e)) => return Err(e),
+            None => break,
+        }
+    }
+    Ok(())
+}
+
+// transforms the documents read from STDIN line by line as they arrive, printing each output as compact
+// JSON on a single line, so that the results can be consumed while the stream goes on
+fn stream(spec: &Transformer) -> Result<(), JsonatrError> {
+    use std::io::BufRead;
+    let mut format = spec.format().clone();
+    if format.encoding.is_binary() {
+        bail!("--stream writes one output per line, so it can't be used with a binary format")
+    }
+    format.compact = true;
+    let mut error = None;
+    let inputs = std::io::stdin()
+        .lock()
+        .lines()
+        .filter(|line| !matches!(line, Ok(line) if line.trim().is_empty()))
+        .map_while(|line| {
+            let result = match line {
+                Ok(line) => parse_string(&line),
+                Err(e) => Err(format!("failed to read from STDIN: {}", e).into()),
+            };
+            match result {
+                Ok(value) => Some(value),
+                Err(e) => {
+                    error = Some(e);
+                    None
+                }
+            }
+        });
+    let mut stdout = std::io::stdout();
+    for value in spec.transform_iter(inputs)? {
+        let content = format.encode(&value?)?;
+        try_with!(stdout.write_all(&content), "failed to write output");
+        try_with!(stdout.write_all(b"\n"), "failed to write output");
+        try_with!(stdout.flush(), "failed to write output");
+    }
+    match error {
+        Some(e) => Err(e),
+        None => Ok(()),
+    }
+}
+
+fn write_stdout(output: &RenderedOutput) -> Result<(), JsonatrError> {
+    let mut stdout = std::io::stdout();
+    try_with!(stdout.write_all(&output.content), "failed to write output");
+    if !output.format.encoding.is_binary() {
+        try_with!(stdout.write_all(b"\n"), "failed to write output");
     }
     Ok(())
 }
 
+// evaluates the expressions read from STDIN one per line; an error is reported, but doesn't end the session
+fn repl(spec: &Transformer, main: &Value) -> Result<(), JsonatrError> {
+    use std::io::{BufRead, IsTerminal};
+    let interactive = std::io::stdin().is_terminal();
+    let prompt = || {
+        if interactive {
+            eprint!("> ");
+            let _ = std::io::stderr().flush();
+        }
+    };
+    prompt();
+    for line in std::io::stdin().lock().lines() {
+        let line = try_with!(line, "failed to read from STDIN");
+        let line = line.trim();
+        if !line.is_empty() {
+            let result = spec
+                .compile(line)
+                .and_then(|expr| spec.eval(&expr, main))
+                .and_then(|value| spec.format().serialize(&value));
+            match result {
+                Ok(text) => println!("{}", text),
+                Err(e) => eprintln!("Error: {}", e),
+            }
+        }
+        prompt();
+    }
+    Ok(())
+}
+
+// Measures the time spent in the inputs and in the top-level expressions, i.e. those not evaluated
+// as a part of another one; the times include the nested evaluations. The report is printed
+// after a successful run
+#[derive(Default)]
+struct Profiler {
+    state: std::sync::Mutex<ProfilerState>,
+}
+
+// the profiler of the run, if --profile is given
+static PROFILER: std::sync::Mutex<Option<Arc<Profiler>>> = std::sync::Mutex::new(None);
+
+#[derive(Default)]
+struct ProfilerState {
+    // the start times of the expressions and inputs being evaluated by each thread, the innermost last
+    expressions: std::collections::HashMap<std::thread::ThreadId, Vec<Instant>>,
+    inputs: std::collections::HashMap<std::thread::ThreadId, Vec<Instant>>,
+    // the total time and the number of evaluations, in the order of the first evaluation
+    times: Vec<(String, Duration, usize)>,
+}
+
+impl ProfilerState {
+    fn record(&mut self, label: String, time: Duration) {
+        match self.times.iter_mut().find(|(l, _, _)| *l == label) {
+            Some((_, total, count)) => {
+                *total += time;
+                *count += 1;
+            }
+            None => self.times.push((label, time, 1)),
+        }
+    }
+}
+
+impl EvaluationObserver for Profiler {
+    fn expression_start(&self, _expr: &str) {
+        let mut state = self.state.lock().unwrap();
+        let stack = state.expressions.entry(std::thread::current().id());
+        stack.or_default().push(Instant::now());
+    }
+
+    fn expression_end(&self, expr: &str, _result: Option<&Value>) {
+        let mut state = self.state.lock().unwrap();
+        let stack = state.expressions.entry(std::thread::current().id());
+        let stack = stack.or_default();
+        if let Some(start) = stack.pop() {
+            if stack.is_empty() {
+                state.record(format!("expression '{}'", expr), start.elapsed());
+            }
+        }
+    }
+
+    fn input_start(&self, _input: &Input) {
+        let mut state = self.state.lock().unwrap();
+        let stack = state.inputs.entry(std::thread::current().id());
+        stack.or_default().push(Instant::now());
+    }
+
+    fn input_end(&self, input: &Input, _result: Option<&Value>) {
+        let mut state = self.state.lock().unwrap();
+        let stack = state.inputs.entry(std::thread::current().id());
+        if let Some(start) = stack.or_default().pop() {
+            let label = format!("input '{}' of kind {:?}", input.name(), input.kind());
+            state.record(label, start.elapsed());
+        }
+    }
+}
+
+impl Profiler {
+    fn report(&self) {
+        let mut times = std::mem::take(&mut self.state.lock().unwrap().times);
+        times.sort_by_key(|(_, time, _)| std::cmp::Reverse(*time));
+        eprintln!("Profile (slowest first):");
+        for (label, time, count) in times {
+            let times = if count > 1 {
+                format!(" ({} times)", count)
+            } else {
+                String::new()
+            };
+            eprintln!(
+                "{:>12.3} ms  {}{}",
+                time.as_secs_f64() * 1000.0,
+                label,
+                times
+            );
+        }
+    }
+}
+
+// Prints the evaluation tree to STDERR as it is evaluated: each output node with its expression, followed by
+// the value its input and path resolve to, and the value after each step of the pipeline; the expressions
+// and inputs evaluated on the way, e.g. builtin arguments, are nested under the step they belong to
+#[derive(Default)]
+struct Explainer {
+    state: std::sync::Mutex<ExplainerState>,
+}
+
+#[derive(Default)]
+struct ExplainerState {
+    depth: usize,
+    // the output node whose expression is about to start
+    node: Option<String>,
+}
+
+impl Explainer {
+    fn line(&self, state: &ExplainerState, text: String) {
+        eprintln!("{}{}", "  ".repeat(state.depth), text);
+    }
+}
+
+// the value, shortened to a single line, or a mark of the failed step
+fn explain_value(result: Option<&Value>) -> String {
+    match result {
+        Some(value) => summarize(value),
+        None => "(failed)".to_string(),
+    }
+}
+
+impl EvaluationObserver for Explainer {
+    fn output_node(&self, pointer: &str, _expr: &str) {
+        let pointer = if pointer.is_empty() {
+            "(root)"
+        } else {
+            pointer
+        };
+        self.state.lock().unwrap().node = Some(pointer.to_string());
+    }
+
+    fn expression_start(&self, expr: &str) {
+        let mut state = self.state.lock().unwrap();
+        match state.node.take() {
+            Some(pointer) => self.line(&state, format!("{}: {}", pointer, expr)),
+            None => self.line(&state, format!("expression {}", expr)),
+        }
+        state.depth += 1;
+    }
+
+    fn expression_end(&self, _expr: &str, result: Option<&Value>) {
+        let mut state = self.state.lock().unwrap();
+        self.line(&state, format!("= {}", explain_value(result)));
+        state.depth = state.depth.saturating_sub(1);
+    }
+
+    fn input_start(&self, input: &Input) {
+        let mut state = self.state.lock().unwrap();
+        let text = format!("input '{}' of kind {:?}", input.name(), input.kind());
+        self.line(&state, text);
+        state.depth += 1;
+    }
+
+    fn input_end(&self, _input: &Input, result: Option<&Value>) {
+        let mut state = self.state.lock().unwrap();
+        self.line(&state, format!("= {}", explain_value(result)));
+        state.depth = state.depth.saturating_sub(1);
+    }
+
+    fn path_resolved(&self, _expr: &str, value: &Value) {
+        let state = self.state.lock().unwrap();
+        self.line(&state, format!("resolved to {}", summarize(value)));
+    }
+
+    fn builtin_applied(&self, name: &str, args: &[String], result: Option<&Value>) {
+        let state = self.state.lock().unwrap();
+        let step = if args.is_empty() {
+            name.to_string()
+        } else {
+            format!("{}({})", name, args.join(", "))
+        };
+        self.line(&state, format!("| {} => {}", step, explain_value(result)));
+    }
+
+    fn input_applied(&self, name: &str, result: Option<&Value>) {
+        let state = self.state.lock().unwrap();
+        self.line(&state, format!("| {} => {}", name, explain_value(result)));
+    }
+}
+
+// prints the diagnostics of the library to STDERR
+struct StderrLogger;
+
+impl log::Log for StderrLogger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &log::Record) {
+        if self.enabled(record.metadata()) {
+            let level = match record.level() {
+                log::Level::Error => "Error",
+                log::Level::Warn => "Warning",
+                log::Level::Info => "Info",
+                log::Level::Debug => "Debug",
+                log::Level::Trace => "Trace",
+            };
+            eprintln!("{}: {}", level, record.args());
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+static LOGGER: StderrLogger = StderrLogger;
+
+// the exit code for the error: 2 if the spec or an input can't be parsed, 3 if the transformation fails,
+// 4 on a failure to read or write a file, and 1 otherwise
+fn exit_code(error: &JsonatrError) -> i32 {
+    match error {
+        JsonatrError::ParseError { .. }
+        | JsonatrError::UnknownInput(_)
+        | JsonatrError::JsonPathError { .. } => 2,
+        JsonatrError::EvaluationError { .. }
+        | JsonatrError::CommandFailed { .. }
+        | JsonatrError::Cancelled => 3,
+        JsonatrError::IoError { .. } => 4,
+        JsonatrError::Other(_) => 1,
+    }
+}
+
+// whether the error reports are colored, as decided by --color
+static COLOR: std::sync::atomic::AtomicBool = std::sync::atomic::AtomicBool::new(false);
+
+// the spec texts given to the CLI, kept to show the parse errors in context: by the origin reported
+// in the errors, e.g. the full path of the file, with the label to show, e.g. the path as given
+type Source = (String, String, String);
+static SOURCES: std::sync::Mutex<Vec<Source>> = std::sync::Mutex::new(Vec::new());
+
+// the ANSI styles of the parts of an error report
+const ERROR: &str = "1;31";
+const LOCATION: &str = "1;36";
+const MARK: &str = "1;31";
+const HINT: &str = "1;32";
+
+fn add_source(origin: &str, label: &str, text: &str) {
+    SOURCES
+        .lock()
+        .unwrap()
+        .push((origin.to_string(), label.to_string(), text.to_string()));
+}
+
+// the file is read once more only for the report; if it can't be, the library reports that anyway.
+// The library reports the used specs by their paths joined to the directory of the spec
+fn add_source_file(dir: &str, path: &str) {
+    if let Ok(text) = std::fs::read_to_string(path) {
+        let origin = std::path::Path::new(dir).join(path);
+        add_source(&origin.to_string_lossy(), path, &text);
+    }
+}
+
+fn paint(style: &str, text: &str) -> String {
+    if COLOR.load(std::sync::atomic::Ordering::Relaxed) {
+        format!("\x1b[{}m{}\x1b[0m", style, text)
+    } else {
+        text.to_string()
+    }
+}
+
+// the part of the text around the column (1-based), at most 80 characters wide, and the column within it
+fn snippet(line: &str, column: usize) -> (String, usize) {
+    const WIDTH: usize = 80;
+    let chars: Vec<char> = line
+        .chars()
+        .map(|c| if c == '\t' { ' ' } else { c })
+        .collect();
+    let start = column
+        .saturating_sub(WIDTH / 2)
+        .min(chars.len().saturating_sub(WIDTH));
+    let end = (start + WIDTH).min(chars.len());
+    (chars[start..end].iter().collect(), column - start)
+}
+
+// the label, line, and column of the JSON error in the spec source it comes from
+fn locate_json_error(
+    origin: &str,
+    error: &serde_json::Error,
+) -> Option<(String, String, usize, usize)> {
+    if error.line() == 0 {
+        return None;
+    }
+    let sources = SOURCES.lock().unwrap();
+    let (_, label, text) = sources.iter().find(|(source, _, _)| source == origin)?;
+    let line = text.lines().nth(error.line() - 1).unwrap_or_default();
+    Some((
+        label.clone(),
+        line.to_string(),
+        error.line(),
+        error.column().max(1),
+    ))
+}
+
+// the hint on how to fix the error, recognized by its kind, and, for JSON errors, by the category of the problem
+fn hint(error: &JsonatrError) -> Option<&'static str> {
+    let hint = match error {
+        JsonatrError::ParseError { source, .. } => {
+            let json_error = source.downcast_ref::<serde_json::Error>()?;
+            let reason = json_error.to_string();
+            if json_error.is_eof() {
+                "the JSON ends too early; check for an unclosed bracket or string"
+            } else if reason.contains("trailing comma") {
+                "JSON doesn't allow a comma after the last element of an array or object"
+            } else if reason.contains("expected `,` or") {
+                "a comma may be missing between the elements, or a bracket left unclosed before this place"
+            } else if reason.contains("key must be a string") {
+                "the keys of JSON objects should be in double quotes"
+            } else if reason.contains("expected value") {
+                "an expression alone should be quoted as a JSON string, e.g. '\"$.height\"'"
+            } else {
+                return None;
+            }
+        }
+        JsonatrError::IoError { .. } => {
+            "relative paths are resolved against the directory of the spec; --include-path adds directories to search"
+        }
+        JsonatrError::JsonPathError { .. } => {
+            "a path starts with $ for the whole input, e.g. $.validators[0].address"
+        }
+        JsonatrError::UnknownInput(_) => {
+            "define the input in the \"input\" section of the spec, or give it with --set NAME=JSON"
+        }
+        JsonatrError::EvaluationError {
+            location: Some(ErrorLocation {
+                builtin: Some(_), ..
+            }),
+            ..
+        } => "check the types of the piped value and of the arguments; --explain shows the intermediate values",
+        JsonatrError::Other(message) if message == "no output specified" => {
+            "give the output spec as the argument, or as \"output\" in the spec file"
+        }
+        _ => return None,
+    };
+    Some(hint)
+}
+
+// renders the error for STDERR: the message, followed, if known, by the place in the spec with the offending
+// part marked (the position of a JSON error, or the failing part of the expression of an output node), and a hint
+fn render_error(error: &JsonatrError) -> String {
+    let mut lines = Vec::new();
+    let mut place = None;
+    match error {
+        JsonatrError::ParseError {
+            source,
+            origin: Some(origin),
+            ..
+        } => {
+            let json_error = source.downcast_ref::<serde_json::Error>();
+            if let Some((label, line, row, column)) =
+                json_error.and_then(|e| locate_json_error(origin, e))
+            {
+                let location = format!("{}:{}:{}", label, row, column);
+                let (text, column) = snippet(&line, column);
+                place = Some((location, row.to_string(), text, column - 1, 1));
+            }
+        }
+        JsonatrError::EvaluationError {
+            message,
+            location: Some(location),
+        } => {
+            // the failed input or builtin is marked in the expression if it occurs there; otherwise the whole expression is
+            let text = &location.expression;
+            let name = location.input.as_ref().or(location.builtin.as_ref());
+            let (start, len) =
+                match name.and_then(|name| text.rfind(name.as_str()).map(|pos| (pos, name))) {
+                    Some((pos, name)) => (text[..pos].chars().count(), name.chars().count()),
+                    None => (0, text.chars().count()),
+                };
+            let location = if location.pointer.is_empty() {
+                "output".to_string()
+            } else {
+                format!("output {}", location.pointer)
+            };
+            place = Some((location, String::new(), text.to_string(), start, len.max(1)));
+            lines.push(format!("{}: {}", paint(ERROR, "Error"), message));
+        }
+        _ => (),
+    }
+    if lines.is_empty() {
+        lines.push(format!("{}: {}", paint(ERROR, "Error"), error));
+    }
+    if let Some((location, number, text, start, len)) = place {
+        let gutter = " ".repeat(number.len().max(1));
+        let number = format!("{:>width$}", number, width = gutter.len());
+        lines.push(format!(
+            "{} {} {}",
+            gutter,
+            paint(LOCATION, "-->"),
+            location
+        ));
+        lines.push(format!("{} {}", gutter, paint(LOCATION, "|")));
+        lines.push(format!(
+            "{} {} {}",
+            paint(LOCATION, &number),
+            paint(LOCATION, "|"),
+            text
+        ));
+        let mark = format!("{}{}", " ".repeat(start), paint(MARK, &"^".repeat(len)));
+        lines.push(format!("{} {} {}", gutter, paint(LOCATION, "|"), mark));
+    }
+    if let Some(hint) = hint(error) {
+        lines.push(format!("{}: {}", paint(HINT, "hint"), hint));
+    }
+    lines.join("\n")
+}
+
 fn main() {
+    if log::set_logger(&LOGGER).is_ok() {
+        log::set_max_level(log::LevelFilter::Warn);
+    }
     match run() {
-        Ok(_) => (),
+        Ok(()) => {
+            if let Some(profiler) = PROFILER.lock().unwrap().take() {
+                profiler.report();
+            }
+        }
         Err(e) => {
-            println!("Error: {}", e);
-            std::process::exit(1);
+            eprintln!("{}", render_error(&e));
+            std::process::exit(exit_code(&e));
         }
     }
 }
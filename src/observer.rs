@@ -0,0 +1,30 @@
+use crate::transformer::Input;
+use serde_json::Value;
+
+// Receives notifications about the steps of evaluating a spec, e.g. for collecting timing, logging,
+// or audit trails; all methods do nothing by default, so only the relevant ones need to be implemented.
+// A failed step is reported with None as the result.
+pub trait EvaluationObserver: Send + Sync {
+    // the expression of the output node at the JSON pointer, e.g. "/validators/3/address", is about
+    // to be evaluated; called before expression_start for the outermost expression of the node
+    fn output_node(&self, _pointer: &str, _expr: &str) {}
+
+    // a Jsonatr expression, e.g. "$input.path | builtin", is about to be evaluated
+    fn expression_start(&self, _expr: &str) {}
+
+    fn expression_end(&self, _expr: &str, _result: Option<&Value>) {}
+
+    // the input is about to be fetched; not called when the input is taken from a cache or a local scope
+    fn input_start(&self, _input: &Input) {}
+
+    fn input_end(&self, _input: &Input, _result: Option<&Value>) {}
+
+    // the input and the path of the expression have been resolved to the value, before its transforms
+    fn path_resolved(&self, _expr: &str, _value: &Value) {}
+
+    // the builtin has been applied with the given arguments
+    fn builtin_applied(&self, _name: &str, _args: &[String], _result: Option<&Value>) {}
+
+    // the input has been applied as a transform of the expression
+    fn input_applied(&self, _name: &str, _result: Option<&Value>) {}
+}
@@ -0,0 +1,444 @@
+#[cfg(feature = "toml")]
+use crate::backend::TomlBackend;
+#[cfg(any(feature = "toml", feature = "yaml"))]
+use crate::backend::ValueBackend;
+#[cfg(feature = "yaml")]
+use crate::backend::YamlBackend;
+use crate::error::JsonatrError;
+#[cfg(feature = "files")]
+use crate::helpers::parse_file;
+use crate::helpers::sort_keys;
+use crate::path::PathEngine;
+use serde::{Deserialize, Serialize};
+use serde_json::ser::PrettyFormatter;
+use serde_json::Value;
+use simple_error::*;
+#[cfg(feature = "commands")]
+use std::io::Write;
+#[cfg(feature = "files")]
+use std::path::Path;
+#[cfg(feature = "commands")]
+use std::process::{Command, Stdio};
+
+// The serialization format of the output
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Encoding {
+    #[default]
+    Json,
+    // newline-delimited JSON: the elements of an array output, each compact on its own line
+    Ndjson,
+    Yaml,
+    Toml,
+    Cbor,
+    MsgPack,
+}
+
+impl Encoding {
+    pub fn is_binary(&self) -> bool {
+        matches!(self, Encoding::Cbor | Encoding::MsgPack)
+    }
+
+    pub fn is_default(&self) -> bool {
+        *self == Encoding::default()
+    }
+}
+
+fn to_ndjson(value: &Value) -> Result<String, JsonatrError> {
+    let lines = match value {
+        Value::Array(values) => values
+            .iter()
+            .map(serde_json::to_string)
+            .collect::<Result<Vec<_>, _>>(),
+        value => serde_json::to_string(value).map(|line| vec![line]),
+    };
+    Ok(try_with!(lines, "failed to produce output").join("\n"))
+}
+
+#[cfg(feature = "yaml")]
+fn to_yaml(value: &Value) -> Result<String, JsonatrError> {
+    let value = YamlBackend.to_value(value.clone())?;
+    Ok(YamlBackend.render(&value)?.trim_end().to_string())
+}
+
+#[cfg(not(feature = "yaml"))]
+fn to_yaml(_: &Value) -> Result<String, JsonatrError> {
+    bail!("YAML output is not supported: jsonatr is built without the 'yaml' feature")
+}
+
+#[cfg(feature = "toml")]
+fn to_toml(value: &Value) -> Result<String, JsonatrError> {
+    let value = TomlBackend.to_value(value.clone())?;
+    Ok(TomlBackend.render(&value)?.trim_end().to_string())
+}
+
+#[cfg(not(feature = "toml"))]
+fn to_toml(_: &Value) -> Result<String, JsonatrError> {
+    bail!("TOML output is not supported: jsonatr is built without the 'toml' feature")
+}
+
+#[cfg(feature = "cbor")]
+fn to_cbor(value: &Value) -> Result<Vec<u8>, JsonatrError> {
+    let mut bytes = Vec::new();
+    try_with!(
+        ciborium::ser::into_writer(value, &mut bytes),
+        "failed to produce CBOR output"
+    );
+    Ok(bytes)
+}
+
+#[cfg(not(feature = "cbor"))]
+fn to_cbor(_: &Value) -> Result<Vec<u8>, JsonatrError> {
+    bail!("CBOR output is not supported: jsonatr is built without the 'cbor' feature")
+}
+
+#[cfg(feature = "msgpack")]
+fn to_msgpack(value: &Value) -> Result<Vec<u8>, JsonatrError> {
+    Ok(try_with!(
+        rmp_serde::to_vec_named(value),
+        "failed to produce MessagePack output"
+    ))
+}
+
+#[cfg(not(feature = "msgpack"))]
+fn to_msgpack(_: &Value) -> Result<Vec<u8>, JsonatrError> {
+    bail!("MessagePack output is not supported: jsonatr is built without the 'msgpack' feature")
+}
+
+// Controls how the transformed output is serialized; can be given in the spec as the "format" object
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Default)]
+#[serde(deny_unknown_fields)]
+pub struct OutputFormat {
+    // produce compact JSON on a single line, instead of pretty-printed JSON
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub compact: bool,
+    // the number of indentation characters per nesting level of pretty-printed JSON (default 2)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub indent: Option<usize>,
+    // the indentation character of pretty-printed JSON: space (default), "\t", "\n", or "\r"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub indent_char: Option<char>,
+    // sort the keys of all objects alphabetically
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub sort_keys: bool,
+    // output a string value as is, without quotes and JSON escaping
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub raw: bool,
+    // render the output through the given Handlebars template, instead of serializing it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub template: Option<String>,
+    // a command receiving the produced output on STDIN; its STDOUT becomes the final output
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pipe: Option<String>,
+    // when writing into an existing JSON file, deep-merge the output into its contents instead of overwriting it
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub merge: bool,
+    // when overwriting an existing file, keep its previous version with the ".bak" extension appended
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub backup: bool,
+    #[serde(default, skip_serializing_if = "Encoding::is_default")]
+    pub encoding: Encoding,
+}
+
+impl OutputFormat {
+    pub fn is_default(&self) -> bool {
+        *self == OutputFormat::default()
+    }
+
+    // serializes the value in a textual encoding
+    pub fn serialize(&self, value: &Value) -> Result<String, JsonatrError> {
+        let text = self.to_text(value)?;
+        if self.pipe.is_none() {
+            return Ok(text);
+        }
+        let result = self.apply_pipe(text.into_bytes())?;
+        Ok(try_with!(
+            String::from_utf8(result),
+            "output pipe produced non-UTF-8 output"
+        ))
+    }
+
+    fn to_text(&self, value: &Value) -> Result<String, JsonatrError> {
+        if let Some(template) = &self.template {
+            return Ok(render_template(template, &self.prepare(value))?);
+        }
+        if self.encoding.is_binary() {
+            bail!("binary output encoding can't be produced as a string")
+        }
+        if let (true, Value::String(string)) = (self.raw, value) {
+            return Ok(string.clone());
+        }
+        let value = &self.prepare(value);
+        match self.encoding {
+            Encoding::Ndjson => return to_ndjson(value),
+            Encoding::Yaml => return to_yaml(value),
+            Encoding::Toml => return to_toml(value),
+            _ => (),
+        }
+        if self.compact {
+            return Ok(try_with!(
+                serde_json::to_string(value),
+                "failed to produce output"
+            ));
+        }
+        // any other character would make the output invalid JSON
+        let indent_char = match self.indent_char.unwrap_or(' ') {
+            c @ (' ' | '\t' | '\n' | '\r') => c,
+            c => bail!(
+                "wrong indentation character {:?}: only space, tab, and line breaks are allowed in JSON",
+                c
+            ),
+        };
+        let indent = indent_char.to_string().repeat(self.indent.unwrap_or(2));
+        let mut bytes = Vec::new();
+        let mut serializer = serde_json::Serializer::with_formatter(
+            &mut bytes,
+            PrettyFormatter::with_indent(indent.as_bytes()),
+        );
+        try_with!(value.serialize(&mut serializer), "failed to produce output");
+        Ok(try_with!(
+            String::from_utf8(bytes),
+            "failed to produce output"
+        ))
+    }
+
+    // applies the format options that don't depend on the encoding
+    fn prepare(&self, value: &Value) -> Value {
+        if self.sort_keys {
+            sort_keys(value)
+        } else {
+            value.clone()
+        }
+    }
+
+    // serializes the value in any encoding, including binary ones
+    pub fn encode(&self, value: &Value) -> Result<Vec<u8>, JsonatrError> {
+        let result = match self.encoding {
+            _ if self.template.is_some() => self.to_text(value)?.into_bytes(),
+            Encoding::Json | Encoding::Ndjson | Encoding::Yaml | Encoding::Toml => {
+                self.to_text(value)?.into_bytes()
+            }
+            Encoding::Cbor => to_cbor(&self.prepare(value))?,
+            Encoding::MsgPack => to_msgpack(&self.prepare(value))?,
+        };
+        self.apply_pipe(result)
+    }
+
+    // passes the output through the pipe command, if any
+    #[cfg(not(feature = "commands"))]
+    fn apply_pipe(&self, output: Vec<u8>) -> Result<Vec<u8>, JsonatrError> {
+        match &self.pipe {
+            None => Ok(output),
+            Some(pipe) => Err(JsonatrError::command(
+                pipe,
+                "jsonatr is built without the 'commands' feature",
+            )),
+        }
+    }
+
+    #[cfg(feature = "commands")]
+    fn apply_pipe(&self, output: Vec<u8>) -> Result<Vec<u8>, JsonatrError> {
+        let pipe = match &self.pipe {
+            None => return Ok(output),
+            Some(pipe) => pipe,
+        };
+        let args = match shell_words::split(pipe) {
+            Ok(args) if !args.is_empty() => args,
+            _ => return Err(JsonatrError::command(pipe, "failed to parse command")),
+        };
+        let mut process = Command::new(&args[0])
+            .args(&args[1..])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .map_err(|e| JsonatrError::command(pipe, e))?;
+        // STDIN is written from a separate thread, so that the command can't block on pipes
+        let mut stdin = process.stdin.take().unwrap();
+        let writer = std::thread::spawn(move || stdin.write_all(&output));
+        let result = process
+            .wait_with_output()
+            .map_err(|e| JsonatrError::command(pipe, e))?;
+        if let Ok(Err(e)) = writer.join() {
+            if e.kind() != std::io::ErrorKind::BrokenPipe {
+                return Err(JsonatrError::command(pipe, e));
+            }
+        }
+        if !result.status.success() {
+            return Err(JsonatrError::command(pipe, result.status));
+        }
+        Ok(result.stdout)
+    }
+}
+
+// renders the value through a Handlebars template; the value is the root context of the template
+#[cfg(feature = "templates")]
+fn render_template(template: &str, value: &Value) -> Result<String, SimpleError> {
+    let mut handlebars = handlebars::Handlebars::new();
+    handlebars.register_escape_fn(handlebars::no_escape);
+    let result = try_with!(
+        handlebars.render_template(template, value),
+        "failed to render output template"
+    );
+    Ok(result)
+}
+
+#[cfg(not(feature = "templates"))]
+fn render_template(_: &str, _: &Value) -> Result<String, SimpleError> {
+    bail!("output templates are not supported: jsonatr is built without the 'templates' feature")
+}
+
+// An additional output of the spec, given in the "outputs" array, with its own destination and format
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct NamedOutput {
+    pub name: String,
+    pub output: Value,
+    // the file to write the output into; if absent, the output goes wherever the main output goes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub file: Option<String>,
+    // the format of the output; if absent, the format of the spec is used
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub format: Option<OutputFormat>,
+    // write each element of the array output into its own file, named by the given template:
+    // "{.name}" is replaced by the JSONPath selection from the element, and "{#}" by its index
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub split: Option<String>,
+}
+
+// The serialized result of one output of the spec
+#[derive(Debug, Clone, PartialEq)]
+pub struct RenderedOutput {
+    // the name of the output, or None for the main output
+    pub name: Option<String>,
+    pub file: Option<String>,
+    pub content: Vec<u8>,
+    pub format: OutputFormat,
+    // the output value before serialization
+    pub value: Value,
+}
+
+#[cfg(feature = "files")]
+impl RenderedOutput {
+    // writes the output into the file, creating its directory if needed; the file is replaced atomically,
+    // by writing into a temporary file next to it first, so that a failure can't leave it truncated
+    pub fn write_to(&self, path: &str) -> Result<(), JsonatrError> {
+        let file = Path::new(path);
+        if let Some(dir) = file.parent() {
+            std::fs::create_dir_all(dir).map_err(|e| JsonatrError::io(path, e))?;
+        }
+        let content = if self.format.merge && file.exists() {
+            if self.format.encoding != Encoding::Json || self.format.template.is_some() {
+                bail!("only JSON output can be merged into an existing file")
+            }
+            let existing = parse_file(path)?;
+            self.format.encode(&merge_values(existing, &self.value))?
+        } else {
+            self.content.clone()
+        };
+        let mut tmp_name = file.file_name().unwrap_or_default().to_os_string();
+        tmp_name.push(format!(".tmp{}", std::process::id()));
+        let tmp = TempFile(Some(file.with_file_name(tmp_name)));
+        std::fs::write(tmp.path(), content).map_err(|e| JsonatrError::io(path, e))?;
+        if self.format.backup && file.exists() {
+            let mut backup = file.as_os_str().to_os_string();
+            backup.push(".bak");
+            std::fs::copy(file, backup).map_err(|e| JsonatrError::io(path, e))?;
+        }
+        tmp.persist(file).map_err(|e| JsonatrError::io(path, e))
+    }
+}
+
+// A temporary file, removed when dropped unless it was renamed into its final place
+#[cfg(feature = "files")]
+struct TempFile(Option<std::path::PathBuf>);
+
+#[cfg(feature = "files")]
+impl TempFile {
+    fn path(&self) -> &Path {
+        self.0.as_deref().unwrap()
+    }
+
+    fn persist(mut self, to: &Path) -> std::io::Result<()> {
+        std::fs::rename(self.path(), to)?;
+        self.0 = None;
+        Ok(())
+    }
+}
+
+#[cfg(feature = "files")]
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        if let Some(path) = self.0.take() {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+// deep-merges the update into the value: objects are merged key by key, anything else is replaced
+#[cfg(feature = "files")]
+fn merge_values(value: Value, update: &Value) -> Value {
+    match (value, update) {
+        (Value::Object(mut obj), Value::Object(update)) => {
+            for (k, v) in update {
+                match obj.get_mut(k) {
+                    Some(existing) => *existing = merge_values(existing.take(), v),
+                    None => {
+                        obj.insert(k.clone(), v.clone());
+                    }
+                }
+            }
+            Value::Object(obj)
+        }
+        (_, update) => update.clone(),
+    }
+}
+
+// expands the file name template for the element at the given index of a split output;
+// the paths in braces are selected by the path engine
+pub fn expand_filename(
+    template: &str,
+    element: &Value,
+    index: usize,
+    engine: &dyn PathEngine,
+) -> Result<String, JsonatrError> {
+    let mut result = String::new();
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        result.push_str(&rest[..start]);
+        let end = require_with!(
+            rest[start..].find('}'),
+            "unclosed brace in file name template '{}'",
+            template
+        ) + start;
+        let path = &rest[start + 1..end];
+        if path == "#" {
+            result.push_str(&index.to_string());
+        } else {
+            match engine.select(element, path)?.as_slice() {
+                [Value::String(s)] => result.push_str(check_file_name_part(s)?),
+                [v] if !v.is_object() && !v.is_array() && !v.is_null() => {
+                    result.push_str(&v.to_string())
+                }
+                _ => bail!(
+                    "JSONPath '{}' of file name template doesn't select a single scalar from element {}",
+                    path,
+                    index
+                ),
+            }
+        }
+        rest = &rest[end + 1..];
+    }
+    result.push_str(rest);
+    Ok(result)
+}
+
+// the values substituted into file names come from the data, so they can't lead into another directory
+pub fn check_file_name_part(part: &str) -> Result<&str, JsonatrError> {
+    if part.contains(['/', '\\']) || part.contains("..") {
+        bail!(
+            "value '{}' can't be a part of a file name: it contains a path separator or '..'",
+            part
+        )
+    }
+    Ok(part)
+}
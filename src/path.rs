@@ -0,0 +1,51 @@
+use crate::error::JsonatrError;
+use serde_json::Value;
+
+// Selects the parts of an input addressed by the path of an expression, i.e. whatever follows
+// the input name in "$input.path"; the selected values are collected into an array
+pub trait PathEngine: Send + Sync {
+    fn select<'a>(&self, value: &'a Value, path: &str) -> Result<Vec<&'a Value>, JsonatrError>;
+
+    // checks the syntax of the path without applying it to a value
+    fn check(&self, path: &str) -> Result<(), JsonatrError> {
+        self.select(&Value::Null, path).map(|_| ())
+    }
+}
+
+// JSONPath expressions, interpreted by the jsonpath_lib; this is the default engine
+#[derive(Debug, Clone, Copy, Default)]
+pub struct JsonPathEngine;
+
+#[cfg(feature = "jsonpath")]
+impl PathEngine for JsonPathEngine {
+    fn select<'a>(&self, value: &'a Value, path: &str) -> Result<Vec<&'a Value>, JsonatrError> {
+        jsonpath::select(value, &format!("${}", path)).map_err(|e| JsonatrError::jsonpath(path, e))
+    }
+}
+
+// without the jsonpath_lib, paths can only be used with another engine
+#[cfg(not(feature = "jsonpath"))]
+impl PathEngine for JsonPathEngine {
+    fn select<'a>(&self, _: &'a Value, path: &str) -> Result<Vec<&'a Value>, JsonatrError> {
+        Err(JsonatrError::Other(format!(
+            "JSONPath '{}' is not supported: jsonatr is built without the 'jsonpath' feature",
+            path
+        )))
+    }
+}
+
+// JSON Pointers (RFC 6901), e.g. "$input/validators/0"; selects at most one value
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PointerEngine;
+
+impl PathEngine for PointerEngine {
+    fn select<'a>(&self, value: &'a Value, path: &str) -> Result<Vec<&'a Value>, JsonatrError> {
+        if !path.starts_with('/') {
+            return Err(JsonatrError::JsonPathError {
+                path: path.to_string(),
+                reason: "a JSON Pointer should start with '/'".to_string(),
+            });
+        }
+        Ok(value.pointer(path).into_iter().collect())
+    }
+}
@@ -0,0 +1,77 @@
+use crate::transformer::Transformer;
+use pyo3::create_exception;
+use pyo3::exceptions::PyException;
+use pyo3::prelude::*;
+use pyo3::types::PyString;
+use serde_json::Value;
+
+create_exception!(jsonatr, JsonatrError, PyException);
+
+fn to_py_err(e: impl std::fmt::Display) -> PyErr {
+    JsonatrError::new_err(e.to_string())
+}
+
+// Python objects are converted to and from JSON through the standard json module
+fn to_value(obj: &Bound<'_, PyAny>) -> PyResult<Value> {
+    let json = obj.py().import("json")?;
+    let text: String = json.call_method1("dumps", (obj,))?.extract()?;
+    serde_json::from_str(&text).map_err(to_py_err)
+}
+
+fn to_object(py: Python<'_>, value: &Value) -> PyResult<PyObject> {
+    let json = py.import("json")?;
+    Ok(json.call_method1("loads", (value.to_string(),))?.unbind())
+}
+
+// The Jsonatr transformer, created from a spec given as a JSON string, or as a dict
+#[pyclass(name = "Transformer")]
+pub struct PyTransformer {
+    spec: Transformer,
+}
+
+#[pymethods]
+impl PyTransformer {
+    // the path is used for resolving the specs referenced in "use"
+    #[new]
+    #[pyo3(signature = (spec, path = "."))]
+    fn new(spec: &Bound<'_, PyAny>, path: &str) -> PyResult<Self> {
+        let spec = if let Ok(text) = spec.downcast::<PyString>() {
+            Transformer::new(text.to_str()?, path)
+        } else {
+            Transformer::from_value(to_value(spec)?, path)
+        };
+        Ok(PyTransformer {
+            spec: spec.map_err(to_py_err)?,
+        })
+    }
+
+    #[staticmethod]
+    fn from_file(path: &str) -> PyResult<Self> {
+        let text = std::fs::read_to_string(path).map_err(to_py_err)?;
+        let spec = Transformer::new(&text, path).map_err(to_py_err)?;
+        Ok(PyTransformer { spec })
+    }
+
+    // transforms the input (any JSON-compatible object, or None) into the output object
+    #[pyo3(signature = (input = None))]
+    fn transform(&self, py: Python<'_>, input: Option<&Bound<'_, PyAny>>) -> PyResult<PyObject> {
+        let input = match input {
+            Some(input) => to_value(input)?,
+            None => Value::Null,
+        };
+        let output = self.spec.transform_value_root(&input).map_err(to_py_err)?;
+        to_object(py, &output)
+    }
+
+    // returns the problems found in the spec, as messages
+    fn validate(&self) -> Vec<String> {
+        self.spec.validate().iter().map(|d| d.to_string()).collect()
+    }
+}
+
+#[pymodule]
+fn jsonatr(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<PyTransformer>()?;
+    m.add("JsonatrError", m.py().get_type::<JsonatrError>())?;
+    Ok(())
+}
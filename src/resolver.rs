@@ -0,0 +1,157 @@
+use crate::error::JsonatrError;
+use std::collections::HashMap;
+
+// Loads the files referenced by a spec: used specs, the sources of FILE, TOML, CSV, CBOR,
+// MSGPACK and ARCHIVE inputs, and the files matched by GLOB inputs; allows them to come from somewhere else than the local disk
+pub trait Resolver: Send + Sync {
+    fn read(&self, path: &str) -> Result<Vec<u8>, JsonatrError>;
+
+    fn read_to_string(&self, path: &str) -> Result<String, JsonatrError> {
+        String::from_utf8(self.read(path)?)
+            .map_err(|e| JsonatrError::Other(format!("file '{}' is not valid UTF-8: {}", path, e)))
+    }
+
+    // whether the path refers to a file, as opposed to a directory
+    fn is_file(&self, path: &str) -> bool {
+        self.read(path).is_ok()
+    }
+
+    // fails if the outputs of the spec can't be written into the file; any file can be by default
+    fn check_write(&self, _path: &str) -> Result<(), JsonatrError> {
+        Ok(())
+    }
+}
+
+// Reads files from the local disk; this is the default resolver
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FileResolver;
+
+#[cfg(feature = "files")]
+impl Resolver for FileResolver {
+    fn read(&self, path: &str) -> Result<Vec<u8>, JsonatrError> {
+        std::fs::read(path).map_err(|e| JsonatrError::io(path, e))
+    }
+
+    fn is_file(&self, path: &str) -> bool {
+        std::path::Path::new(path).is_file()
+    }
+}
+
+// Reads files from the local disk, but only those within the root directory, e.g. for specs from
+// untrusted sources; symbolic links are followed before checking, so they can't lead outside of the root
+#[cfg(feature = "files")]
+#[derive(Debug, Clone)]
+pub struct ConfinedResolver {
+    root: std::path::PathBuf,
+}
+
+#[cfg(feature = "files")]
+impl ConfinedResolver {
+    pub fn new(root: &str) -> Result<Self, JsonatrError> {
+        let root = std::fs::canonicalize(root).map_err(|e| JsonatrError::io(root, e))?;
+        Ok(ConfinedResolver { root })
+    }
+
+    fn confine(&self, path: &str) -> Result<std::path::PathBuf, JsonatrError> {
+        let canonical = std::fs::canonicalize(path).map_err(|e| JsonatrError::io(path, e))?;
+        if !canonical.starts_with(&self.root) {
+            return Err(JsonatrError::Other(format!(
+                "file '{}' is outside of the directory '{}'",
+                path,
+                self.root.display()
+            )));
+        }
+        Ok(canonical)
+    }
+}
+
+#[cfg(feature = "files")]
+impl Resolver for ConfinedResolver {
+    fn read(&self, path: &str) -> Result<Vec<u8>, JsonatrError> {
+        std::fs::read(self.confine(path)?).map_err(|e| JsonatrError::io(path, e))
+    }
+
+    fn is_file(&self, path: &str) -> bool {
+        self.confine(path).is_ok_and(|path| path.is_file())
+    }
+
+    // the file and its directories may not exist yet, so the nearest existing ancestor is confined,
+    // and the rest of the path can't go up from it
+    fn check_write(&self, path: &str) -> Result<(), JsonatrError> {
+        let file = std::path::Path::new(path);
+        let mut base = file;
+        while !base.as_os_str().is_empty() && !base.exists() {
+            base = base.parent().unwrap_or(std::path::Path::new(""));
+        }
+        let rest = file.strip_prefix(base).unwrap_or(file);
+        let base = if base.as_os_str().is_empty() {
+            "."
+        } else {
+            base.to_str().unwrap_or(path)
+        };
+        let inside = rest
+            .components()
+            .all(|c| matches!(c, std::path::Component::Normal(_)))
+            && self.confine(base).is_ok();
+        if !inside {
+            return Err(JsonatrError::Other(format!(
+                "file '{}' is outside of the directory '{}'",
+                path,
+                self.root.display()
+            )));
+        }
+        Ok(())
+    }
+}
+
+// without access to the local disk, all files should come from another resolver
+#[cfg(not(feature = "files"))]
+impl Resolver for FileResolver {
+    fn read(&self, path: &str) -> Result<Vec<u8>, JsonatrError> {
+        Err(JsonatrError::Other(format!(
+            "can't read '{}': jsonatr is built without the 'files' feature",
+            path
+        )))
+    }
+}
+
+// Serves files from memory, e.g. from assets embedded into the binary
+#[derive(Debug, Clone, Default)]
+pub struct MemoryResolver {
+    files: HashMap<String, Vec<u8>>,
+}
+
+impl MemoryResolver {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn with_file(mut self, path: &str, content: impl Into<Vec<u8>>) -> Self {
+        self.insert(path, content);
+        self
+    }
+
+    pub fn insert(&mut self, path: &str, content: impl Into<Vec<u8>>) {
+        self.files.insert(normalize(path), content.into());
+    }
+}
+
+impl Resolver for MemoryResolver {
+    fn read(&self, path: &str) -> Result<Vec<u8>, JsonatrError> {
+        match self.files.get(&normalize(path)) {
+            Some(content) => Ok(content.clone()),
+            None => Err(JsonatrError::io(
+                path,
+                std::io::Error::new(std::io::ErrorKind::NotFound, "no such file"),
+            )),
+        }
+    }
+}
+
+// removes the "." components from the path, so that e.g. "./a/./b.json" and "a/b.json" are the same file
+fn normalize(path: &str) -> String {
+    path.split('/')
+        .filter(|c| *c != ".")
+        .collect::<Vec<_>>()
+        .join("/")
+}
@@ -1,75 +1,881 @@
+use crate::backend::ValueBackend;
+use crate::error::*;
 use crate::helpers::*;
+use crate::observer::EvaluationObserver;
+use crate::output::*;
+use crate::path::{JsonPathEngine, PathEngine};
+use crate::resolver::{FileResolver, Resolver};
+#[cfg(feature = "regex")]
 use regex::Regex;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use simple_error::*;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+#[cfg(feature = "commands")]
 use std::io::{Read, Write};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+#[cfg(feature = "commands")]
 use std::process::{Command, Stdio};
+use std::sync::{Arc, Mutex};
+#[cfg(feature = "commands")]
+use wait_timeout::ChildExt;
 
-#[derive(Debug, Deserialize, PartialEq, Clone)]
-enum InputKind {
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Debug, Deserialize, Serialize, PartialEq, Clone)]
+pub enum InputKind {
     INLINE,  // inline JSON
     FILE,    // external JSON file
     COMMAND, // external command; its output should either be a valid JSON, or otherwise is converted to a JSON string
+    HTTP, // HTTP(S) request; the response should either be a valid JSON, or otherwise is converted to a JSON string
+    TOML, // external TOML file
+    CSV,  // external CSV file with a header row; converted into an array of objects
+    ENV,  // environment variables: all of them, a single one, or a named subset
+    GLOB, // glob pattern over files; converted into an array of {path, content} objects
+    CBOR, // external CBOR file
+    MSGPACK, // external MessagePack file
+    STDIN, // JSON document(s) read from STDIN; several documents can be separated by whitespace, JSON-seq record separators, or a custom separator
+    GIT,   // file at a git revision, given as {"repo", "rev", "path"}
+    ARCHIVE, // file inside a .zip, .tar, or .tar.gz archive, given as {"archive", "path"}
+    LITERAL, // JSON data taken as is; unlike INLINE, the strings in it are never evaluated as expressions
 }
 
-#[derive(Debug, Deserialize, Clone, PartialEq)]
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
 pub struct Input {
     name: String,
     kind: InputKind,
-    #[serde(rename = "let")]
+    #[serde(rename = "let", skip_serializing_if = "Option::is_none")]
     lets: Option<Value>,
+    #[serde(default, skip_serializing_if = "Value::is_null")]
     source: Value,
-    #[serde(default = "Input::pass_stdin")]
+    #[serde(
+        default = "Input::pass_stdin",
+        skip_serializing_if = "Input::passes_stdin"
+    )]
     stdin: bool,
-    #[serde(default)]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
     args: Vec<String>,
+    // COMMAND options: environment variables (values may contain Jsonatr expressions) and working directory
+    #[serde(skip_serializing_if = "Option::is_none")]
+    env: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cwd: Option<String>,
+    // COMMAND output capture mode: "stdout" (default) fails on non-zero exit, and evaluates to the command output;
+    // "full" evaluates to {stdout, stderr, code} regardless of the exit status;
+    // "lines" evaluates to the array of output lines, each parsed as JSON when possible
+    #[serde(skip_serializing_if = "Option::is_none")]
+    capture: Option<String>,
+    // COMMAND timeout: seconds as a number, or a string with a unit, e.g. "500ms", "10s", "2m"
+    #[serde(skip_serializing_if = "Option::is_none")]
+    timeout: Option<Value>,
+    // COMMAND option for the "lines" capture mode: the maximal number of lines to collect
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_count: Option<u64>,
+    // HTTP request options; all of them may contain Jsonatr expressions, evaluated against the root
+    #[serde(skip_serializing_if = "Option::is_none")]
+    method: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    headers: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    query: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    auth: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    body: Option<Value>,
+    // CSV parsing options: a single-character field delimiter, and an object mapping column names
+    // to their types ("string", "number", "boolean", or "json")
+    #[serde(skip_serializing_if = "Option::is_none")]
+    delimiter: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    types: Option<Value>,
+    // STDIN option: a separator between documents, instead of the default whitespace/JSON-seq framing
+    #[serde(skip_serializing_if = "Option::is_none")]
+    separator: Option<String>,
+    // caching policy: false (default) re-evaluates the input on every reference, true evaluates it only once,
+    // and "per-root" evaluates it once for every distinct root value; the results are kept for one transformation
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cache: Option<Value>,
 }
 
 impl Input {
+    // creates an input of the given kind; the other input options can be set with the with_* methods
+    pub fn new(name: &str, kind: InputKind, source: Value) -> Input {
+        Input {
+            name: name.to_string(),
+            kind,
+            lets: None,
+            source,
+            stdin: Input::pass_stdin(),
+            args: vec![],
+            env: None,
+            cwd: None,
+            capture: None,
+            timeout: None,
+            max_count: None,
+            method: None,
+            headers: None,
+            query: None,
+            auth: None,
+            body: None,
+            delimiter: None,
+            types: None,
+            separator: None,
+            cache: None,
+        }
+    }
+
+    pub fn inline(name: &str, value: Value) -> Input {
+        Input::new(name, InputKind::INLINE, value)
+    }
+
+    pub fn literal(name: &str, value: Value) -> Input {
+        Input::new(name, InputKind::LITERAL, value)
+    }
+
+    pub fn file(name: &str, path: &str) -> Input {
+        Input::new(name, InputKind::FILE, Value::from(path))
+    }
+
+    pub fn command(name: &str, command: &str) -> Input {
+        Input::new(name, InputKind::COMMAND, Value::from(command))
+    }
+
+    pub fn http(name: &str, url: &str) -> Input {
+        Input::new(name, InputKind::HTTP, Value::from(url))
+    }
+
+    pub fn env(name: &str, variables: Value) -> Input {
+        Input::new(name, InputKind::ENV, variables)
+    }
+
+    // the let clause: an object of local definitions, evaluated before the input
+    pub fn with_lets(mut self, lets: Value) -> Input {
+        self.lets = Some(lets);
+        self
+    }
+
+    pub fn with_args(mut self, args: Vec<String>) -> Input {
+        self.args = args;
+        self
+    }
+
+    // whether the root value is passed to a COMMAND input on STDIN
+    pub fn with_stdin(mut self, stdin: bool) -> Input {
+        self.stdin = stdin;
+        self
+    }
+
+    // the caching policy: true, false, or "per-root"
+    pub fn with_cache(mut self, cache: Value) -> Input {
+        self.cache = Some(cache);
+        self
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn kind(&self) -> &InputKind {
+        &self.kind
+    }
+
+    pub fn source(&self) -> &Value {
+        &self.source
+    }
+
+    pub fn lets(&self) -> Option<&Value> {
+        self.lets.as_ref()
+    }
+
     pub fn pass_stdin() -> bool {
         true
     }
+
+    fn passes_stdin(stdin: &bool) -> bool {
+        *stdin
+    }
+
+    // returns the cache key for the result of evaluating the input against the root,
+    // or None if the result should not be cached
+    fn cache_key(&self, root: &Value) -> Option<String> {
+        match self.cache.as_ref()? {
+            Value::Bool(true) => Some(String::new()),
+            Value::String(policy) if policy == "per-root" => Some(root.to_string()),
+            _ => None,
+        }
+    }
+}
+
+// A parsed Jsonatr expression, which is of the form
+// $<input>.<jsonpath>  [| <transform> [(arg,...)]]*
+//   <input> is an identifier, referring to an some of the inputs
+//   $.<jsonpath> is a JsonPath expression, interpreted by the jsonpath_lib
+//   [| <transform> [(arg,...)]]* is a pipe-separated sequence of transforms,
+// each transform being an identifier with optional arguments
+#[derive(Debug, Clone, PartialEq)]
+pub struct Expr {
+    // the name of the input; empty for the value the expression is applied to, e.g. the transformation input
+    pub input: String,
+    // the JSONPath applied to the input, without the leading "$"; empty to take the input as a whole
+    pub jpath: String,
+    // the names of builtins or inputs applied one after another, each with its unevaluated arguments
+    pub transforms: Vec<(String, Vec<String>)>,
+}
+
+impl Expr {
+    // returns None if the text is not an expression, i.e. doesn't start with "$"
+    pub fn parse(text: &str) -> Option<Expr> {
+        parse_expr(text)
+    }
+}
+
+// An expression parsed and checked once by Transformer::compile, to be evaluated many times
+#[derive(Debug, Clone)]
+pub struct CompiledExpr {
+    text: String,
+    expr: Arc<Expr>,
+}
+
+impl CompiledExpr {
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    pub fn expr(&self) -> &Expr {
+        &self.expr
+    }
+}
+
+fn parse_expr(text: &str) -> Option<Expr> {
+    let rest = text.strip_prefix('$')?; // parsing fails if text doesn't contain input
+    let input_len = rest.len() - rest.trim_start_matches(is_word_char).len();
+    let start = 1 + input_len;
+    let mut end = text.len();
+    let mut transforms: Vec<(String, Vec<String>)> = Vec::new();
+    while let Some((transform_start, name, args)) = parse_last_transform(&text[start..end]) {
+        end = start + transform_start;
+        transforms.insert(0, (name, split_args(args)));
+    }
+    Some(Expr {
+        input: rest[..input_len].to_string(),
+        jpath: text[start..end].to_string(),
+        transforms,
+    })
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_'
+}
+
+fn is_blank(c: char) -> bool {
+    c == ' ' || c == '\t'
+}
+
+// finds the last transform "| <name> [(args)]" of the text; returns the position where it starts
+// (including the preceding blanks), its name, and its arguments.
+// Since the arguments may contain "|", it is the leftmost such transform that extends to the end of the text
+fn parse_last_transform(text: &str) -> Option<(usize, String, &str)> {
+    for (pos, c) in text.char_indices() {
+        if c != '|' {
+            continue;
+        }
+        if let Some((name, args)) = parse_transform(&text[pos + 1..]) {
+            let start = text[..pos].trim_end_matches(is_blank).len();
+            return Some((start, name, args));
+        }
+    }
+    None
+}
+
+// parses the text following "|" as the transform name with optional arguments, up to the end of the text;
+// the arguments may contain quoted strings and one level of nested parentheses
+fn parse_transform(text: &str) -> Option<(String, &str)> {
+    let text = text.trim_start_matches(is_blank);
+    let name_len = text.len() - text.trim_start_matches(is_word_char).len();
+    if name_len == 0 {
+        return None;
+    }
+    let name = text[..name_len].to_string();
+    let rest = text[name_len..].trim_start_matches(is_blank);
+    if rest.is_empty() {
+        return Some((name, ""));
+    }
+    let args = rest.strip_prefix('(')?;
+    let mut chars = args.char_indices();
+    while let Some((i, c)) = chars.next() {
+        let closing = match c {
+            '\'' => '\'',
+            '"' => '"',
+            '(' => ')',
+            ')' => {
+                if !args[i + 1..].trim_start_matches(is_blank).is_empty() {
+                    return None;
+                }
+                return Some((name, args[..i].trim_matches(is_blank)));
+            }
+            _ => continue,
+        };
+        // skip to the closing quote or parenthesis
+        let mut escaped = false;
+        loop {
+            let (_, c) = chars.next()?;
+            if escaped {
+                escaped = false;
+            } else if c == '\\' && closing == '"' {
+                escaped = true;
+            } else if c == closing {
+                break;
+            } else if closing == ')' && c == '(' {
+                return None;
+            }
+        }
+    }
+    None
+}
+
+// splits the argument list of a transform at top-level commas,
+// i.e. those not enclosed in quotes or brackets
+fn split_args(text: &str) -> Vec<String> {
+    let mut args = Vec::new();
+    if text.trim().is_empty() {
+        return args;
+    }
+    let mut current = String::new();
+    let mut quote: Option<char> = None;
+    let mut escaped = false;
+    let mut depth = 0;
+    for c in text.chars() {
+        if let Some(q) = quote {
+            if escaped {
+                escaped = false;
+            } else if c == '\\' && q == '"' {
+                escaped = true;
+            } else if c == q {
+                quote = None;
+            }
+        } else {
+            match c {
+                '\'' | '"' => quote = Some(c),
+                '(' | '[' | '{' => depth += 1,
+                ')' | ']' | '}' => depth -= 1,
+                ',' if depth == 0 => {
+                    args.push(current.trim().to_string());
+                    current.clear();
+                    continue;
+                }
+                _ => (),
+            }
+        }
+        current.push(c);
+    }
+    args.push(current.trim().to_string());
+    args
+}
+
+// renders a value for embedding into a string: strings are taken as is, other values as compact JSON
+fn display_value(v: &Value) -> String {
+    match v {
+        Value::String(s) => s.clone(),
+        _ => v.to_string(),
+    }
+}
+
+// applies the arithmetic operator to two numbers; integer arithmetic is preserved
+// as long as the result is representable (and, for division, exact), otherwise floats are used
+fn arithmetic(op: char, a: &Value, b: &Value) -> Option<Value> {
+    if let (Some(x), Some(y)) = (a.as_i64(), b.as_i64()) {
+        let res = match op {
+            '+' => x.checked_add(y),
+            '-' => x.checked_sub(y),
+            '*' => x.checked_mul(y),
+            // dividing i64::MIN by -1 overflows, as does the remainder, which is always 0 though
+            '/' if y == -1 => x.checked_neg(),
+            '/' if x.checked_rem(y) == Some(0) => x.checked_div(y),
+            '%' if y == -1 => Some(0),
+            '%' => x.checked_rem(y),
+            _ => None,
+        };
+        if let Some(res) = res {
+            return Some(Value::from(res));
+        }
+    }
+    let (x, y) = (a.as_f64()?, b.as_f64()?);
+    let res = match op {
+        '+' => x + y,
+        '-' => x - y,
+        '*' => x * y,
+        '/' if y != 0f64 => x / y,
+        '%' if y != 0f64 => x % y,
+        _ => return None,
+    };
+    serde_json::Number::from_f64(res).map(Value::Number)
+}
+
+// compares two numbers or two strings; values of other or mixed types are incomparable
+fn compare_values(a: &Value, b: &Value) -> Option<std::cmp::Ordering> {
+    match (a, b) {
+        (Value::String(x), Value::String(y)) => Some(x.cmp(y)),
+        (Value::Number(_), Value::Number(_)) => match (a.as_i64(), b.as_i64()) {
+            (Some(x), Some(y)) => Some(x.cmp(&y)),
+            _ => a.as_f64()?.partial_cmp(&b.as_f64()?),
+        },
+        _ => None,
+    }
+}
+
+// converts a float into a JSON number, preferring integers for whole values
+fn float_to_value(x: f64) -> Option<Value> {
+    if x.fract() == 0f64 && x.abs() < i64::MAX as f64 {
+        Some(Value::from(x as i64))
+    } else {
+        serde_json::Number::from_f64(x).map(Value::Number)
+    }
+}
+
+// returns the name of the JSON type of the value
+fn type_name(v: &Value) -> &'static str {
+    match v {
+        Value::Null => "null",
+        Value::Bool(_) => "boolean",
+        Value::Number(_) => "number",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+    }
+}
+
+// checks the value for non-emptiness/non-zeroness
+fn is_truthy(v: &Value) -> Option<bool> {
+    let res = match v {
+        Value::Null => false,
+        Value::Bool(x) => *x,
+        Value::Number(x) => {
+            if let Some(n) = x.as_f64() {
+                n != 0f64
+            } else if let Some(n) = x.as_i64() {
+                n != 0i64
+            } else if let Some(n) = x.as_u64() {
+                n != 0u64
+            } else {
+                return None;
+            }
+        }
+        Value::Array(x) => !x.is_empty(),
+        Value::String(x) => !x.is_empty(),
+        Value::Object(x) => !x.is_empty(),
+    };
+    Some(res)
+}
+
+// interprets a string as its UTF-8 bytes, or an array of numbers as raw bytes
+fn value_to_bytes(v: &Value) -> Option<Vec<u8>> {
+    match v {
+        Value::String(s) => Some(s.as_bytes().to_vec()),
+        Value::Array(arr) => arr
+            .iter()
+            .map(|x| x.as_u64().filter(|b| *b <= 255).map(|b| b as u8))
+            .collect(),
+        _ => None,
+    }
+}
+
+// represents decoded bytes as a string if they are valid UTF-8, and as an array of numbers otherwise
+fn bytes_to_value(bytes: Vec<u8>) -> Value {
+    match String::from_utf8(bytes) {
+        Ok(s) => Value::String(s),
+        Err(e) => Value::Array(e.into_bytes().into_iter().map(Value::from).collect()),
+    }
+}
+
+// the error message for the input of a kind excluded from the build
+#[cfg(not(all(feature = "commands", feature = "files", feature = "http")))]
+fn unsupported(input: &Input, feature: &str) -> String {
+    format!(
+        "input '{}' of kind {:?} is not supported: jsonatr is built without the '{}' feature",
+        input.name, input.kind, feature
+    )
+}
+
+// reads everything from the (optional) pipe in a separate thread
+#[cfg(feature = "commands")]
+fn read_pipe<R: Read + Send + 'static>(
+    pipe: Option<R>,
+) -> std::thread::JoinHandle<std::io::Result<Vec<u8>>> {
+    std::thread::spawn(move || {
+        let mut buffer = Vec::new();
+        if let Some(mut pipe) = pipe {
+            pipe.read_to_end(&mut buffer)?;
+        }
+        Ok(buffer)
+    })
+}
+
+// runs the transformation, which waits for COMMAND and HTTP inputs, on the blocking thread pool of tokio,
+// so that the current task is suspended meanwhile, and the other tasks keep running on either runtime flavor;
+// outside of tokio there is no pool to run it on, so the transformation simply blocks
+#[cfg(feature = "async")]
+async fn run_blocking<T: Send + 'static>(
+    transformation: impl FnOnce() -> Result<T, JsonatrError> + Send + 'static,
+) -> Result<T, JsonatrError> {
+    if tokio::runtime::Handle::try_current().is_err() {
+        return transformation();
+    }
+    match tokio::task::spawn_blocking(transformation).await {
+        Ok(result) => result,
+        Err(e) if e.is_panic() => std::panic::resume_unwind(e.into_panic()),
+        Err(_) => Err(JsonatrError::Cancelled),
+    }
+}
+
+// calls the visitor for every string in the value, as a potential expression
+fn visit_value_exprs(v: &Value, location: &str, visit: &mut dyn FnMut(&str, Option<&str>)) {
+    match v {
+        Value::String(text) => visit(location, Some(text)),
+        Value::Array(arr) => {
+            for x in arr {
+                visit_value_exprs(x, location, visit);
+            }
+        }
+        Value::Object(obj) => {
+            for x in obj.values() {
+                visit_value_exprs(x, location, visit);
+            }
+        }
+        _ => (),
+    }
+}
+
+// calls the visitor for every {$...} placeholder in the source of a non-INLINE input
+fn visit_placeholder_exprs(
+    source: &Value,
+    location: &str,
+    visit: &mut dyn FnMut(&str, Option<&str>),
+) {
+    match source {
+        Value::String(template) => {
+            let mut rest = template.as_str();
+            while let Some(start) = rest.find("{$") {
+                let end = match find_closing_brace(&rest[start..]) {
+                    Some(end) => end,
+                    None => return visit(location, None),
+                };
+                visit(location, Some(&rest[start + 1..start + end]));
+                rest = &rest[start + end + 1..];
+            }
+        }
+        Value::Object(obj) => {
+            for v in obj.values() {
+                visit_placeholder_exprs(v, location, visit);
+            }
+        }
+        _ => (),
+    }
+}
+
+// Restricts what a spec from an untrusted source can do: running commands (COMMAND and GIT inputs,
+// and output pipes) is always rejected, reading environment variables unless allowed, while reading
+// files and making HTTP requests only on demand
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Sandbox {
+    // reject using other specs and the inputs reading files: FILE, TOML, CSV, CBOR, MSGPACK, GLOB and ARCHIVE
+    pub deny_files: bool,
+    pub deny_http: bool,
+    // reject ENV inputs, which could expose secrets of the environment; on by default
+    pub deny_env: bool,
+}
+
+impl Default for Sandbox {
+    fn default() -> Self {
+        Sandbox {
+            deny_files: false,
+            deny_http: false,
+            deny_env: true,
+        }
+    }
+}
+
+impl Sandbox {
+    fn check_input(&self, input: &Input) -> Result<(), JsonatrError> {
+        let denied = match input.kind {
+            InputKind::COMMAND | InputKind::GIT => true,
+            InputKind::FILE
+            | InputKind::TOML
+            | InputKind::CSV
+            | InputKind::CBOR
+            | InputKind::MSGPACK
+            | InputKind::GLOB
+            | InputKind::ARCHIVE => self.deny_files,
+            InputKind::HTTP => self.deny_http,
+            InputKind::ENV => self.deny_env,
+            InputKind::INLINE | InputKind::LITERAL | InputKind::STDIN => false,
+        };
+        if denied {
+            bail!(
+                "input '{}' of kind {:?} is not allowed in the sandbox",
+                input.name,
+                input.kind
+            )
+        }
+        Ok(())
+    }
+
+    fn check_format(&self, format: &OutputFormat) -> Result<(), JsonatrError> {
+        if let Some(pipe) = &format.pipe {
+            bail!("output pipe '{}' is not allowed in the sandbox", pipe)
+        }
+        Ok(())
+    }
+}
+
+// Limits on the resources used by a single transformation; exceeding any of them aborts it with an error,
+// also outside of strict mode. There are no limits by default
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Limits {
+    // the maximal nesting depth of input evaluations, e.g. of an input referencing itself
+    pub max_depth: Option<usize>,
+    // the maximal number of evaluated expressions
+    pub max_expressions: Option<usize>,
+    // the maximal total size of the produced outputs, in bytes of compact JSON
+    pub max_output_size: Option<usize>,
+    // the maximal running time of any COMMAND input; shorter timeouts of inputs are kept
+    pub command_timeout: Option<std::time::Duration>,
+}
+
+// Allows to abort running transformations, also from other threads; it is checked
+// before evaluating each expression and before running each command
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken(std::sync::Arc<std::sync::atomic::AtomicBool>);
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, std::sync::atomic::Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(std::sync::atomic::Ordering::SeqCst)
+    }
 }
 
-struct Expr {
-    input: String,
-    jpath: String,
-    transforms: Vec<(String, Vec<String>)>,
+// What to put in place of an expression that failed to evaluate, as decided by the error handler
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ErrorAction {
+    // keep the expression text unchanged; this is what happens without an error handler
+    #[default]
+    Keep,
+    // substitute null
+    Null,
+    // abort the transformation with the error
+    Abort,
 }
 
-lazy_static! {
-    static ref INPUT_RE: Regex = Regex::new(r"^\$([[:word:]]*)").unwrap();
-    static ref TRANSFORM_RE: Regex =
-        Regex::new(r"[ \t]*\|[ \t]*([[:word:]]+)[ \t]*(?:\([ \t]*([^)]*?)[ \t]*\))?[ \t]*$")
-            .unwrap();
-    static ref SEP_RE: Regex = Regex::new(r"[ \t]*,[ \t]*").unwrap();
+// Called with the text of the failed expression, and the first error reported while evaluating it
+pub type ErrorHandler = Arc<dyn Fn(&str, &str) -> ErrorAction + Send + Sync>;
+
+// the builtins, whose arguments (all, or only those not starting with $) are input names
+const INPUT_ARG_BUILTINS: &[&str] = &["map", "ifelse", "find"];
+
+type Locals = Vec<HashMap<String, Value>>;
+type BuiltinFn = Arc<dyn Fn(&mut Context, Value, &[String]) -> Option<Value> + Send + Sync>;
+type Builtins = HashMap<String, Builtin>;
+type InputCache = HashMap<String, HashMap<String, Value>>;
+
+// the minimal and maximal (if any) number of arguments of a builtin
+pub type Arity = (usize, Option<usize>);
+
+// A registered builtin with its arity, which is checked by validate and before each call;
+// the arity of builtins registered by the library user is unknown, so they check their arguments themselves
+#[derive(Clone)]
+struct Builtin {
+    function: BuiltinFn,
+    arity: Option<Arity>,
 }
 
-type Locals = Vec<std::collections::HashMap<String, Value>>;
-type Builtin = fn(&mut Transformer, Value, &[String]) -> Option<Value>;
-type Builtins = std::collections::HashMap<String, Builtin>;
+impl Builtin {
+    fn accepts(&self, count: usize) -> bool {
+        match self.arity {
+            Some((min, max)) => count >= min && max.is_none_or(|max| count <= max),
+            None => true,
+        }
+    }
+}
 
-#[derive(Deserialize)]
+// The loaded spec; it is Send + Sync, so that one spec can serve concurrent transformations,
+// each keeping its evaluation state in its own Context
+#[derive(Deserialize, Serialize)]
 pub struct Transformer {
-    #[serde(rename = "use")]
+    #[serde(rename = "use", skip_serializing_if = "Option::is_none")]
     uses: Option<Vec<String>>,
 
+    #[serde(skip_serializing_if = "Option::is_none")]
     input: Option<Vec<Input>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     output: Option<Value>,
 
-    #[serde(skip)]
-    inputs: std::collections::HashMap<String, Input>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    outputs: Vec<NamedOutput>,
+
+    #[serde(default, skip_serializing_if = "OutputFormat::is_default")]
+    format: OutputFormat,
 
     #[serde(skip)]
-    locals: Locals,
+    inputs: std::collections::HashMap<String, Input>,
 
     #[serde(skip)]
     builtins: Builtins,
 
     #[serde(skip)]
     path: String,
+
+    // the contents of STDIN, read on the first reference to a STDIN input
+    #[serde(skip)]
+    stdin_text: Mutex<Option<String>>,
+
+    // in strict mode, evaluation errors make the transformation fail,
+    // instead of leaving the failed expression in the output
+    #[serde(skip)]
+    strict: bool,
+
+    #[serde(skip)]
+    observer: Option<Arc<dyn EvaluationObserver>>,
+
+    #[serde(skip, default = "Transformer::default_resolver")]
+    resolver: Arc<dyn Resolver>,
+
+    #[serde(skip, default = "Transformer::default_path_engine")]
+    path_engine: Arc<dyn PathEngine>,
+
+    #[serde(skip)]
+    include_path: Vec<String>,
+
+    #[serde(skip)]
+    error_handler: Option<ErrorHandler>,
+
+    #[serde(skip)]
+    sandbox: Option<Sandbox>,
+
+    #[serde(skip)]
+    limits: Limits,
+
+    #[serde(skip)]
+    cancellation: Option<CancellationToken>,
+
+    // with a seed, the pseudo-random builtins produce the same sequence of values in each transformation
+    #[serde(skip)]
+    seed: Option<u64>,
+}
+
+impl Clone for Transformer {
+    fn clone(&self) -> Self {
+        Transformer {
+            uses: self.uses.clone(),
+            input: self.input.clone(),
+            output: self.output.clone(),
+            outputs: self.outputs.clone(),
+            format: self.format.clone(),
+            inputs: self.inputs.clone(),
+            builtins: self.builtins.clone(),
+            path: self.path.clone(),
+            stdin_text: Mutex::new(self.stdin_text.lock().unwrap().clone()),
+            strict: self.strict,
+            observer: self.observer.clone(),
+            resolver: self.resolver.clone(),
+            path_engine: self.path_engine.clone(),
+            include_path: self.include_path.clone(),
+            error_handler: self.error_handler.clone(),
+            sandbox: self.sandbox,
+            limits: self.limits,
+            cancellation: self.cancellation.clone(),
+            seed: self.seed,
+        }
+    }
+}
+
+// The iterator over the outputs of a streaming transformation, see Transformer::transform_iter
+pub struct TransformIter<'a, I> {
+    spec: &'a Transformer,
+    output: &'a Value,
+    inputs: I,
+    parsed: HashMap<String, Option<Arc<Expr>>>,
+}
+
+impl<I: Iterator<Item = Value>> Iterator for TransformIter<'_, I> {
+    type Item = Result<Value, JsonatrError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let input = self.inputs.next()?;
+        let mut context = self.spec.context();
+        context.parsed = std::mem::take(&mut self.parsed);
+        let result = context.evaluate(self.output, &input);
+        self.parsed = context.parsed;
+        Some(result)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inputs.size_hint()
+    }
+}
+
+// An evaluation failure: the error returned to the callers, and its message with the location, as logged
+struct Failure {
+    error: JsonatrError,
+    message: String,
+    // the message without the location
+    reason: String,
+    location: Option<ErrorLocation>,
+}
+
+impl Failure {
+    // the failure as an EvaluationError, whatever the kind of its error
+    fn evaluation_error(&self) -> JsonatrError {
+        JsonatrError::EvaluationError {
+            message: self.reason.clone(),
+            location: self.location.clone(),
+        }
+    }
+}
+
+// The mutable state of a single transformation, so that a Transformer can be used for many of them at once
+pub struct Context<'a> {
+    spec: &'a Transformer,
+
+    locals: Locals,
+
+    // the results of inputs shared between all outputs, while producing several outputs at once
+    run_cache: Option<InputCache>,
+
+    // the results of inputs with a caching policy, by input name and cache key
+    input_cache: InputCache,
+
+    // the evaluation failures reported during the current output
+    errors: Vec<Failure>,
+
+    // the resources used so far, checked against the limits of the spec
+    depth: usize,
+    expressions: usize,
+    output_size: usize,
+
+    // the reason to abort the transformation, e.g. an exceeded limit or cancellation
+    aborted: Option<JsonatrError>,
+
+    // the JSON pointer components of the output node being evaluated, and its expression
+    pointer: Vec<String>,
+    expression: Option<String>,
+
+    // the builtin being applied, to locate the failures reported by it
+    builtin: Option<String>,
+
+    // the parsed expressions, kept across the inputs of a streaming transformation
+    parsed: HashMap<String, Option<Arc<Expr>>>,
+
+    // the compiled regular expressions of the regex builtins, by pattern
+    #[cfg(feature = "regex")]
+    regexes: HashMap<String, Regex>,
+
+    // the number of values drawn from the seeded pseudo-random sequence so far
+    random_draws: u64,
 }
 
 impl Transformer {
@@ -78,57 +884,231 @@ impl Transformer {
             uses: None,
             input: None,
             output: None,
+            outputs: vec![],
+            format: Default::default(),
             inputs: Default::default(),
-            locals: vec![],
             builtins: Default::default(),
             path: path.to_owned(),
+            stdin_text: Default::default(),
+            strict: false,
+            observer: None,
+            resolver: Transformer::default_resolver(),
+            path_engine: Transformer::default_path_engine(),
+            include_path: vec![],
+            error_handler: None,
+            sandbox: None,
+            limits: Default::default(),
+            cancellation: None,
+            seed: None,
         };
         spec.add_builtins();
         spec
     }
 
-    pub fn new(spec: &str, path: &str) -> Result<Transformer, SimpleError> {
-        let mut spec: Transformer = try_with!(serde_json::from_str(spec), "failed to parse JSON");
-        spec.path = path.to_owned();
-        spec.add_builtins();
-        if let Some(uses) = spec.uses.clone() {
+    pub fn new(spec: &str, path: &str) -> Result<Transformer, JsonatrError> {
+        Transformer::new_with_resolver(spec, path, Transformer::default_resolver())
+    }
+
+    // same as new, but the used specs and the files of inputs are loaded by the given resolver
+    pub fn new_with_resolver(
+        spec: &str,
+        path: &str,
+        resolver: Arc<dyn Resolver>,
+    ) -> Result<Transformer, JsonatrError> {
+        let mut spec: Transformer =
+            serde_json::from_str(spec).map_err(|e| JsonatrError::parse("JSON", e))?;
+        spec.resolver = resolver;
+        spec.init(path)
+    }
+
+    // same as new, but for the spec that is already parsed
+    pub fn from_value(spec: Value, path: &str) -> Result<Transformer, JsonatrError> {
+        let spec: Transformer =
+            serde_json::from_value(spec).map_err(|e| JsonatrError::parse("JSON", e))?;
+        spec.init(path)
+    }
+
+    // same as new, but the spec is loaded in the sandbox, and rejected if it does anything not allowed there
+    pub fn new_sandboxed(
+        spec: &str,
+        path: &str,
+        sandbox: Sandbox,
+    ) -> Result<Transformer, JsonatrError> {
+        let mut spec: Transformer =
+            serde_json::from_str(spec).map_err(|e| JsonatrError::parse("JSON", e))?;
+        spec.sandbox = Some(sandbox);
+        spec.init(path)
+    }
+
+    fn default_resolver() -> Arc<dyn Resolver> {
+        Arc::new(FileResolver)
+    }
+
+    fn default_path_engine() -> Arc<dyn PathEngine> {
+        Arc::new(JsonPathEngine)
+    }
+
+    fn init(mut self, path: &str) -> Result<Transformer, JsonatrError> {
+        self.path = path.to_owned();
+        self.add_builtins();
+        for output in std::mem::take(&mut self.outputs) {
+            self.add_named_output(output)?;
+        }
+        if let Some(uses) = self.uses.clone() {
             for path in uses {
-                spec.add_use(path)?;
+                self.add_use(path)?;
             }
         }
-        if let Some(inputs) = spec.input.clone() {
+        if let Some(inputs) = self.input.clone() {
             for input in inputs {
-                spec.add_input(input)?;
+                self.insert_input(input)?;
             }
         }
-        Ok(spec)
+        self.check_sandbox()?;
+        Ok(self)
     }
 
-    pub fn merge(&mut self, other: &Transformer) -> Result<(), SimpleError> {
-        if other.output.is_some() {
-            self.add_output(other.output.as_ref().unwrap().clone())?
+    // restricts the spec to the sandbox; fails if it already contains anything not allowed there
+    pub fn set_sandbox(&mut self, sandbox: Sandbox) -> Result<(), JsonatrError> {
+        self.sandbox = Some(sandbox);
+        self.check_sandbox()
+    }
+
+    fn check_sandbox(&self) -> Result<(), JsonatrError> {
+        let sandbox = match &self.sandbox {
+            Some(sandbox) => sandbox,
+            None => return Ok(()),
+        };
+        if sandbox.deny_files && self.uses.as_ref().is_some_and(|uses| !uses.is_empty()) {
+            bail!("using other specs is not allowed in the sandbox")
+        }
+        for input in self.inputs() {
+            sandbox.check_input(input)?;
+        }
+        sandbox.check_format(&self.format)?;
+        for output in &self.outputs {
+            if let Some(format) = &output.format {
+                sandbox.check_format(format)?;
+            }
+        }
+        Ok(())
+    }
+
+    // fails if the output format is not allowed in the sandbox, e.g. when it was set after loading the spec
+    fn check_output_format(&self, format: &OutputFormat) -> Result<(), JsonatrError> {
+        match &self.sandbox {
+            Some(sandbox) => sandbox.check_format(format),
+            None => Ok(()),
+        }
+    }
+
+    pub fn merge(&mut self, other: &Transformer) -> Result<(), JsonatrError> {
+        if let Some(output) = &other.output {
+            self.add_output(output.clone())?
+        }
+        for output in &other.outputs {
+            self.add_named_output(output.clone())?;
         }
         for input in other.inputs.values() {
-            self.add_input(input.clone())?;
+            self.insert_input(input.clone())?;
         }
         Ok(())
     }
 
-    pub fn add_use(&mut self, path: String) -> Result<(), SimpleError> {
+    pub fn add_use(&mut self, path: String) -> Result<(), JsonatrError> {
+        if self.sandbox.is_some_and(|sandbox| sandbox.deny_files) {
+            bail!("using spec '{}' is not allowed in the sandbox", path)
+        }
         // TODO: The below code is ugly, amd includes unwraps; find the better way
+        let path = expand_env(&path)?;
         let mut file_path = PathBuf::from(&self.path);
-        if file_path.is_file() {
+        if self.resolver.is_file(&self.path) {
             file_path = file_path.parent().unwrap().to_path_buf();
         }
-        file_path.push(path);
-        let file_path_str = file_path.to_str().unwrap();
-        let file = read_file(file_path_str)?;
-        let other = Transformer::new(&file, file_path_str)?;
-        self.merge(&other)?;
-        Ok(())
+        file_path.push(&path);
+        let mut file_path_str = file_path.to_str().unwrap().to_owned();
+        if !self.include_path.is_empty() && !self.resolver.is_file(&file_path_str) {
+            if let Some(found) = self.search_include_path(&path) {
+                file_path_str = found;
+            }
+        }
+        let file = self.resolver.read_to_string(&file_path_str)?;
+        self.merge_spec(&file, &file_path_str)
+            .map_err(|e| e.with_origin(&file_path_str))
+    }
+
+    // merges the spec given as a JSON string; the specs it uses are loaded as by add_use
+    pub fn merge_str(&mut self, spec: &str) -> Result<(), JsonatrError> {
+        let path = self.path.clone();
+        self.merge_spec(spec, &path)
+    }
+
+    fn merge_spec(&mut self, spec: &str, path: &str) -> Result<(), JsonatrError> {
+        let mut other: Transformer =
+            serde_json::from_str(spec).map_err(|e| JsonatrError::parse("JSON", e))?;
+        other.resolver = self.resolver.clone();
+        other.include_path = self.include_path.clone();
+        let other = other.init(path)?;
+        self.merge(&other)
+    }
+
+    // registers a custom builtin transform, replacing the standard builtin with the same name, if any;
+    // the function receives the piped value and the argument strings, which can be evaluated with eval_arg
+    pub fn register_builtin<F>(&mut self, name: &str, builtin: F) -> Result<(), JsonatrError>
+    where
+        F: Fn(&mut Context, Value, &[String]) -> Option<Value> + Send + Sync + 'static,
+    {
+        if self.inputs.contains_key(name) {
+            bail!(
+                "can't register builtin '{}' because of the input with the same name",
+                name
+            )
+        }
+        let builtin = Builtin {
+            function: Arc::new(builtin),
+            arity: None,
+        };
+        self.builtins.insert(name.to_string(), builtin);
+        Ok(())
     }
 
-    pub fn add_input(&mut self, input: Input) -> Result<(), SimpleError> {
+    fn add_builtin<F>(&mut self, name: &str, arity: Arity, builtin: F)
+    where
+        F: Fn(&mut Context, Value, &[String]) -> Option<Value> + Send + Sync + 'static,
+    {
+        let builtin = Builtin {
+            function: Arc::new(builtin),
+            arity: Some(arity),
+        };
+        self.builtins.insert(name.to_string(), builtin);
+    }
+
+    // the names of the builtins, sorted
+    pub fn builtins(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = self.builtins.keys().map(String::as_str).collect();
+        names.sort();
+        names
+    }
+
+    // the arity of the builtin, unless it is unknown, as for the builtins registered by the library user
+    pub fn builtin_arity(&self, name: &str) -> Option<Arity> {
+        self.builtins.get(name).and_then(|builtin| builtin.arity)
+    }
+
+    pub fn add_input(&mut self, input: Input) -> Result<(), JsonatrError> {
+        self.insert_input(input.clone())?;
+        // the input becomes a part of the spec, as if it was declared in it
+        let declared = self.input.get_or_insert_with(Vec::new);
+        if !declared.contains(&input) {
+            declared.push(input);
+        }
+        Ok(())
+    }
+
+    fn insert_input(&mut self, input: Input) -> Result<(), JsonatrError> {
+        if let Some(sandbox) = &self.sandbox {
+            sandbox.check_input(&input)?;
+        }
         if self.builtins.contains_key(&input.name) {
             bail!(
                 "can't define input '{}' because of the builtin function with the same name",
@@ -148,11 +1128,96 @@ impl Transformer {
                 )
             }
         }
+        match &input.cache {
+            None | Some(Value::Bool(_)) => (),
+            Some(Value::String(policy)) if policy == "per-root" => (),
+            Some(_) => bail!(
+                "wrong 'cache' clause of input '{}': should be true, false, or \"per-root\"",
+                input.name
+            ),
+        }
         self.inputs.insert(input.name.clone(), input);
         Ok(())
     }
 
-    pub fn add_output(&mut self, output: Value) -> Result<(), SimpleError> {
+    pub fn format(&self) -> &OutputFormat {
+        &self.format
+    }
+
+    pub fn set_format(&mut self, format: OutputFormat) {
+        self.format = format;
+    }
+
+    pub fn set_compact(&mut self, compact: bool) {
+        self.format.compact = compact;
+    }
+
+    pub fn set_raw(&mut self, raw: bool) {
+        self.format.raw = raw;
+    }
+
+    pub fn set_strict(&mut self, strict: bool) {
+        self.strict = strict;
+    }
+
+    // the resolver loads the files of inputs in the following transformations; used specs are loaded
+    // when the spec is created, so the resolver for them should be given to new_with_resolver
+    pub fn set_resolver(&mut self, resolver: Arc<dyn Resolver>) {
+        self.resolver = resolver;
+    }
+
+    pub fn resolver(&self) -> &dyn Resolver {
+        self.resolver.as_ref()
+    }
+
+    // the directories searched, in the order of adding, for the used specs and the files of FILE, TOML, CSV,
+    // CBOR and MSGPACK inputs that don't exist at their relative paths; used specs are loaded when they are added,
+    // so the include path should be set before
+    pub fn add_include_path(&mut self, dir: &str) {
+        self.include_path.push(dir.to_owned());
+    }
+
+    fn search_include_path(&self, path: &str) -> Option<String> {
+        if Path::new(path).is_absolute() {
+            return None;
+        }
+        self.include_path
+            .iter()
+            .map(|dir| Path::new(dir).join(path).to_string_lossy().into_owned())
+            .find(|candidate| self.resolver.is_file(candidate))
+    }
+
+    // the path engine selects the parts of inputs addressed by expressions, and by file name templates
+    pub fn set_path_engine(&mut self, engine: Arc<dyn PathEngine>) {
+        self.path_engine = engine;
+    }
+
+    // the error handler decides what becomes of the expressions that fail to evaluate;
+    // strict mode still fails the transformation on any error, unless the handler aborts it first
+    pub fn set_error_handler(&mut self, handler: ErrorHandler) {
+        self.error_handler = Some(handler);
+    }
+
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.limits = limits;
+    }
+
+    // the transformations are aborted with JsonatrError::Cancelled after the token is cancelled
+    pub fn set_cancellation(&mut self, token: CancellationToken) {
+        self.cancellation = Some(token);
+    }
+
+    // seeds the pseudo-random builtins, e.g. uuid() without arguments, making their values reproducible
+    pub fn set_seed(&mut self, seed: Option<u64>) {
+        self.seed = seed;
+    }
+
+    // the observer is notified about every evaluation step of the following transformations
+    pub fn set_observer(&mut self, observer: Arc<dyn EvaluationObserver>) {
+        self.observer = Some(observer);
+    }
+
+    pub fn add_output(&mut self, output: Value) -> Result<(), JsonatrError> {
         if self.output.is_some() {
             bail!("double definition of output")
         }
@@ -160,13 +1225,576 @@ impl Transformer {
         Ok(())
     }
 
+    pub fn add_named_output(&mut self, output: NamedOutput) -> Result<(), JsonatrError> {
+        if self.outputs.iter().any(|o| o.name == output.name) {
+            bail!("double definition of output '{}'", output.name)
+        }
+        if let (Some(sandbox), Some(format)) = (&self.sandbox, &output.format) {
+            sandbox.check_format(format)?;
+        }
+        self.outputs.push(output);
+        Ok(())
+    }
+
+    pub fn output(&self) -> Option<&Value> {
+        self.output.as_ref()
+    }
+
+    pub fn named_outputs(&self) -> &[NamedOutput] {
+        &self.outputs
+    }
+
     fn add_builtins(&mut self) {
-        self.builtins
-            .insert("unwrap".to_string(), Transformer::builtin_unwrap);
-        self.builtins
-            .insert("map".to_string(), Transformer::builtin_map);
-        self.builtins
-            .insert("ifelse".to_string(), Transformer::builtin_ifelse);
+        self.add_builtin("unwrap", (0, Some(0)), |c, v, args| {
+            c.builtin_unwrap(v, args)
+        });
+        self.add_builtin("map", (1, Some(1)), |c, v, args| c.builtin_map(v, args));
+        self.add_builtin("ifelse", (2, Some(2)), |c, v, args| {
+            c.builtin_ifelse(v, args)
+        });
+        #[cfg(feature = "regex")]
+        {
+            self.add_builtin("regex_extract", (1, Some(2)), |c, v, args| {
+                c.builtin_regex_extract(v, args)
+            });
+            self.add_builtin("regex_replace", (2, Some(2)), |c, v, args| {
+                c.builtin_regex_replace(v, args)
+            });
+        }
+        #[cfg(not(feature = "regex"))]
+        for (name, arity) in [
+            ("regex_extract", (1, Some(2))),
+            ("regex_replace", (2, Some(2))),
+        ] {
+            self.add_builtin(name, arity, move |c, _, _| {
+                c.report_error(format!(
+                    "builtin '{}' is not supported: jsonatr is built without the 'regex' feature",
+                    name
+                ));
+                None
+            });
+        }
+        self.add_builtin("format", (1, None), |c, v, args| c.builtin_format(v, args));
+        self.add_builtin("add", (1, Some(1)), |c, v, args| c.builtin_add(v, args));
+        self.add_builtin("sub", (1, Some(1)), |c, v, args| c.builtin_sub(v, args));
+        self.add_builtin("mul", (1, Some(1)), |c, v, args| c.builtin_mul(v, args));
+        self.add_builtin("div", (1, Some(1)), |c, v, args| c.builtin_div(v, args));
+        self.add_builtin("mod", (1, Some(1)), |c, v, args| c.builtin_mod(v, args));
+        self.add_builtin("sum", (0, Some(0)), |c, v, args| c.builtin_sum(v, args));
+        self.add_builtin("avg", (0, Some(0)), |c, v, args| c.builtin_avg(v, args));
+        self.add_builtin("min", (0, Some(0)), |c, v, args| c.builtin_min(v, args));
+        self.add_builtin("max", (0, Some(0)), |c, v, args| c.builtin_max(v, args));
+        self.add_builtin("min_by", (1, Some(1)), |c, v, args| {
+            c.builtin_min_by(v, args)
+        });
+        self.add_builtin("max_by", (1, Some(1)), |c, v, args| {
+            c.builtin_max_by(v, args)
+        });
+        self.add_builtin("round", (0, Some(1)), |c, v, args| c.builtin_round(v, args));
+        self.add_builtin("floor", (0, Some(0)), |c, v, args| c.builtin_floor(v, args));
+        self.add_builtin("ceil", (0, Some(0)), |c, v, args| c.builtin_ceil(v, args));
+        self.add_builtin("to_fixed", (1, Some(1)), |c, v, args| {
+            c.builtin_to_fixed(v, args)
+        });
+        self.add_builtin("type", (0, Some(0)), |c, v, args| c.builtin_type(v, args));
+        self.add_builtin("assert_type", (1, None), |c, v, args| {
+            c.builtin_assert_type(v, args)
+        });
+        self.add_builtin("to_number", (0, Some(0)), |c, v, args| {
+            c.builtin_to_number(v, args)
+        });
+        self.add_builtin("to_string", (0, Some(0)), |c, v, args| {
+            c.builtin_to_string(v, args)
+        });
+        self.add_builtin("to_bool", (0, Some(0)), |c, v, args| {
+            c.builtin_to_bool(v, args)
+        });
+        self.add_builtin("parse_json", (0, Some(0)), |c, v, args| {
+            c.builtin_parse_json(v, args)
+        });
+        self.add_builtin("to_json", (0, Some(0)), |c, v, args| {
+            c.builtin_to_json(v, args)
+        });
+        self.add_builtin("default", (1, Some(1)), |c, v, args| {
+            c.builtin_default(v, args)
+        });
+        self.add_builtin("contains", (1, Some(1)), |c, v, args| {
+            c.builtin_contains(v, args)
+        });
+        self.add_builtin("has", (1, Some(1)), |c, v, args| c.builtin_has(v, args));
+        self.add_builtin("find", (1, Some(1)), |c, v, args| c.builtin_find(v, args));
+        self.add_builtin("index_of", (1, Some(1)), |c, v, args| {
+            c.builtin_index_of(v, args)
+        });
+        self.add_builtin("first", (0, Some(0)), |c, v, args| c.builtin_first(v, args));
+        self.add_builtin("last", (0, Some(0)), |c, v, args| c.builtin_last(v, args));
+        self.add_builtin("nth", (1, Some(1)), |c, v, args| c.builtin_nth(v, args));
+        self.add_builtin("chunk", (1, Some(1)), |c, v, args| c.builtin_chunk(v, args));
+        self.add_builtin("window", (1, Some(1)), |c, v, args| {
+            c.builtin_window(v, args)
+        });
+        self.add_builtin("reverse", (0, Some(0)), |c, v, args| {
+            c.builtin_reverse(v, args)
+        });
+        self.add_builtin("base64_encode", (0, Some(1)), |c, v, args| {
+            c.builtin_base64_encode(v, args)
+        });
+        self.add_builtin("base64_decode", (0, Some(1)), |c, v, args| {
+            c.builtin_base64_decode(v, args)
+        });
+        self.add_builtin("hex_encode", (0, Some(1)), |c, v, args| {
+            c.builtin_hex_encode(v, args)
+        });
+        self.add_builtin("hex_decode", (0, Some(0)), |c, v, args| {
+            c.builtin_hex_decode(v, args)
+        });
+        self.add_builtin("sha256", (0, Some(1)), |c, v, args| {
+            c.builtin_sha256(v, args)
+        });
+        self.add_builtin("sha512", (0, Some(1)), |c, v, args| {
+            c.builtin_sha512(v, args)
+        });
+        self.add_builtin("ripemd160", (0, Some(1)), |c, v, args| {
+            c.builtin_ripemd160(v, args)
+        });
+        self.add_builtin("uuid", (0, Some(1)), |c, v, args| c.builtin_uuid(v, args));
+        self.add_builtin("now", (0, Some(2)), |c, v, args| c.builtin_now(v, args));
+    }
+
+    // the declared inputs, sorted by name
+    pub fn inputs(&self) -> Vec<&Input> {
+        let mut inputs: Vec<&Input> = self.inputs.values().collect();
+        inputs.sort_by(|a, b| a.name.cmp(&b.name));
+        inputs
+    }
+
+    pub fn input(&self, name: &str) -> Option<&Input> {
+        self.inputs.get(name)
+    }
+
+    // the names of the inputs referenced by the expression, including those in builtin arguments
+    pub fn references(&self, expr: &str) -> BTreeSet<String> {
+        let mut references = BTreeSet::new();
+        self.collect_references(expr, &mut references);
+        references
+    }
+
+    // the inputs referenced from each part of the spec: "output", "outputs.<name>" for named outputs,
+    // and "input.<name>" for all expressions of an input (let clause, source, and request options)
+    pub fn dependencies(&self) -> BTreeMap<String, BTreeSet<String>> {
+        let mut dependencies: BTreeMap<String, BTreeSet<String>> = self
+            .inputs
+            .keys()
+            .map(|name| (format!("input.{}", name), BTreeSet::new()))
+            .collect();
+        self.visit_exprs(&mut |location, text| {
+            let key = match location.strip_prefix("input.") {
+                Some(rest) => {
+                    let name = rest.split('.').next().unwrap_or_default();
+                    format!("input.{}", name)
+                }
+                None => location.to_string(),
+            };
+            let references = dependencies.entry(key).or_default();
+            if let Some(text) = text {
+                self.collect_references(text, references);
+            }
+        });
+        dependencies
+    }
+
+    fn collect_references(&self, text: &str, references: &mut BTreeSet<String>) {
+        let expr = match parse_expr(text) {
+            Some(expr) => expr,
+            None => return,
+        };
+        let mut add = |name: &str| {
+            if self.inputs.contains_key(name) {
+                references.insert(name.to_string());
+            }
+        };
+        add(&expr.input);
+        let mut nested = Vec::new();
+        for (name, args) in &expr.transforms {
+            if !self.builtins.contains_key(name) {
+                add(name);
+                continue;
+            }
+            for arg in args {
+                if arg.starts_with('$') {
+                    nested.push(arg.clone());
+                } else if INPUT_ARG_BUILTINS.contains(&name.as_str()) {
+                    add(arg);
+                }
+            }
+        }
+        for arg in nested {
+            self.collect_references(&arg, references);
+        }
+    }
+
+    // calls the visitor with the location and the text of every expression in the spec;
+    // the text is None for a malformed {$...} placeholder
+    fn visit_exprs(&self, visit: &mut dyn FnMut(&str, Option<&str>)) {
+        if let Some(output) = &self.output {
+            visit_value_exprs(output, "output", visit);
+        }
+        for output in &self.outputs {
+            let location = format!("outputs.{}", output.name);
+            visit_value_exprs(&output.output, &location, visit);
+        }
+        for input in self.inputs() {
+            let location = format!("input.{}", input.name);
+            if let Some(Value::Object(lets)) = &input.lets {
+                for (k, v) in lets {
+                    visit_value_exprs(v, &format!("{}.let.{}", location, k), visit);
+                }
+            }
+            let source_location = format!("{}.source", location);
+            match input.kind {
+                InputKind::INLINE => visit_value_exprs(&input.source, &source_location, visit),
+                InputKind::LITERAL => (),
+                _ => visit_placeholder_exprs(&input.source, &source_location, visit),
+            }
+            let options = [
+                ("env", &input.env),
+                ("headers", &input.headers),
+                ("query", &input.query),
+                ("auth", &input.auth),
+                ("body", &input.body),
+            ];
+            for (option, value) in options.iter() {
+                if let Some(value) = value {
+                    visit_value_exprs(value, &format!("{}.{}", location, option), visit);
+                }
+            }
+        }
+    }
+
+    // checks the spec without evaluating any inputs: parses all expressions, and verifies that
+    // the referenced inputs and builtins exist, builtins get a valid number of arguments,
+    // and let clauses are objects; returns the list of found problems
+    pub fn validate(&self) -> Vec<Diagnostic> {
+        let mut diagnostics = Vec::new();
+        let no_locals = HashSet::new();
+        // locals are visible in all inputs evaluated within their scope,
+        // so inside inputs any local defined in the spec may be referenced
+        let mut locals = HashSet::new();
+        for input in self.inputs() {
+            match &input.lets {
+                None => (),
+                Some(Value::Object(lets)) => locals.extend(lets.keys().cloned()),
+                Some(_) => diagnostics.push(Diagnostic {
+                    location: format!("input.{}.let", input.name),
+                    message: "let clause should be an object".to_string(),
+                }),
+            }
+        }
+        self.visit_exprs(&mut |location, text| {
+            let locals = if location.starts_with("input.") {
+                &locals
+            } else {
+                &no_locals
+            };
+            match text {
+                Some(text) => self.validate_expr(text, location, locals, &mut diagnostics),
+                None => diagnostics.push(Diagnostic {
+                    location: location.to_string(),
+                    message: "unterminated placeholder".to_string(),
+                }),
+            }
+        });
+        diagnostics
+    }
+
+    fn validate_expr(
+        &self,
+        text: &str,
+        location: &str,
+        locals: &HashSet<String>,
+        diagnostics: &mut Vec<Diagnostic>,
+    ) {
+        let expr = match parse_expr(text) {
+            Some(expr) => expr,
+            None => return,
+        };
+        let mut report = |message: String| {
+            diagnostics.push(Diagnostic {
+                location: location.to_string(),
+                message: format!("{} in '{}'", message, text),
+            })
+        };
+        let is_input = |name: &str| self.inputs.contains_key(name) || locals.contains(name);
+        if !expr.input.is_empty() && !is_input(&expr.input) {
+            report(format!("unknown input '{}'", expr.input));
+        }
+        if !expr.jpath.is_empty() {
+            if let Err(e) = self.path_engine.check(&expr.jpath) {
+                report(e.to_string());
+            }
+        }
+        let mut nested = Vec::new();
+        for (name, args) in &expr.transforms {
+            let builtin = match self.builtins.get(name) {
+                Some(builtin) => builtin,
+                None => {
+                    if !is_input(name) {
+                        report(format!("unknown builtin or input '{}'", name));
+                    }
+                    continue;
+                }
+            };
+            if builtin.arity.is_none() {
+                continue;
+            }
+            if !builtin.accepts(args.len()) {
+                report(format!(
+                    "wrong number of arguments ({}) of builtin '{}'",
+                    args.len(),
+                    name
+                ));
+            }
+            for arg in args {
+                if arg.starts_with('$') {
+                    nested.push(arg.clone());
+                } else if INPUT_ARG_BUILTINS.contains(&name.as_str()) && !is_input(arg) {
+                    report(format!(
+                        "unknown input '{}' in arguments of '{}'",
+                        arg, name
+                    ));
+                }
+            }
+        }
+        for arg in nested {
+            self.validate_expr(&arg, location, locals, diagnostics);
+        }
+    }
+
+    // starts the evaluation context of a single transformation
+    fn context(&self) -> Context<'_> {
+        Context {
+            spec: self,
+            locals: vec![],
+            run_cache: None,
+            input_cache: Default::default(),
+            errors: vec![],
+            depth: 0,
+            expressions: 0,
+            output_size: 0,
+            aborted: None,
+            pointer: vec![],
+            expression: None,
+            builtin: None,
+            parsed: HashMap::new(),
+            #[cfg(feature = "regex")]
+            regexes: HashMap::new(),
+            random_draws: 0,
+        }
+    }
+
+    // produces the output as a JSON value, without serializing it
+    pub fn transform_value_root(&self, input: &Value) -> Result<Value, JsonatrError> {
+        let output = require_with!(self.output.as_ref(), "no output specified");
+        self.context().evaluate(output, input)
+    }
+
+    // same as transform_value_root, but also returns the evaluation errors, which don't fail
+    // the transformation outside of strict mode; they are also logged at the error level
+    pub fn transform_value_with_errors(
+        &self,
+        input: &Value,
+    ) -> Result<(Value, Vec<String>), JsonatrError> {
+        let output = require_with!(self.output.as_ref(), "no output specified");
+        let mut context = self.context();
+        let value = context.evaluate(output, input)?;
+        let errors = context.errors.into_iter().map(|f| f.message).collect();
+        Ok((value, errors))
+    }
+
+    // parses the expression, and checks that the inputs and builtins it refers to are defined
+    pub fn compile(&self, text: &str) -> Result<CompiledExpr, JsonatrError> {
+        let expr = require_with!(parse_expr(text), "'{}' is not a Jsonatr expression", text);
+        let mut diagnostics = Vec::new();
+        self.validate_expr(text, "expression", &HashSet::new(), &mut diagnostics);
+        if let Some(diagnostic) = diagnostics.into_iter().next() {
+            return Err(JsonatrError::Other(diagnostic.message));
+        }
+        Ok(CompiledExpr {
+            text: text.to_string(),
+            expr: Arc::new(expr),
+        })
+    }
+
+    // evaluates the compiled expression against the input; unlike the expressions of the output,
+    // it fails on any evaluation error, also outside of strict mode
+    pub fn eval(&self, expr: &CompiledExpr, input: &Value) -> Result<Value, JsonatrError> {
+        let mut context = self.context();
+        context
+            .parsed
+            .insert(expr.text.clone(), Some(expr.expr.clone()));
+        let value = context.transform_string(&expr.text, input);
+        if let Some(error) = context.aborted.take() {
+            return Err(error);
+        }
+        match (value, context.errors.into_iter().next()) {
+            (Some(value), None) => Ok(value),
+            (_, Some(failure)) => Err(failure.error),
+            (None, None) => bail!("failed to evaluate expression '{}'", expr.text),
+        }
+    }
+
+    // transforms the inputs one by one, e.g. the documents of a large NDJSON stream, as the iterator advances;
+    // the parsed expressions are reused across the inputs, while everything else is evaluated anew for each
+    pub fn transform_iter<I>(
+        &self,
+        inputs: I,
+    ) -> Result<TransformIter<'_, I::IntoIter>, JsonatrError>
+    where
+        I: IntoIterator<Item = Value>,
+    {
+        let output = require_with!(self.output.as_ref(), "no output specified");
+        Ok(TransformIter {
+            spec: self,
+            output,
+            inputs: inputs.into_iter(),
+            parsed: HashMap::new(),
+        })
+    }
+
+    // applies the transformation to a value of another representation, e.g. TOML or YAML,
+    // and produces the output in the same representation; see ValueBackend
+    pub fn transform_with<B: ValueBackend>(
+        &self,
+        backend: &B,
+        input: B::Value,
+    ) -> Result<B::Value, JsonatrError> {
+        let input = backend.to_json(input)?;
+        backend.to_value(self.transform_value_root(&input)?)
+    }
+
+    // same as transform_with, but parses the input from text, and renders the output as text
+    pub fn transform_text<B: ValueBackend>(
+        &self,
+        backend: &B,
+        input: &str,
+    ) -> Result<String, JsonatrError> {
+        let output = self.transform_with(backend, backend.parse(input)?)?;
+        backend.render(&output)
+    }
+
+    pub fn transform(&self, input: &Value) -> Result<String, JsonatrError> {
+        self.check_output_format(&self.format)?;
+        let transformed_output = self.transform_value_root(input)?;
+        self.format.serialize(&transformed_output)
+    }
+
+    // same as transform, but also supports binary output encodings
+    pub fn transform_bytes(&self, input: &Value) -> Result<Vec<u8>, JsonatrError> {
+        self.check_output_format(&self.format)?;
+        let transformed_output = self.transform_value_root(input)?;
+        self.format.encode(&transformed_output)
+    }
+
+    // same as transform, but without blocking the async executor; see run_blocking.
+    // The spec is shared with the thread running the transformation, hence the Arc
+    #[cfg(feature = "async")]
+    pub async fn transform_async(self: Arc<Self>, input: Value) -> Result<String, JsonatrError> {
+        run_blocking(move || self.transform(&input)).await
+    }
+
+    // same as transform_all, but without blocking the async executor; see run_blocking
+    #[cfg(feature = "async")]
+    pub async fn transform_all_async(
+        self: Arc<Self>,
+        input: Value,
+    ) -> Result<Vec<RenderedOutput>, JsonatrError> {
+        run_blocking(move || self.transform_all(&input)).await
+    }
+
+    // produces the main output, if any, followed by all named outputs;
+    // inputs referenced from several outputs are evaluated only once
+    pub fn transform_all(&self, input: &Value) -> Result<Vec<RenderedOutput>, JsonatrError> {
+        if self.output.is_none() && self.outputs.is_empty() {
+            bail!("no output specified")
+        }
+        let mut context = self.context();
+        context.run_cache = Some(Default::default());
+        let mut rendered = Vec::new();
+        if let Some(output) = &self.output {
+            self.check_output_format(&self.format)?;
+            let transformed_output = context.evaluate(output, input)?;
+            rendered.push(RenderedOutput {
+                name: None,
+                file: None,
+                content: self.format.encode(&transformed_output)?,
+                format: self.format.clone(),
+                value: transformed_output,
+            });
+        }
+        for output in &self.outputs {
+            let format = output.format.clone().unwrap_or_else(|| self.format.clone());
+            self.check_output_format(&format)?;
+            let transformed_output = context.evaluate(&output.output, input)?;
+            let elements = match &output.split {
+                None => {
+                    let file = output.file.as_deref().map(expand_env).transpose()?;
+                    vec![(file, transformed_output)]
+                }
+                Some(template) => {
+                    let elements = require_with!(
+                        transformed_output.as_array(),
+                        "output '{}' is split into files, but it is not an array",
+                        output.name
+                    );
+                    let mut files = Vec::new();
+                    for (index, element) in elements.iter().enumerate() {
+                        let template = expand_env(template)?;
+                        let file =
+                            expand_filename(&template, element, index, self.path_engine.as_ref())?;
+                        files.push((Some(file), element.clone()));
+                    }
+                    files
+                }
+            };
+            for (file, value) in elements {
+                let content = format
+                    .encode(&value)
+                    .map_err(|e| SimpleError::new(format!("output '{}': {}", output.name, e)))?;
+                rendered.push(RenderedOutput {
+                    name: Some(output.name.clone()),
+                    file,
+                    content,
+                    format: format.clone(),
+                    value,
+                });
+            }
+        }
+        Ok(rendered)
+    }
+}
+
+impl Context<'_> {
+    // interprets a builtin argument, which can be either a 'single-quoted' raw string,
+    // a JSON literal, or a Jsonatr expression evaluated against the transformed value;
+    // anything else is taken as a bare string
+    pub fn eval_arg(&mut self, arg: &str, v: &Value) -> Option<Value> {
+        if arg.len() >= 2 && arg.starts_with('\'') && arg.ends_with('\'') {
+            return Some(Value::String(arg[1..arg.len() - 1].to_string()));
+        }
+        if arg.starts_with('$') {
+            return self.transform_string(arg, v);
+        }
+        match serde_json::from_str(arg) {
+            Ok(value) => Some(value),
+            Err(_) => Some(Value::String(arg.to_string())),
+        }
+    }
+
+    pub fn eval_string_arg(&mut self, arg: &str, v: &Value) -> Option<String> {
+        match self.eval_arg(arg, v)? {
+            Value::String(s) => Some(s),
+            _ => None,
+        }
     }
 
     // assumes that the value is a singleton array; transforms array into its single element
@@ -185,13 +1813,13 @@ impl Transformer {
             1 => {
                 let new_arr: Vec<Value> = arr
                     .iter()
-                    .map(|x| match self.apply_input_by_name(&args[0], &x) {
+                    .map(|x| match self.apply_input_by_name(&args[0], x) {
                         Ok(res) => res,
                         Err(e) => {
-                            eprintln!(
-                                "Error: failed to apply input transform '{}'; reason: {}",
-                                args[0],
-                                e.to_string()
+                            self.report_input_error(
+                                &args[0],
+                                format!("failed to apply input transform '{}'", args[0]),
+                                e,
                             );
                             x.clone()
                         }
@@ -199,77 +1827,726 @@ impl Transformer {
                     .collect();
                 Some(Value::Array(new_arr))
             }
-            _ => None,
-        }
+            _ => None,
+        }
+    }
+
+    // checks the value for non-emptiness/non-zeroness,
+    // and assumes that there are two arguments: if_branch and else_branch transformers
+    fn builtin_ifelse(&mut self, v: Value, args: &[String]) -> Option<Value> {
+        if args.len() != 2 {
+            return None;
+        }
+        let cond = is_truthy(&v)?;
+        let index = if cond { 0 } else { 1 };
+        match self.apply_input_by_name(&args[index], &v) {
+            Ok(res) => Some(res),
+            Err(e) => {
+                self.report_input_error(
+                    &args[index],
+                    format!("failed to apply input transform '{}'", args[index]),
+                    e,
+                );
+                None
+            }
+        }
+    }
+
+    // compiles the regular expression once per transformation
+    #[cfg(feature = "regex")]
+    fn regex(&mut self, pattern: String) -> Option<Regex> {
+        if let Some(re) = self.regexes.get(&pattern) {
+            return Some(re.clone());
+        }
+        let re = Regex::new(&pattern).ok()?;
+        self.regexes.insert(pattern, re.clone());
+        Some(re)
+    }
+
+    // assumes that the value is a string, and the first argument is a regular expression;
+    // returns the capture groups of the first match as an array (or the whole match if there are no groups),
+    // or only the group given by the optional second argument (index or name); null if there is no match
+    #[cfg(feature = "regex")]
+    fn builtin_regex_extract(&mut self, v: Value, args: &[String]) -> Option<Value> {
+        let text = v.as_str()?;
+        if args.is_empty() || args.len() > 2 {
+            return None;
+        }
+        let pattern = self.eval_string_arg(&args[0], &v)?;
+        let re = self.regex(pattern)?;
+        let caps = match re.captures(text) {
+            Some(caps) => caps,
+            None => return Some(Value::Null),
+        };
+        let to_value = |m: Option<regex::Match>| match m {
+            Some(m) => Value::String(m.as_str().to_string()),
+            None => Value::Null,
+        };
+        if args.len() == 2 {
+            return match self.eval_arg(&args[1], &v)? {
+                Value::Number(n) => Some(to_value(caps.get(n.as_u64()? as usize))),
+                Value::String(name) => Some(to_value(caps.name(&name))),
+                _ => None,
+            };
+        }
+        if caps.len() == 1 {
+            return Some(Value::Array(vec![to_value(caps.get(0))]));
+        }
+        Some(Value::Array(
+            (1..caps.len()).map(|i| to_value(caps.get(i))).collect(),
+        ))
+    }
+
+    // assumes that the value is a string, and there are two arguments: a regular expression and a replacement;
+    // all matches are replaced, and the replacement may refer to capture groups as $1 or ${name}
+    #[cfg(feature = "regex")]
+    fn builtin_regex_replace(&mut self, v: Value, args: &[String]) -> Option<Value> {
+        let text = v.as_str()?;
+        if args.len() != 2 {
+            return None;
+        }
+        let pattern = self.eval_string_arg(&args[0], &v)?;
+        let re = self.regex(pattern)?;
+        let replacement = self.eval_string_arg(&args[1], &v)?;
+        Some(Value::String(
+            re.replace_all(text, replacement.as_str()).to_string(),
+        ))
+    }
+
+    // assumes that the first argument is a format string, where each {} is substituted with the next argument,
+    // and {N} with the N-th argument (counting from 0); {{ and }} stand for literal braces.
+    // If no arguments besides the format string are given, the transformed value itself is substituted
+    fn builtin_format(&mut self, v: Value, args: &[String]) -> Option<Value> {
+        if args.is_empty() {
+            return None;
+        }
+        let fmt = self.eval_string_arg(&args[0], &v)?;
+        let mut values = Vec::new();
+        for arg in &args[1..] {
+            values.push(self.eval_arg(arg, &v)?);
+        }
+        if values.is_empty() {
+            values.push(v);
+        }
+        let mut result = String::new();
+        let mut next = 0;
+        let mut chars = fmt.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    result.push('{');
+                }
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    result.push('}');
+                }
+                '{' => {
+                    let mut index = String::new();
+                    loop {
+                        match chars.next()? {
+                            '}' => break,
+                            d => index.push(d),
+                        }
+                    }
+                    let index = if index.is_empty() {
+                        next += 1;
+                        next - 1
+                    } else {
+                        index.trim().parse::<usize>().ok()?
+                    };
+                    result.push_str(&display_value(values.get(index)?));
+                }
+                '}' => return None,
+                c => result.push(c),
+            }
+        }
+        Some(Value::String(result))
+    }
+
+    // assumes that the value is a number or an array of numbers, and there is a single numeric argument;
+    // applies the arithmetic operator to the value, or element-wise to the array
+    fn apply_arithmetic(&mut self, op: char, v: Value, args: &[String]) -> Option<Value> {
+        if args.len() != 1 {
+            return None;
+        }
+        let operand = self.eval_arg(&args[0], &v)?;
+        match &v {
+            Value::Array(arr) => arr
+                .iter()
+                .map(|x| arithmetic(op, x, &operand))
+                .collect::<Option<Vec<Value>>>()
+                .map(Value::Array),
+            _ => arithmetic(op, &v, &operand),
+        }
+    }
+
+    fn builtin_add(&mut self, v: Value, args: &[String]) -> Option<Value> {
+        self.apply_arithmetic('+', v, args)
+    }
+
+    fn builtin_sub(&mut self, v: Value, args: &[String]) -> Option<Value> {
+        self.apply_arithmetic('-', v, args)
+    }
+
+    fn builtin_mul(&mut self, v: Value, args: &[String]) -> Option<Value> {
+        self.apply_arithmetic('*', v, args)
+    }
+
+    fn builtin_div(&mut self, v: Value, args: &[String]) -> Option<Value> {
+        self.apply_arithmetic('/', v, args)
+    }
+
+    fn builtin_mod(&mut self, v: Value, args: &[String]) -> Option<Value> {
+        self.apply_arithmetic('%', v, args)
+    }
+
+    // assumes that the value is an array of numbers; returns their sum (0 for an empty array)
+    fn builtin_sum(&mut self, v: Value, _args: &[String]) -> Option<Value> {
+        v.as_array()?
+            .iter()
+            .try_fold(Value::from(0), |acc, x| arithmetic('+', &acc, x))
+    }
+
+    // assumes that the value is a non-empty array of numbers; returns their arithmetic mean
+    fn builtin_avg(&mut self, v: Value, _args: &[String]) -> Option<Value> {
+        let arr = v.as_array()?;
+        if arr.is_empty() {
+            return None;
+        }
+        let mut sum = 0f64;
+        for x in arr {
+            sum += x.as_f64()?;
+        }
+        serde_json::Number::from_f64(sum / arr.len() as f64).map(Value::Number)
+    }
+
+    // selects the extreme element of an array (null for an empty array) w.r.t. the given ordering;
+    // elements are compared either directly, or by the key given as an optional argument,
+    // which is either a field name, or an expression evaluated against each element
+    fn select_extreme(
+        &mut self,
+        v: Value,
+        args: &[String],
+        wanted: std::cmp::Ordering,
+    ) -> Option<Value> {
+        let arr = v.as_array()?;
+        let mut best: Option<(&Value, Value)> = None;
+        for x in arr {
+            let key = match args.len() {
+                0 => x.clone(),
+                1 if args[0].starts_with('$') => self.eval_arg(&args[0], x)?,
+                1 => {
+                    let field = self.eval_string_arg(&args[0], x)?;
+                    x.as_object()?.get(&field)?.clone()
+                }
+                _ => return None,
+            };
+            best = match best {
+                Some((y, best_key)) if compare_values(&key, &best_key)? != wanted => {
+                    Some((y, best_key))
+                }
+                _ => Some((x, key)),
+            };
+        }
+        Some(best.map_or(Value::Null, |(x, _)| x.clone()))
+    }
+
+    fn builtin_min(&mut self, v: Value, args: &[String]) -> Option<Value> {
+        if !args.is_empty() {
+            return None;
+        }
+        self.select_extreme(v, args, std::cmp::Ordering::Less)
+    }
+
+    fn builtin_max(&mut self, v: Value, args: &[String]) -> Option<Value> {
+        if !args.is_empty() {
+            return None;
+        }
+        self.select_extreme(v, args, std::cmp::Ordering::Greater)
+    }
+
+    fn builtin_min_by(&mut self, v: Value, args: &[String]) -> Option<Value> {
+        if args.len() != 1 {
+            return None;
+        }
+        self.select_extreme(v, args, std::cmp::Ordering::Less)
+    }
+
+    fn builtin_max_by(&mut self, v: Value, args: &[String]) -> Option<Value> {
+        if args.len() != 1 {
+            return None;
+        }
+        self.select_extreme(v, args, std::cmp::Ordering::Greater)
+    }
+
+    // assumes that the value is a number or an array of numbers, and applies the given function
+    // to the value, or element-wise to the array
+    fn map_numbers(v: &Value, f: &dyn Fn(f64) -> Option<Value>) -> Option<Value> {
+        match v {
+            Value::Array(arr) => arr
+                .iter()
+                .map(|x| f(x.as_f64()?))
+                .collect::<Option<Vec<Value>>>()
+                .map(Value::Array),
+            _ => f(v.as_f64()?),
+        }
+    }
+
+    // rounds to the nearest integer, or to the number of decimal digits given as an optional argument
+    fn builtin_round(&mut self, v: Value, args: &[String]) -> Option<Value> {
+        let digits = match args.len() {
+            0 => 0,
+            1 => self.decimal_digits(&args[0], &v)?,
+            _ => return None,
+        };
+        let scale = 10f64.powi(digits as i32);
+        Context::map_numbers(&v, &|x| {
+            // beyond the precision of floats the number is already as rounded as it can be
+            let scaled = x * scale;
+            if scaled.is_finite() {
+                float_to_value(scaled.round() / scale)
+            } else {
+                float_to_value(x)
+            }
+        })
+    }
+
+    // evaluates the argument giving the number of decimal digits, which can be from 0 to MAX_DECIMAL_DIGITS
+    fn decimal_digits(&mut self, arg: &str, v: &Value) -> Option<usize> {
+        const MAX_DECIMAL_DIGITS: u64 = 100;
+        let digits = self.eval_arg(arg, v)?.as_u64()?;
+        if digits > MAX_DECIMAL_DIGITS {
+            return None;
+        }
+        Some(digits as usize)
+    }
+
+    fn builtin_floor(&mut self, v: Value, args: &[String]) -> Option<Value> {
+        if !args.is_empty() {
+            return None;
+        }
+        Context::map_numbers(&v, &|x| float_to_value(x.floor()))
+    }
+
+    fn builtin_ceil(&mut self, v: Value, args: &[String]) -> Option<Value> {
+        if !args.is_empty() {
+            return None;
+        }
+        Context::map_numbers(&v, &|x| float_to_value(x.ceil()))
+    }
+
+    // formats the number as a string with exactly the given number of decimal digits
+    fn builtin_to_fixed(&mut self, v: Value, args: &[String]) -> Option<Value> {
+        if args.len() != 1 {
+            return None;
+        }
+        let digits = self.decimal_digits(&args[0], &v)?;
+        Context::map_numbers(&v, &|x| Some(Value::String(format!("{:.*}", digits, x))))
+    }
+
+    // returns the type of the value: "null", "boolean", "number", "string", "array", or "object"
+    fn builtin_type(&mut self, v: Value, args: &[String]) -> Option<Value> {
+        if !args.is_empty() {
+            return None;
+        }
+        Some(Value::String(type_name(&v).to_string()))
+    }
+
+    // passes the value through unchanged if its type is one of those given as arguments; otherwise
+    // the whole transformation fails, also outside of strict mode
+    fn builtin_assert_type(&mut self, v: Value, args: &[String]) -> Option<Value> {
+        if args.is_empty() {
+            return None;
+        }
+        let mut types = Vec::new();
+        for arg in args {
+            types.push(self.eval_string_arg(arg, &v)?);
+        }
+        if types.iter().any(|t| t == type_name(&v)) {
+            Some(v)
+        } else {
+            self.report_error(format!(
+                "expected value of type '{}', but got '{}'",
+                types.join("' or '"),
+                type_name(&v)
+            ));
+            if let Some(failure) = self.errors.last() {
+                self.abort(failure.evaluation_error());
+            }
+            None
+        }
+    }
+
+    // converts a number, a numeric string, a boolean, or null into a number
+    fn builtin_to_number(&mut self, v: Value, args: &[String]) -> Option<Value> {
+        if !args.is_empty() {
+            return None;
+        }
+        match v {
+            Value::Number(_) => Some(v),
+            Value::Bool(x) => Some(Value::from(x as i64)),
+            Value::Null => Some(Value::from(0)),
+            Value::String(x) => match serde_json::from_str(x.trim()).ok()? {
+                Value::Number(n) => Some(Value::Number(n)),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    // converts the value into a string; strings are kept as is, and other values are encoded as JSON
+    fn builtin_to_string(&mut self, v: Value, args: &[String]) -> Option<Value> {
+        if !args.is_empty() {
+            return None;
+        }
+        Some(Value::String(display_value(&v)))
+    }
+
+    // converts the value into a boolean following the same non-emptiness/non-zeroness rules as ifelse;
+    // the strings "true" and "false" are converted into the corresponding booleans
+    fn builtin_to_bool(&mut self, v: Value, args: &[String]) -> Option<Value> {
+        if !args.is_empty() {
+            return None;
+        }
+        match v.as_str() {
+            Some("true") => Some(Value::Bool(true)),
+            Some("false") => Some(Value::Bool(false)),
+            _ => Some(Value::Bool(is_truthy(&v)?)),
+        }
+    }
+
+    // assumes that the value is a string containing JSON; returns the parsed value
+    fn builtin_parse_json(&mut self, v: Value, args: &[String]) -> Option<Value> {
+        if !args.is_empty() {
+            return None;
+        }
+        serde_json::from_str(v.as_str()?).ok()
+    }
+
+    // encodes the value as a compact JSON string
+    fn builtin_to_json(&mut self, v: Value, args: &[String]) -> Option<Value> {
+        if !args.is_empty() {
+            return None;
+        }
+        Some(Value::String(v.to_string()))
+    }
+
+    // replaces a null value or an empty selection (i.e. an empty array) with the fallback given as the argument
+    fn builtin_default(&mut self, v: Value, args: &[String]) -> Option<Value> {
+        if args.len() != 1 {
+            return None;
+        }
+        match &v {
+            Value::Null => self.eval_arg(&args[0], &v),
+            Value::Array(arr) if arr.is_empty() => self.eval_arg(&args[0], &v),
+            _ => Some(v),
+        }
+    }
+
+    // checks whether the array contains the argument as an element, or the string contains it as a substring
+    fn builtin_contains(&mut self, v: Value, args: &[String]) -> Option<Value> {
+        if args.len() != 1 {
+            return None;
+        }
+        let needle = self.eval_arg(&args[0], &v)?;
+        match &v {
+            Value::Array(arr) => Some(Value::Bool(arr.contains(&needle))),
+            Value::String(s) => Some(Value::Bool(s.contains(needle.as_str()?))),
+            _ => None,
+        }
+    }
+
+    // checks whether the object has the key given as the argument
+    fn builtin_has(&mut self, v: Value, args: &[String]) -> Option<Value> {
+        if args.len() != 1 {
+            return None;
+        }
+        let key = self.eval_string_arg(&args[0], &v)?;
+        Some(Value::Bool(v.as_object()?.contains_key(&key)))
+    }
+
+    // evaluates the predicate, which is either an expression or an input name, against the value,
+    // and checks the result for non-emptiness/non-zeroness
+    fn check_predicate(&mut self, predicate: &str, v: &Value) -> Option<bool> {
+        let res = if predicate.starts_with('$') {
+            self.transform_string(predicate, v)?
+        } else {
+            match self.apply_input_by_name(predicate, v) {
+                Ok(res) => res,
+                Err(e) => {
+                    self.report_input_error(
+                        predicate,
+                        format!("failed to apply input transform '{}'", predicate),
+                        e,
+                    );
+                    return None;
+                }
+            }
+        };
+        is_truthy(&res)
+    }
+
+    // assumes that the value is an array, and the argument is a predicate;
+    // returns the first element satisfying the predicate, or null if there is none
+    fn builtin_find(&mut self, v: Value, args: &[String]) -> Option<Value> {
+        if args.len() != 1 {
+            return None;
+        }
+        for x in v.as_array()? {
+            if self.check_predicate(&args[0], x)? {
+                return Some(x.clone());
+            }
+        }
+        Some(Value::Null)
+    }
+
+    // assumes that the value is an array; returns the position of the first element
+    // equal to the argument, or null if there is none
+    fn builtin_index_of(&mut self, v: Value, args: &[String]) -> Option<Value> {
+        if args.len() != 1 {
+            return None;
+        }
+        let needle = self.eval_arg(&args[0], &v)?;
+        Some(
+            v.as_array()?
+                .iter()
+                .position(|x| *x == needle)
+                .map_or(Value::Null, Value::from),
+        )
+    }
+
+    // returns the array element at the given position, counting from the end for negative positions;
+    // null if the position is out of range
+    fn nth_element(arr: &[Value], index: i64) -> Value {
+        let index = if index < 0 {
+            arr.len() as i64 + index
+        } else {
+            index
+        };
+        if index < 0 {
+            return Value::Null;
+        }
+        arr.get(index as usize).cloned().unwrap_or(Value::Null)
+    }
+
+    fn builtin_first(&mut self, v: Value, args: &[String]) -> Option<Value> {
+        if !args.is_empty() {
+            return None;
+        }
+        Some(Context::nth_element(v.as_array()?, 0))
+    }
+
+    fn builtin_last(&mut self, v: Value, args: &[String]) -> Option<Value> {
+        if !args.is_empty() {
+            return None;
+        }
+        Some(Context::nth_element(v.as_array()?, -1))
+    }
+
+    fn builtin_nth(&mut self, v: Value, args: &[String]) -> Option<Value> {
+        if args.len() != 1 {
+            return None;
+        }
+        let index = self.eval_arg(&args[0], &v)?.as_i64()?;
+        Some(Context::nth_element(v.as_array()?, index))
+    }
+
+    // evaluates the single argument as a positive size
+    fn size_arg(&mut self, v: &Value, args: &[String]) -> Option<usize> {
+        if args.len() != 1 {
+            return None;
+        }
+        match self.eval_arg(&args[0], v)?.as_u64()? {
+            0 => None,
+            n => Some(n as usize),
+        }
+    }
+
+    // splits the array into consecutive sub-arrays of the given size; the last one may be shorter
+    fn builtin_chunk(&mut self, v: Value, args: &[String]) -> Option<Value> {
+        let size = self.size_arg(&v, args)?;
+        Some(Value::Array(
+            v.as_array()?
+                .chunks(size)
+                .map(|c| Value::Array(c.to_vec()))
+                .collect(),
+        ))
+    }
+
+    // produces all sliding windows of the given size over the array
+    fn builtin_window(&mut self, v: Value, args: &[String]) -> Option<Value> {
+        let size = self.size_arg(&v, args)?;
+        Some(Value::Array(
+            v.as_array()?
+                .windows(size)
+                .map(|w| Value::Array(w.to_vec()))
+                .collect(),
+        ))
+    }
+
+    // reverses the order of array elements, or of string characters
+    fn builtin_reverse(&mut self, v: Value, args: &[String]) -> Option<Value> {
+        if !args.is_empty() {
+            return None;
+        }
+        match v {
+            Value::Array(arr) => Some(Value::Array(arr.into_iter().rev().collect())),
+            Value::String(s) => Some(Value::String(s.chars().rev().collect())),
+            _ => None,
+        }
+    }
+
+    // evaluates the optional argument selecting the base64 alphabet: "standard" (default) or "url"
+    fn base64_config(&mut self, v: &Value, args: &[String]) -> Option<base64::Config> {
+        match args.len() {
+            0 => Some(base64::STANDARD),
+            1 => match self.eval_string_arg(&args[0], v)?.as_str() {
+                "standard" => Some(base64::STANDARD),
+                "url" => Some(base64::URL_SAFE),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    // encodes the string, or the array of bytes, as base64
+    fn builtin_base64_encode(&mut self, v: Value, args: &[String]) -> Option<Value> {
+        let config = self.base64_config(&v, args)?;
+        Some(Value::String(base64::encode_config(
+            value_to_bytes(&v)?,
+            config,
+        )))
+    }
+
+    // decodes the base64 string into a string, or into an array of bytes if the result is not valid UTF-8
+    fn builtin_base64_decode(&mut self, v: Value, args: &[String]) -> Option<Value> {
+        let config = self.base64_config(&v, args)?;
+        let bytes = base64::decode_config(v.as_str()?, config).ok()?;
+        Some(bytes_to_value(bytes))
+    }
+
+    // encodes the string, or the array of bytes, as hex; the optional argument selects
+    // the case of hex digits: "lower" (default) or "upper"
+    fn builtin_hex_encode(&mut self, v: Value, args: &[String]) -> Option<Value> {
+        let bytes = value_to_bytes(&v)?;
+        match args.len() {
+            0 => Some(Value::String(hex::encode(bytes))),
+            1 => match self.eval_string_arg(&args[0], &v)?.as_str() {
+                "lower" => Some(Value::String(hex::encode(bytes))),
+                "upper" => Some(Value::String(hex::encode_upper(bytes))),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    // decodes the hex string (in either case) into a string, or into an array of bytes if the result is not valid UTF-8
+    fn builtin_hex_decode(&mut self, v: Value, args: &[String]) -> Option<Value> {
+        if !args.is_empty() {
+            return None;
+        }
+        Some(bytes_to_value(hex::decode(v.as_str()?).ok()?))
+    }
+
+    // hashes the string, or the canonical JSON encoding (compact, with sorted keys) of any other value;
+    // the optional argument selects the digest encoding: "hex" (default), "base64", or "bytes"
+    fn apply_digest<D: sha2::Digest>(&mut self, v: Value, args: &[String]) -> Option<Value> {
+        let digest = match &v {
+            Value::String(s) => D::digest(s.as_bytes()),
+            _ => D::digest(sort_keys(&v).to_string().as_bytes()),
+        };
+        let encoding = match args.len() {
+            0 => "hex".to_string(),
+            1 => self.eval_string_arg(&args[0], &v)?,
+            _ => return None,
+        };
+        match encoding.as_str() {
+            "hex" => Some(Value::String(hex::encode(digest))),
+            "base64" => Some(Value::String(base64::encode(digest))),
+            "bytes" => Some(Value::Array(digest.into_iter().map(Value::from).collect())),
+            _ => None,
+        }
+    }
+
+    fn builtin_sha256(&mut self, v: Value, args: &[String]) -> Option<Value> {
+        self.apply_digest::<sha2::Sha256>(v, args)
+    }
+
+    fn builtin_sha512(&mut self, v: Value, args: &[String]) -> Option<Value> {
+        self.apply_digest::<sha2::Sha512>(v, args)
+    }
+
+    fn builtin_ripemd160(&mut self, v: Value, args: &[String]) -> Option<Value> {
+        self.apply_digest::<ripemd::Ripemd160>(v, args)
+    }
+
+    // generates a random (version 4) UUID; if the seed argument is given, the UUID is derived
+    // deterministically from the SHA-256 hash of the seed (a string or the canonical JSON of a value)
+    fn builtin_uuid(&mut self, v: Value, args: &[String]) -> Option<Value> {
+        let uuid = match args.len() {
+            0 => match self.random_bytes() {
+                Some(bytes) => uuid::Builder::from_random_bytes(bytes).into_uuid(),
+                None => uuid::Uuid::new_v4(),
+            },
+            1 => {
+                let seed = match self.eval_arg(&args[0], &v)? {
+                    Value::String(s) => s,
+                    x => sort_keys(&x).to_string(),
+                };
+                let digest = <sha2::Sha256 as sha2::Digest>::digest(seed.as_bytes());
+                let mut bytes = [0u8; 16];
+                bytes.copy_from_slice(&digest[..16]);
+                uuid::Builder::from_random_bytes(bytes).into_uuid()
+            }
+            _ => return None,
+        };
+        Some(Value::String(uuid.to_string()))
     }
 
-    // checks the value for non-emptiness/non-zeroness,
-    // and assumes that there are two arguments: if_branch and else_branch transformers
-    fn builtin_ifelse(&mut self, v: Value, args: &[String]) -> Option<Value> {
-        if args.len() != 2 {
+    // the next 16 bytes of the pseudo-random sequence derived from the seed of the spec, if it has one;
+    // the sequence restarts with each transformation, so that its outputs are reproducible
+    fn random_bytes(&mut self) -> Option<[u8; 16]> {
+        let seed = self.spec.seed?;
+        let block = format!("{}:{}", seed, self.random_draws);
+        self.random_draws += 1;
+        let digest = <sha2::Sha256 as sha2::Digest>::digest(block.as_bytes());
+        let mut bytes = [0u8; 16];
+        bytes.copy_from_slice(&digest[..16]);
+        Some(bytes)
+    }
+
+    // returns the current local time, formatted with the strftime-like format given as an optional argument
+    // (RFC 3339 by default); the optional second argument "utc" switches to UTC time
+    fn builtin_now(&mut self, v: Value, args: &[String]) -> Option<Value> {
+        if args.len() > 2 {
             return None;
         }
-        let cond = match v.clone() {
-            Value::Null => false,
-            Value::Bool(x) => x,
-            Value::Number(x) => {
-                if let Some(n) = x.as_f64() {
-                    n != 0f64
-                } else if let Some(n) = x.as_i64() {
-                    n != 0i64
-                } else if let Some(n) = x.as_u64() {
-                    n != 0u64
-                } else {
-                    return None;
-                }
-            }
-            Value::Array(x) => !x.is_empty(),
-            Value::String(x) => !x.is_empty(),
-            Value::Object(x) => !x.is_empty(),
+        let utc = match args.get(1) {
+            None => false,
+            Some(arg) => match self.eval_string_arg(arg, &v)?.as_str() {
+                "utc" => true,
+                "local" => false,
+                _ => return None,
+            },
         };
-        let index = if cond { 0 } else { 1 };
-        match self.apply_input_by_name(&args[index], &v) {
-            Ok(res) => Some(res),
-            Err(e) => {
-                eprintln!(
-                    "Error: failed to apply input transform '{}'; reason: {}",
-                    args[index],
-                    e.to_string()
-                );
-                None
-            }
-        }
-    }
-
-    // parses a Jsonatr expression, which is of the form
-    // $<input>.<jsonpath>  [| <transform> [(arg,...)]]*
-    //   <input> is an identifier, referring to an some of the inputs
-    //   $.<jsonpath> is a JsonPath expression, interpreted by the jsonpath_lib
-    //   [| <transform> [(arg,...)]]* is a pipe-separated sequence of transforms,
-    // each transform being an identifier with optional arguments
-    fn parse_expr(&self, text: &str) -> Option<Expr> {
-        let input_cap = INPUT_RE.captures(text)?; // parsing fails if text doesn't contain input
-        let start = input_cap[0].len();
-        let mut end = text.len();
-        let mut transforms: Vec<(String, Vec<String>)> = Vec::new();
-        while let Some(transform_cap) = TRANSFORM_RE.captures(&text[start..end]) {
-            let name = transform_cap[1].to_string();
-            end -= transform_cap[0].len();
-            let mut args: Vec<String> = Vec::new();
-            if let Some(args_match) = transform_cap.get(2) {
-                args = SEP_RE
-                    .split(args_match.as_str())
-                    .into_iter()
-                    .map(|s| s.to_string())
-                    .collect();
-            }
-            transforms.insert(0, (name, args));
+        let now = if utc {
+            chrono::Utc::now().with_timezone(&chrono::FixedOffset::east_opt(0)?)
+        } else {
+            let now = chrono::Local::now();
+            now.with_timezone(now.offset())
+        };
+        let fmt = match args.first() {
+            None => return Some(Value::String(now.to_rfc3339())),
+            Some(arg) => self.eval_string_arg(arg, &v)?,
+        };
+        let items: Vec<chrono::format::Item> = chrono::format::StrftimeItems::new(&fmt).collect();
+        if items.contains(&chrono::format::Item::Error) {
+            return None;
         }
-        Some(Expr {
-            input: input_cap[1].to_string(),
-            jpath: text[start..end].to_string(),
-            transforms,
-        })
+        Some(Value::String(
+            now.format_with_items(items.into_iter()).to_string(),
+        ))
     }
 
     fn apply_input(
@@ -277,85 +2554,598 @@ impl Transformer {
         input: &Input,
         root: &Value,
     ) -> Result<Value, Box<dyn std::error::Error>> {
+        if let Some(sandbox) = &self.spec.sandbox {
+            sandbox.check_input(input)?;
+        }
+        let resolved;
+        let input = if matches!(input.kind, InputKind::INLINE | InputKind::LITERAL) {
+            input
+        } else {
+            let is_path = matches!(
+                input.kind,
+                InputKind::FILE
+                    | InputKind::TOML
+                    | InputKind::CSV
+                    | InputKind::CBOR
+                    | InputKind::MSGPACK
+            );
+            // environment variables are expanded in the path as written in the spec, and not in the values
+            // substituted for its placeholders, which may come from the input data
+            let mut source = match &input.source {
+                Value::String(path) if is_path => {
+                    let path = Value::String(expand_env(path)?);
+                    self.resolve_placeholders(&path, root, &input.name)?
+                }
+                source => self.resolve_placeholders(source, root, &input.name)?,
+            };
+            if let (true, Value::String(path)) = (is_path, &source) {
+                if !self.spec.include_path.is_empty() && !self.spec.resolver.is_file(path) {
+                    if let Some(found) = self.spec.search_include_path(path) {
+                        source = Value::String(found);
+                    }
+                }
+            }
+            resolved = Input {
+                source,
+                ..input.clone()
+            };
+            &resolved
+        };
         let result: Value;
         match input.kind {
             InputKind::INLINE => {
                 result = self.transform_value(&input.source, root);
             }
+            InputKind::LITERAL => {
+                result = input.source.clone();
+            }
             InputKind::FILE => {
                 if let Some(path) = input.source.as_str() {
-                    let file = std::fs::read_to_string(path)?;
+                    let file = self.spec.resolver.read_to_string(path)?;
                     let value = serde_json::from_str(&file)?;
-                    result = self.transform_value(&value, &root);
+                    result = self.transform_value(&value, root);
                 } else {
                     bail!("non-string provided as source for input '{}'", input.name)
                 }
             }
-            InputKind::COMMAND => {
-                if let Some(command) = input.source.as_str() {
-                    match shell_words::split(command) {
-                        Ok(args) => {
-                            if args.is_empty() {
-                                bail!("failed to parse command for input '{}'", input.name);
-                            }
-
-                            let mut process = match Command::new(&args[0])
-                                .args(&args[1..])
-                                .stdin(Stdio::piped())
-                                .stdout(Stdio::piped())
-                                .spawn()
-                            {
-                                Err(e) => bail!(
-                                    "failed to run command for input '{}'; reason: {}",
-                                    input.name,
-                                    e.to_string()
-                                ),
-                                Ok(process) => process,
-                            };
-                            if input.stdin {
-                                match &process
-                                    .stdin
-                                    .as_mut()
-                                    .unwrap()
-                                    .write(serde_json::to_string(root).unwrap().as_bytes())
-                                {
-                                    Err(_) => bail!(
-                                        "couldn't write to command stdin for input '{}'",
-                                        input.name
-                                    ),
-                                    Ok(_) => (),
-                                }
-                            }
-                            let status = process.wait()?;
-                            if !status.success() {
-                                bail!(
-                                    "failed to execute command for input '{}': {}",
-                                    input.name,
-                                    status.to_string()
-                                )
-                            }
-                            let mut output = String::new();
-                            if process.stdout.unwrap().read_to_string(&mut output).is_err() {
-                                bail!(
-                                    "couldn't read from command stdout for input '{}",
-                                    input.name
-                                )
-                            }
-                            match serde_json::from_str(&output) {
-                                Err(_) => result = Value::String(output.trim_end().to_string()),
-                                Ok(value) => result = value,
-                            }
+            InputKind::TOML => {
+                if let Some(path) = input.source.as_str() {
+                    let file = self.spec.resolver.read_to_string(path)?;
+                    let value = parse_toml(&file)?;
+                    result = self.transform_value(&value, root);
+                } else {
+                    bail!("non-string provided as source for input '{}'", input.name)
+                }
+            }
+            InputKind::CSV => {
+                if let Some(path) = input.source.as_str() {
+                    let delimiter = match input.delimiter.as_deref() {
+                        None => b',',
+                        Some(d) if d.len() == 1 => d.as_bytes()[0],
+                        Some(_) => bail!(
+                            "wrong delimiter of input '{}': should be a single character",
+                            input.name
+                        ),
+                    };
+                    let types = match &input.types {
+                        None => serde_json::Map::new(),
+                        Some(types) => require_with!(
+                            types.as_object(),
+                            "wrong 'types' clause of input '{}': should be an object",
+                            input.name
+                        )
+                        .clone(),
+                    };
+                    let file = self.spec.resolver.read_to_string(path)?;
+                    result = parse_csv(&file, delimiter, &types)?;
+                } else {
+                    bail!("non-string provided as source for input '{}'", input.name)
+                }
+            }
+            InputKind::ENV => {
+                let var = |name: &str| match std::env::var(name) {
+                    Ok(value) => Value::String(value),
+                    Err(_) => Value::Null,
+                };
+                result = match &input.source {
+                    Value::Null => {
+                        let mut vars: Vec<(String, String)> = std::env::vars().collect();
+                        vars.sort();
+                        Value::Object(
+                            vars.into_iter()
+                                .map(|(k, v)| (k, Value::String(v)))
+                                .collect(),
+                        )
+                    }
+                    Value::String(name) => var(name),
+                    Value::Array(names) => {
+                        let mut vars = serde_json::Map::new();
+                        for name in names {
+                            let name = require_with!(
+                                name.as_str(),
+                                "non-string variable name provided in source for input '{}'",
+                                input.name
+                            );
+                            vars.insert(name.to_string(), var(name));
                         }
-                        Err(_) => bail!("failed to parse command for input '{}'", input.name),
+                        Value::Object(vars)
+                    }
+                    _ => bail!(
+                        "wrong source for input '{}': should be a variable name or an array of names",
+                        input.name
+                    ),
+                };
+            }
+            #[cfg(feature = "files")]
+            InputKind::GLOB => {
+                if let Some(pattern) = input.source.as_str() {
+                    let mut files = Vec::new();
+                    for path in glob::glob(pattern)? {
+                        let path = path?;
+                        if !path.is_file() {
+                            continue;
+                        }
+                        let file = self.spec.resolver.read_to_string(&path.to_string_lossy())?;
+                        let content = match serde_json::from_str(&file) {
+                            Ok(value) => value,
+                            Err(_) => Value::String(file),
+                        };
+                        let mut entry = serde_json::Map::new();
+                        entry.insert(
+                            "path".to_string(),
+                            Value::String(path.to_string_lossy().to_string()),
+                        );
+                        entry.insert("content".to_string(), content);
+                        files.push(Value::Object(entry));
                     }
+                    result = Value::Array(files);
                 } else {
                     bail!("non-string provided as source for input '{}'", input.name)
                 }
             }
+            InputKind::CBOR | InputKind::MSGPACK => {
+                if let Some(path) = input.source.as_str() {
+                    let bytes = self.spec.resolver.read(path)?;
+                    let value = if input.kind == InputKind::CBOR {
+                        parse_cbor(&bytes)?
+                    } else {
+                        parse_msgpack(&bytes)?
+                    };
+                    result = self.transform_value(&value, root);
+                } else {
+                    bail!("non-string provided as source for input '{}'", input.name)
+                }
+            }
+            InputKind::STDIN => {
+                let text = {
+                    let mut stdin_text = self.spec.stdin_text.lock().unwrap();
+                    if stdin_text.is_none() {
+                        *stdin_text = Some(read_stdin()?);
+                    }
+                    stdin_text.clone().unwrap()
+                };
+                let mut documents = parse_documents(&text, input.separator.as_deref())?;
+                result = match &input.source {
+                    Value::Null => Value::Array(documents),
+                    Value::Number(n) => match n.as_u64() {
+                        Some(i) if (i as usize) < documents.len() => {
+                            documents.swap_remove(i as usize)
+                        }
+                        _ => bail!(
+                            "STDIN contains no document at position {} for input '{}'",
+                            n,
+                            input.name
+                        ),
+                    },
+                    _ => bail!(
+                        "wrong source for input '{}': should be a document position",
+                        input.name
+                    ),
+                };
+            }
+            #[cfg(feature = "commands")]
+            InputKind::GIT => {
+                let field = |name: &str| input.source.get(name).and_then(|v| v.as_str());
+                let (repo, rev, path) = match (field("repo"), field("rev"), field("path")) {
+                    (repo, Some(rev), Some(path)) => (repo.unwrap_or("."), rev, path),
+                    _ => bail!(
+                        "wrong source for input '{}': should be an object with 'rev' and 'path' fields",
+                        input.name
+                    ),
+                };
+                // the revision may come from the data through placeholders; git would take it for an option
+                if rev.starts_with('-') {
+                    bail!("wrong revision '{}' for input '{}'", rev, input.name)
+                }
+                self.check_cancelled()?;
+                log::debug!(
+                    "running git show {}:{} in '{}' for input '{}'",
+                    rev,
+                    path,
+                    repo,
+                    input.name
+                );
+                let output = Command::new("git")
+                    .args(["-C", repo, "show", "--end-of-options"])
+                    .arg(format!("{}:{}", rev, path))
+                    .stdin(Stdio::null())
+                    .output()?;
+                if !output.status.success() {
+                    bail!(
+                        "failed to read '{}' at revision '{}' for input '{}': {}",
+                        path,
+                        rev,
+                        input.name,
+                        String::from_utf8_lossy(&output.stderr).trim_end()
+                    )
+                }
+                let file = String::from_utf8(output.stdout)?;
+                result = match serde_json::from_str(&file) {
+                    Ok(value) => self.transform_value(&value, root),
+                    Err(_) => Value::String(file.trim_end().to_string()),
+                };
+            }
+            InputKind::ARCHIVE => {
+                let field = |name: &str| input.source.get(name).and_then(|v| v.as_str());
+                let (archive, path) = match (field("archive"), field("path")) {
+                    (Some(archive), Some(path)) => (archive, path),
+                    _ => bail!(
+                        "wrong source for input '{}': should be an object with 'archive' and 'path' fields",
+                        input.name
+                    ),
+                };
+                let file = read_archive_entry(archive, self.spec.resolver.read(archive)?, path)?;
+                result = match serde_json::from_str(&file) {
+                    Ok(value) => self.transform_value(&value, root),
+                    Err(_) => Value::String(file.trim_end().to_string()),
+                };
+            }
+            #[cfg(feature = "http")]
+            InputKind::HTTP => {
+                result = self.fetch_http(input, root)?;
+            }
+            #[cfg(feature = "commands")]
+            InputKind::COMMAND => {
+                result = self.run_command(input, root)?;
+            }
+            #[cfg(not(feature = "files"))]
+            InputKind::GLOB => bail!(unsupported(input, "files")),
+            #[cfg(not(feature = "http"))]
+            InputKind::HTTP => bail!(unsupported(input, "http")),
+            #[cfg(not(feature = "commands"))]
+            InputKind::COMMAND | InputKind::GIT => bail!(unsupported(input, "commands")),
         };
         Ok(result)
     }
 
+    // substitutes each {$...} placeholder in the string (or in the strings nested in the source object)
+    // with the result of evaluating the Jsonatr expression in it against the root;
+    // a singleton selection is substituted by its only element
+    fn resolve_placeholders(
+        &mut self,
+        source: &Value,
+        root: &Value,
+        name: &str,
+    ) -> Result<Value, Box<dyn std::error::Error>> {
+        match source {
+            Value::String(template) => {
+                let mut result = String::new();
+                let mut rest = template.as_str();
+                while let Some(start) = rest.find("{$") {
+                    result.push_str(&rest[..start]);
+                    let end = require_with!(
+                        find_closing_brace(&rest[start..]),
+                        "unterminated placeholder in source of input '{}'",
+                        name
+                    );
+                    let expr = &rest[start + 1..start + end];
+                    let value = require_with!(
+                        self.transform_string(expr, root),
+                        "failed to resolve placeholder '{}' in source of input '{}'",
+                        expr,
+                        name
+                    );
+                    let value = match value {
+                        Value::Array(mut arr) if arr.len() == 1 => arr.remove(0),
+                        value => value,
+                    };
+                    result.push_str(&display_value(&value));
+                    rest = &rest[start + end + 1..];
+                }
+                result.push_str(rest);
+                Ok(Value::String(result))
+            }
+            Value::Object(obj) => {
+                let mut resolved = serde_json::Map::new();
+                for (k, v) in obj {
+                    resolved.insert(k.clone(), self.resolve_placeholders(v, root, name)?);
+                }
+                Ok(Value::Object(resolved))
+            }
+            _ => Ok(source.clone()),
+        }
+    }
+
+    // runs the command given as the input source, passing the root to its STDIN if requested;
+    // the command's output should either be a valid JSON, or otherwise is converted to a JSON string
+    #[cfg(feature = "commands")]
+    fn run_command(
+        &mut self,
+        input: &Input,
+        root: &Value,
+    ) -> Result<Value, Box<dyn std::error::Error>> {
+        let command_line = require_with!(
+            input.source.as_str(),
+            "non-string provided as source for input '{}'",
+            input.name
+        );
+        let args = match shell_words::split(command_line) {
+            Ok(args) if !args.is_empty() => args,
+            _ => bail!("failed to parse command for input '{}'", input.name),
+        };
+        let (full, lines) = match input.capture.as_deref() {
+            None | Some("stdout") => (false, false),
+            Some("full") => (true, false),
+            Some("lines") => (false, true),
+            Some(other) => bail!(
+                "unknown capture mode '{}' of input '{}': should be 'stdout', 'full', or 'lines'",
+                other,
+                input.name
+            ),
+        };
+        log::debug!(
+            "running command '{}' for input '{}'",
+            command_line,
+            input.name
+        );
+        let mut command = Command::new(&args[0]);
+        command
+            .args(&args[1..])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped());
+        if full {
+            command.stderr(Stdio::piped());
+        }
+        if let Some(cwd) = &input.cwd {
+            command.current_dir(cwd);
+        }
+        if let Some(env) = &input.env {
+            let env = require_with!(
+                self.transform_value(env, root).as_object().cloned(),
+                "wrong 'env' clause of input '{}': should be an object",
+                input.name
+            );
+            for (k, v) in env {
+                command.env(k, display_value(&v));
+            }
+        }
+        self.check_cancelled()?;
+        let mut process = match command.spawn() {
+            Err(e) => return Err(Box::new(JsonatrError::command(command_line, e))),
+            Ok(process) => process,
+        };
+        // STDIN is written and the output is read from separate threads, so that the command can't block on pipes
+        let mut stdin = process.stdin.take().unwrap();
+        let writer = if input.stdin {
+            let data = serde_json::to_string(root).unwrap();
+            Some(std::thread::spawn(move || stdin.write_all(data.as_bytes())))
+        } else {
+            drop(stdin);
+            None
+        };
+        let timeout = match &input.timeout {
+            None => None,
+            Some(timeout) => Some(parse_duration(timeout)?),
+        };
+        let timeout = match (timeout, self.spec.limits.command_timeout) {
+            (Some(timeout), Some(limit)) => Some(timeout.min(limit)),
+            (timeout, limit) => timeout.or(limit),
+        };
+        if lines {
+            return Context::collect_lines(input, process, timeout);
+        }
+        let stdout_reader = read_pipe(process.stdout.take());
+        let stderr_reader = read_pipe(process.stderr.take());
+        let status = match timeout {
+            None => process.wait()?,
+            Some(timeout) => match process.wait_timeout(timeout)? {
+                Some(status) => status,
+                None => {
+                    process.kill()?;
+                    process.wait()?;
+                    let reason = format!("timed out after {:?} and was killed", timeout);
+                    return Err(Box::new(JsonatrError::command(command_line, reason)));
+                }
+            },
+        };
+        if let Some(writer) = writer {
+            // the command is free to exit without reading its STDIN
+            match writer.join() {
+                Ok(Err(e)) if e.kind() != std::io::ErrorKind::BrokenPipe => {
+                    bail!("couldn't write to command stdin for input '{}'", input.name)
+                }
+                _ => (),
+            }
+        }
+        let output = std::process::Output {
+            status,
+            stdout: require_with!(
+                stdout_reader.join().ok().and_then(|r| r.ok()),
+                "couldn't read from command stdout for input '{}'",
+                input.name
+            ),
+            stderr: stderr_reader
+                .join()
+                .ok()
+                .and_then(|r| r.ok())
+                .unwrap_or_default(),
+        };
+        if !full && !output.status.success() {
+            return Err(Box::new(JsonatrError::command(command_line, output.status)));
+        }
+        let stdout = match String::from_utf8(output.stdout) {
+            Ok(stdout) => stdout,
+            Err(_) => bail!(
+                "couldn't read from command stdout for input '{}'",
+                input.name
+            ),
+        };
+        let stdout = match serde_json::from_str(&stdout) {
+            Err(_) => Value::String(stdout.trim_end().to_string()),
+            Ok(value) => value,
+        };
+        if !full {
+            return Ok(stdout);
+        }
+        let mut result = serde_json::Map::new();
+        result.insert("stdout".to_string(), stdout);
+        result.insert(
+            "stderr".to_string(),
+            Value::String(
+                String::from_utf8_lossy(&output.stderr)
+                    .trim_end()
+                    .to_string(),
+            ),
+        );
+        result.insert(
+            "code".to_string(),
+            output.status.code().map_or(Value::Null, Value::from),
+        );
+        Ok(Value::Object(result))
+    }
+
+    // collects each line of the command output as a separate value (parsed as JSON when possible),
+    // until the command exits, the maximal count of lines is reached, or the timeout expires;
+    // in the latter two cases the command is killed, and the lines collected so far are returned
+    #[cfg(feature = "commands")]
+    fn collect_lines(
+        input: &Input,
+        mut process: std::process::Child,
+        timeout: Option<std::time::Duration>,
+    ) -> Result<Value, Box<dyn std::error::Error>> {
+        let deadline = timeout.map(|t| std::time::Instant::now() + t);
+        let max_count = input.max_count.unwrap_or(u64::MAX) as usize;
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let stdout = std::io::BufReader::new(process.stdout.take().unwrap());
+        std::thread::spawn(move || {
+            for line in std::io::BufRead::lines(stdout) {
+                if line.is_err() || sender.send(line.unwrap()).is_err() {
+                    break;
+                }
+            }
+        });
+        let mut values = Vec::new();
+        while values.len() < max_count {
+            let line = match deadline {
+                None => receiver.recv().ok(),
+                Some(deadline) => {
+                    let now = std::time::Instant::now();
+                    if now >= deadline {
+                        None
+                    } else {
+                        receiver.recv_timeout(deadline - now).ok()
+                    }
+                }
+            };
+            match line {
+                None => break,
+                Some(line) if line.trim().is_empty() => (),
+                Some(line) => values.push(match serde_json::from_str(&line) {
+                    Err(_) => Value::String(line.trim_end().to_string()),
+                    Ok(value) => value,
+                }),
+            }
+        }
+        if process.try_wait()?.is_none() {
+            // the command has been stopped early; its exit status is irrelevant
+            process.kill()?;
+            process.wait()?;
+        } else {
+            let status = process.wait()?;
+            if !status.success() {
+                let command_line = input.source.as_str().unwrap_or_default();
+                return Err(Box::new(JsonatrError::command(command_line, status)));
+            }
+        }
+        Ok(Value::Array(values))
+    }
+
+    // performs the HTTP request described by the input; if a body is given, it is sent as JSON
+    #[cfg(feature = "http")]
+    fn fetch_http(
+        &mut self,
+        input: &Input,
+        root: &Value,
+    ) -> Result<Value, Box<dyn std::error::Error>> {
+        let url = require_with!(
+            input.source.as_str(),
+            "non-string provided as source for input '{}'",
+            input.name
+        );
+        let default_method = if input.body.is_some() { "POST" } else { "GET" };
+        let method = input.method.as_deref().unwrap_or(default_method);
+        log::debug!("fetching {} {} for input '{}'", method, url, input.name);
+        let mut request = ureq::request(method, url);
+        for (field, option) in &[("headers", &input.headers), ("query", &input.query)] {
+            if let Some(option) = option {
+                let params = require_with!(
+                    self.transform_value(option, root).as_object().cloned(),
+                    "wrong '{}' clause of input '{}': should be an object",
+                    field,
+                    input.name
+                );
+                for (k, v) in params {
+                    request = match *field {
+                        "headers" => request.set(&k, &display_value(&v)),
+                        _ => request.query(&k, &display_value(&v)),
+                    };
+                }
+            }
+        }
+        if let Some(auth) = &input.auth {
+            let auth = self.transform_value(auth, root);
+            let header = if let Some(token) = auth.get("bearer").and_then(|t| t.as_str()) {
+                format!("Bearer {}", token)
+            } else if let Some(basic) = auth.get("basic") {
+                let user = basic.get("user").and_then(|u| u.as_str()).unwrap_or("");
+                let password = basic.get("password").and_then(|p| p.as_str()).unwrap_or("");
+                format!("Basic {}", base64::encode(format!("{}:{}", user, password)))
+            } else {
+                bail!(
+                    "wrong 'auth' clause of input '{}': should contain either 'basic' or 'bearer'",
+                    input.name
+                )
+            };
+            request = request.set("Authorization", &header);
+        }
+        let response = match &input.body {
+            Some(body) => {
+                let body = self.transform_value(body, root);
+                request
+                    .set("Content-Type", "application/json")
+                    .send_string(&body.to_string())
+            }
+            None => request.call(),
+        };
+        let output = match response {
+            Ok(response) => response.into_string()?,
+            Err(ureq::Error::Status(code, _)) => bail!(
+                "HTTP request for input '{}' failed with status {}",
+                input.name,
+                code
+            ),
+            Err(e) => bail!(
+                "failed to perform HTTP request for input '{}'; reason: {}",
+                input.name,
+                e
+            ),
+        };
+        match serde_json::from_str(&output) {
+            Err(_) => Ok(Value::String(output.trim_end().to_string())),
+            Ok(value) => Ok(value),
+        }
+    }
+
     fn apply_input_by_name(
         &mut self,
         name: &str,
@@ -368,18 +3158,67 @@ impl Transformer {
             }
         }
         // if none is found, it should be present in the inputs
-        let input = require_with!(
-            self.inputs.get(name),
-            "found reference to unknown input '{}'",
-            name
-        )
-        .clone();
+        let input = match self.spec.inputs.get(name) {
+            Some(input) => input.clone(),
+            None => return Err(Box::new(JsonatrError::UnknownInput(name.to_string()))),
+        };
+        let cache_key = input.cache_key(root);
+        if let Some(key) = &cache_key {
+            if let Some(value) = self.input_cache.get(name).and_then(|c| c.get(key)) {
+                return Ok(value.clone());
+            }
+        }
+        // when producing several outputs, inputs not depending on any local scope are shared
+        let shared = self.run_cache.is_some() && self.locals.is_empty() && input.lets.is_none();
+        // the root is serialized into the key only for the shared inputs
+        let root_key = if shared { Some(root.to_string()) } else { None };
+        if let (Some(key), Some(run_cache)) = (&root_key, &self.run_cache) {
+            if let Some(value) = run_cache.get(name).and_then(|c| c.get(key)) {
+                return Ok(value.clone());
+            }
+        }
+        if let Some(max_depth) = self.spec.limits.max_depth {
+            if self.depth >= max_depth {
+                let reason = format!(
+                    "nesting depth of input '{}' exceeds the limit of {}",
+                    name, max_depth
+                );
+                self.abort(JsonatrError::Other(reason.clone()));
+                return Err(reason.into());
+            }
+        }
+        self.depth += 1;
+        let result = self.evaluate_input(&input, root);
+        self.depth -= 1;
+        if let (Some(key), Ok(value)) = (cache_key, &result) {
+            self.input_cache
+                .entry(name.to_string())
+                .or_default()
+                .insert(key, value.clone());
+        }
+        if let (Some(root_key), Some(run_cache), Ok(value)) =
+            (root_key, self.run_cache.as_mut(), &result)
+        {
+            run_cache
+                .entry(name.to_string())
+                .or_default()
+                .insert(root_key, value.clone());
+        }
+        result
+    }
+
+    // evaluates the let clause of the input in a new local scope, and then the input itself
+    fn evaluate_input(
+        &mut self,
+        input: &Input,
+        root: &Value,
+    ) -> Result<Value, Box<dyn std::error::Error>> {
         let lets: serde_json::Map<String, Value> = match input.lets.clone() {
             None => serde_json::Map::new(),
             Some(lets) => require_with!(
                 lets.as_object(),
                 "let clause of input '{}' is not an object",
-                name
+                input.name
             )
             .clone(),
         };
@@ -388,34 +3227,191 @@ impl Transformer {
             locals.insert(k.clone(), self.transform_value(&v, root));
         }
         self.locals.push(locals);
-        let result = self.apply_input(&input, root);
+        log::debug!("evaluating input '{}' of kind {:?}", input.name, input.kind);
+        if let Some(observer) = &self.spec.observer {
+            observer.input_start(input);
+        }
+        let result = self.apply_input(input, root);
+        if let Some(observer) = &self.spec.observer {
+            observer.input_end(input, result.as_ref().ok());
+        }
         self.locals.pop();
         result
     }
 
-    pub fn transform(&mut self, input: &Value) -> Result<String, SimpleError> {
-        let output = require_with!(self.output.clone(), "no output specified");
-        let transformed_output = self.transform_value(&output, input);
-        let result = try_with!(
-            serde_json::to_string_pretty(&transformed_output),
-            "failed to produce output"
-        );
+    // transforms the output template; in strict mode fails on the first evaluation error
+    fn evaluate(&mut self, output: &Value, input: &Value) -> Result<Value, JsonatrError> {
+        self.errors.clear();
+        let result = self.transform_value(output, input);
+        if let Some(error) = self.aborted.take() {
+            return Err(error);
+        }
+        if self.spec.strict && !self.errors.is_empty() {
+            return Err(self.errors.swap_remove(0).error);
+        }
         Ok(result)
     }
 
+    // reports a failure without a more specific kind, e.g. of a builtin, as an EvaluationError
+    fn report_error(&mut self, message: String) {
+        self.report_failure(None, message, None)
+    }
+
+    // reports a failure of the input, keeping the kind of its reason, e.g. an unknown input
+    fn report_input_error(
+        &mut self,
+        input: &str,
+        message: String,
+        reason: Box<dyn std::error::Error>,
+    ) {
+        let message = format!("{}; reason: {}", message, reason);
+        let error = reason.downcast::<JsonatrError>().ok().map(|e| *e);
+        self.report_failure(error, message, Some(input))
+    }
+
+    // records the failure with its location in the output, if it happened there
+    fn report_failure(&mut self, error: Option<JsonatrError>, reason: String, input: Option<&str>) {
+        let reason = reason.trim_end().to_string();
+        let location = self.expression.as_ref().map(|expr| ErrorLocation {
+            pointer: self.pointer(),
+            expression: expr.clone(),
+            builtin: self.builtin.clone(),
+            input: input.map(str::to_string),
+        });
+        let evaluation_error = JsonatrError::EvaluationError {
+            message: reason.clone(),
+            location: location.clone(),
+        };
+        let message = evaluation_error.to_string();
+        log::error!("{}", message);
+        self.errors.push(Failure {
+            error: error.unwrap_or(evaluation_error),
+            message,
+            reason,
+            location,
+        });
+    }
+
+    // the JSON pointer of the output node being evaluated, e.g. "/validators/3/address"
+    fn pointer(&self) -> String {
+        self.pointer
+            .iter()
+            .map(|c| format!("/{}", c.replace('~', "~0").replace('/', "~1")))
+            .collect()
+    }
+
     fn transform_string(&mut self, text: &str, root: &Value) -> Option<Value> {
-        let expr = self.parse_expr(text)?;
+        // errors are located by the output node, also when they happen inside of inputs or builtin arguments
+        let outermost = self.depth == 0 && self.expression.is_none();
+        if outermost {
+            self.expression = Some(text.to_string());
+        }
+        let result = self.observe_expr(text, root, outermost);
+        if let Some(value) = &result {
+            if log::log_enabled!(log::Level::Trace) {
+                log::trace!("expression '{}' = {}", text, summarize(value));
+            }
+        }
+        if outermost {
+            self.expression = None;
+        }
+        result
+    }
+
+    fn observe_expr(&mut self, text: &str, root: &Value, outermost: bool) -> Option<Value> {
+        let observer = self.spec.observer.clone();
+        let observer = match observer {
+            Some(observer) if self.parse(text).is_some() => observer,
+            _ => return self.evaluate_expr(text, root),
+        };
+        if outermost {
+            observer.output_node(&self.pointer(), text);
+        }
+        observer.expression_start(text);
+        let result = self.evaluate_expr(text, root);
+        observer.expression_end(text, result.as_ref());
+        result
+    }
+
+    fn parse(&mut self, text: &str) -> Option<Arc<Expr>> {
+        if let Some(expr) = self.parsed.get(text) {
+            return expr.clone();
+        }
+        let expr = parse_expr(text).map(Arc::new);
+        self.parsed.insert(text.to_string(), expr.clone());
+        expr
+    }
+
+    // decides about the failed expression, given the index of the first error reported for it
+    fn handle_error(&mut self, text: &str, error: usize) -> Value {
+        let action = match &self.spec.error_handler {
+            Some(handler) => handler(text, &self.errors[error].message),
+            None => ErrorAction::Keep,
+        };
+        match action {
+            ErrorAction::Keep => Value::String(text.to_string()),
+            ErrorAction::Null => Value::Null,
+            ErrorAction::Abort => {
+                let failure = &mut self.errors[error];
+                let evaluation_error = failure.evaluation_error();
+                let error = std::mem::replace(&mut failure.error, evaluation_error);
+                self.abort(error);
+                Value::String(text.to_string())
+            }
+        }
+    }
+
+    // records the reason to abort, so that the rest of the transformation is skipped
+    fn abort(&mut self, error: JsonatrError) {
+        if self.aborted.is_none() {
+            self.aborted = Some(error);
+        }
+    }
+
+    // aborts the transformation if it has been cancelled
+    fn check_cancelled(&mut self) -> Result<(), JsonatrError> {
+        if let Some(token) = &self.spec.cancellation {
+            if token.is_cancelled() {
+                self.abort(JsonatrError::Cancelled);
+                return Err(JsonatrError::Cancelled);
+            }
+        }
+        Ok(())
+    }
+
+    fn evaluate_expr(&mut self, text: &str, root: &Value) -> Option<Value> {
+        if self.aborted.is_some() || self.check_cancelled().is_err() {
+            return None;
+        }
+        let expr = self.parse(text)?;
+        log::trace!("evaluating expression '{}'", text);
+        self.expressions += 1;
+        if let Some(max_expressions) = self.spec.limits.max_expressions {
+            if self.expressions > max_expressions {
+                self.abort(JsonatrError::Other(format!(
+                    "number of evaluated expressions exceeds the limit of {}",
+                    max_expressions
+                )));
+                return None;
+            }
+        }
         let json = match expr.input.as_str() {
             "" => match root {
+                // without the main input the expression is kept unchanged; it's an error only in strict mode
+                Value::Null if self.spec.strict => {
+                    self.report_error("no main input to evaluate against".to_string());
+                    None
+                }
                 Value::Null => None,
                 x => Some(x.clone()),
             },
             _ => match self.apply_input_by_name(&expr.input, root) {
                 Ok(v) => Some(v),
                 Err(e) => {
-                    eprintln!(
-                        "Error: failed to apply transform; reason: {} ",
-                        e.to_string()
+                    self.report_input_error(
+                        &expr.input,
+                        "failed to apply transform".to_string(),
+                        e,
                     );
                     None
                 }
@@ -425,34 +3421,53 @@ impl Transformer {
         if expr.jpath.is_empty() {
             value = json;
         } else {
-            value = match jsonpath::select(&json, ("$".to_string() + &expr.jpath).as_str()) {
+            value = match self.spec.path_engine.select(&json, &expr.jpath) {
                 Ok(values) => Some(Value::Array(values.into_iter().cloned().collect())),
-                Err(_) => {
-                    eprintln!(
-                        "Error: failed to apply JsonPath expression '{}'",
-                        expr.jpath
-                    );
+                Err(e) => {
+                    let message = e.to_string();
+                    self.report_failure(Some(e), message, None);
                     None
                 }
             }?;
         }
-        for transform in expr.transforms {
-            if let Some(builtin) = self.builtins.get(&transform.0) {
-                match builtin(self, value, &transform.1) {
-                    Some(new_value) => value = new_value,
-                    None => {
-                        eprintln!("Error: failed to apply builtin transform '{}'", transform.0);
-                        return None;
-                    }
+        if let Some(observer) = &self.spec.observer {
+            observer.path_resolved(text, &value);
+        }
+        for transform in &expr.transforms {
+            if let Some(builtin) = self.spec.builtins.get(&transform.0).cloned() {
+                if !builtin.accepts(transform.1.len()) {
+                    self.report_error(format!(
+                        "wrong number of arguments ({}) of builtin '{}'",
+                        transform.1.len(),
+                        transform.0
+                    ));
+                    return None;
                 }
+                let outer = self.builtin.replace(transform.0.clone());
+                let result = (builtin.function)(self, value, &transform.1);
+                if let Some(observer) = &self.spec.observer {
+                    observer.builtin_applied(&transform.0, &transform.1, result.as_ref());
+                }
+                if result.is_none() {
+                    self.report_error(format!(
+                        "failed to apply builtin transform '{}'",
+                        transform.0
+                    ));
+                }
+                self.builtin = outer;
+                value = result?;
             } else {
-                match self.apply_input_by_name(&transform.0, &value) {
+                let result = self.apply_input_by_name(&transform.0, &value);
+                if let Some(observer) = &self.spec.observer {
+                    observer.input_applied(&transform.0, result.as_ref().ok());
+                }
+                match result {
                     Ok(new_value) => value = new_value,
                     Err(e) => {
-                        eprintln!(
-                            "Error: failed to apply input transform '{}'; reason: {}",
-                            transform.0,
-                            e.to_string()
+                        self.report_input_error(
+                            &transform.0,
+                            format!("failed to apply input transform '{}'", transform.0),
+                            e,
                         );
                         return None;
                     }
@@ -462,31 +3477,97 @@ impl Transformer {
         Some(value)
     }
 
+    // transforms the child of an array or object, keeping track of its location in the output
+    fn transform_node(&mut self, component: String, v: &Value, input: &Value) -> Value {
+        if self.depth > 0 {
+            return self.transform_value(v, input);
+        }
+        self.pointer.push(component);
+        let result = self.transform_value(v, input);
+        self.pointer.pop();
+        result
+    }
+
     fn transform_value(&mut self, v: &Value, input: &Value) -> Value {
+        // only the output is counted against the size limit, not the inputs
+        let counted = self.depth == 0 && self.spec.limits.max_output_size.is_some();
         match v {
             Value::String(string) => {
-                if let Some(value) = self.transform_string(string, input) {
+                let errors = self.errors.len();
+                let result = if let Some(value) = self.transform_string(string, input) {
                     value
+                } else if self.errors.len() > errors && self.aborted.is_none() {
+                    self.handle_error(string, errors)
                 } else {
                     v.clone()
+                };
+                if counted {
+                    self.count_output(json_size(&result));
                 }
+                result
             }
             Value::Array(values) => {
-                let new_values = values
-                    .iter()
-                    .map(|x| self.transform_value(x, input))
-                    .collect();
+                if counted {
+                    // the brackets and the commas
+                    self.count_output(values.len().max(1) + 1);
+                }
+                let mut new_values = Vec::new();
+                for (i, x) in values.iter().enumerate() {
+                    new_values.push(self.transform_node(i.to_string(), x, input));
+                }
                 Value::Array(new_values)
             }
             Value::Object(values) => {
+                if counted {
+                    // the braces, the keys with their colons, and the commas
+                    let keys: usize = values.keys().map(|k| json_size(k) + 1).sum();
+                    self.count_output(keys + values.len().max(1) + 1);
+                }
                 let mut new_values: serde_json::map::Map<String, Value> =
                     serde_json::map::Map::new();
                 for (k, v) in values.iter() {
-                    new_values.insert(k.to_string(), self.transform_value(v, input));
+                    new_values.insert(k.to_string(), self.transform_node(k.to_string(), v, input));
                 }
                 Value::Object(new_values)
             }
-            _ => v.clone(),
+            _ => {
+                if counted {
+                    self.count_output(json_size(v));
+                }
+                v.clone()
+            }
+        }
+    }
+
+    // counts the bytes of the output while it is produced, so that the transformation is aborted
+    // as soon as the output exceeds the limit, and not only after all of it is built
+    fn count_output(&mut self, size: usize) {
+        if let Some(max_output_size) = self.spec.limits.max_output_size {
+            self.output_size += size;
+            if self.output_size > max_output_size {
+                self.abort(JsonatrError::Other(format!(
+                    "output size exceeds the limit of {} bytes",
+                    max_output_size
+                )));
+            }
+        }
+    }
+}
+
+// the length of the compact JSON of the value, counted without building the string
+fn json_size<T: Serialize + ?Sized>(value: &T) -> usize {
+    struct Counter(usize);
+    impl std::io::Write for Counter {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0 += buf.len();
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
         }
     }
+    let mut counter = Counter(0);
+    let _ = serde_json::to_writer(&mut counter, value);
+    counter.0
 }
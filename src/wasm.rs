@@ -0,0 +1,54 @@
+use crate::transformer::{Sandbox, Transformer};
+use serde_json::Value;
+use wasm_bindgen::prelude::*;
+
+// the browser has no commands or local files; inputs should be INLINE, or given with the transformation
+const SANDBOX: Sandbox = Sandbox {
+    deny_files: true,
+    deny_http: true,
+    deny_env: true,
+};
+
+fn to_js(e: impl std::fmt::Display) -> JsValue {
+    JsValue::from_str(&e.to_string())
+}
+
+// A transformer for JavaScript, created from a spec given as a JSON string
+#[wasm_bindgen(js_name = Transformer)]
+pub struct JsTransformer {
+    spec: Transformer,
+}
+
+#[wasm_bindgen(js_class = Transformer)]
+impl JsTransformer {
+    #[wasm_bindgen(constructor)]
+    pub fn new(spec: &str) -> Result<JsTransformer, JsValue> {
+        let spec = Transformer::new_sandboxed(spec, ".", SANDBOX).map_err(to_js)?;
+        Ok(JsTransformer { spec })
+    }
+
+    // transforms the input, given as a JSON string (empty for no input), into the output string
+    pub fn transform(&self, input: &str) -> Result<String, JsValue> {
+        let input = if input.trim().is_empty() {
+            Value::Null
+        } else {
+            serde_json::from_str(input).map_err(to_js)?
+        };
+        self.spec.transform(&input).map_err(to_js)
+    }
+
+    // returns the problems found in the spec, as messages
+    pub fn validate(&self) -> Vec<JsValue> {
+        self.spec
+            .validate()
+            .iter()
+            .map(|d| JsValue::from_str(&d.to_string()))
+            .collect()
+    }
+}
+
+// transforms the input by the spec, both given as JSON strings
+#[wasm_bindgen]
+pub fn transform(spec: &str, input: &str) -> Result<String, JsValue> {
+    JsTransformer::new(spec)?.transform(input)
+}
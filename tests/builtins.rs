@@ -0,0 +1,527 @@
+use jsonatr::error::*;
+use jsonatr::transformer::*;
+use serde_json::Value;
+
+fn transform_file(file: &str) -> String {
+    let input = std::fs::read_to_string(file).unwrap();
+    let spec = Transformer::new(&input, file).unwrap();
+    spec.transform(&Value::Null).unwrap()
+}
+
+fn test_expect(file: &str, expect: &str) {
+    assert_eq!(transform_file(file), expect)
+}
+
+#[test]
+fn test_regex() {
+    test_expect(
+        "tests/support/builtins_regex.json",
+        r#"{
+  "version": [
+    "0",
+    "1",
+    "2"
+  ],
+  "minor": "1",
+  "date": "2020-06-01",
+  "missing": null,
+  "release": "jsonatr version v0.1.2 (01.06.2020)",
+  "alternatives": "x x"
+}"#,
+    );
+
+    // the compiled patterns are reused, but each pattern is compiled on its own
+    let spec = r#"{
+  "input": [
+    { "name": "digits", "kind": "INLINE", "source": "$ | regex_extract('([0-9]+)', 1)" },
+    { "name": "letters", "kind": "INLINE", "source": "$ | regex_extract('([a-z]+)', 1)" }
+  ],
+  "output": { "digits": "$.items | unwrap | map(digits)", "letters": "$.items | unwrap | map(letters)" }
+}"#;
+    let spec = Transformer::new(spec, "").unwrap();
+    let input = serde_json::json!({ "items": ["a1", "bb22", "ccc333"] });
+    assert_eq!(
+        spec.transform_value_root(&input).unwrap(),
+        serde_json::json!({ "digits": ["1", "22", "333"], "letters": ["a", "bb", "ccc"] })
+    );
+}
+
+#[test]
+fn test_format() {
+    test_expect(
+        "tests/support/builtins_format.json",
+        r#"{
+  "label": "block 5E8B3A at height 42",
+  "file": "fixtures/height_42.json",
+  "positional": "[\"test\",\"chain\"]/5E8B3A {raw}"
+}"#,
+    );
+}
+
+#[test]
+fn test_arithmetic() {
+    test_expect(
+        "tests/support/builtins_arithmetic.json",
+        r#"{
+  "next_height": 42,
+  "prev_height": 40,
+  "double_time": 3.0,
+  "half_height": 20.5,
+  "parity": 1,
+  "powers": [
+    100,
+    200,
+    300
+  ],
+  "shifted": [
+    51,
+    61,
+    71
+  ]
+}"#,
+    );
+}
+
+#[test]
+fn test_arithmetic_overflow() {
+    let spec = r#"{
+  "input": [ { "name": "lowest", "kind": "INLINE", "source": -9223372036854775808 } ],
+  "output": {
+    "div": "$lowest | div(-1)",
+    "mod": "$lowest | mod(-1)",
+    "mod_neg": "$lowest | add(1) | mod(-1)",
+    "div_neg": "$lowest | add(2) | div(-1)"
+  }
+}"#;
+    let spec = Transformer::new(spec, ".").unwrap();
+    let res = spec.transform_value_root(&Value::Null).unwrap();
+    assert_eq!(res["div"], 9223372036854775808.0);
+    assert_eq!(res["mod"], 0);
+    assert_eq!(res["mod_neg"], 0);
+    assert_eq!(res["div_neg"], 9223372036854775806i64);
+}
+
+#[test]
+fn test_aggregate() {
+    test_expect(
+        "tests/support/builtins_aggregate.json",
+        r#"{
+  "total_power": 100,
+  "avg_power": 33.333333333333336,
+  "max_round": 3,
+  "min_round": 0,
+  "strongest": {
+    "address": "B",
+    "power": 50
+  },
+  "weakest": {
+    "address": "C",
+    "power": 20
+  }
+}"#,
+    );
+}
+
+#[test]
+fn test_rounding() {
+    test_expect(
+        "tests/support/builtins_rounding.json",
+        r#"{
+  "round": 33,
+  "round_2": 33.33,
+  "floor": [
+    1,
+    2,
+    -1
+  ],
+  "ceil": [
+    2,
+    3,
+    0
+  ],
+  "fixed": "33.333"
+}"#,
+    );
+}
+
+#[test]
+fn test_rounding_digits() {
+    let spec = r#"{
+  "input": [ { "name": "mean", "kind": "INLINE", "source": 33.333333333333336 } ],
+  "output": {
+    "round": "$mean | round(100)",
+    "fixed": "$mean | to_fixed(1)",
+    "round_too_many": "$mean | round(400)",
+    "fixed_too_many": "$mean | to_fixed(99999999999)",
+    "round_negative": "$mean | round(-1)"
+  }
+}"#;
+    let spec = Transformer::new(spec, ".").unwrap();
+    let res = spec.transform_value_root(&Value::Null).unwrap();
+    assert_eq!(res["round"], 33.333333333333336);
+    assert_eq!(res["fixed"], "33.3");
+    assert_eq!(res["round_too_many"], "$mean | round(400)");
+    assert_eq!(res["fixed_too_many"], "$mean | to_fixed(99999999999)");
+    assert_eq!(res["round_negative"], "$mean | round(-1)");
+}
+
+#[test]
+fn test_type() {
+    test_expect(
+        "tests/support/builtins_type.json",
+        r#"{
+  "types": [
+    "number",
+    "array",
+    "object",
+    "string",
+    "array"
+  ],
+  "txs": []
+}"#,
+    );
+    let spec = r#"{ "output": { "height": "$.height | unwrap | assert_type('string', 'null')" } }"#;
+    let spec = Transformer::new(spec, ".").unwrap();
+    let result = spec.transform(&serde_json::json!({ "height": 5 }));
+    assert!(matches!(result, Err(JsonatrError::EvaluationError { .. })));
+}
+
+#[test]
+fn test_cast() {
+    test_expect(
+        "tests/support/builtins_cast.json",
+        r#"{
+  "height": 42,
+  "catching_up": false,
+  "has_log": true,
+  "events": [
+    {
+      "events": [
+        {
+          "type": "transfer"
+        }
+      ]
+    }
+  ],
+  "height_label": "43",
+  "json": "{\"height\":\"42\",\"catching_up\":\"false\",\"log\":\"[{\\\"events\\\":[{\\\"type\\\":\\\"transfer\\\"}]}]\"}"
+}"#,
+    );
+}
+
+#[test]
+fn test_default() {
+    test_expect(
+        "tests/support/builtins_default.json",
+        r#"{
+  "height": 42,
+  "round": 0,
+  "evidence": "none",
+  "proposer": [
+    42
+  ]
+}"#,
+    );
+}
+
+#[test]
+fn test_contains() {
+    test_expect(
+        "tests/support/builtins_contains.json",
+        r#"{
+  "signed_by_a": true,
+  "signed_by_b": "absent",
+  "is_test": true,
+  "has_height": true,
+  "has_time": false
+}"#,
+    );
+}
+
+#[test]
+fn test_find() {
+    test_expect(
+        "tests/support/builtins_find.json",
+        r#"{
+  "first_jailed": {
+    "address": "B",
+    "power": 50,
+    "jailed": true
+  },
+  "first_strong": {
+    "address": "B",
+    "power": 50,
+    "jailed": true
+  },
+  "commit_index": 3,
+  "timeout_index": null
+}"#,
+    );
+}
+
+#[test]
+fn test_nth() {
+    test_expect(
+        "tests/support/builtins_nth.json",
+        r#"{
+  "first": 1,
+  "last": 4,
+  "second": 2,
+  "second_to_last": 3,
+  "out_of_range": null,
+  "selection": 3
+}"#,
+    );
+}
+
+#[test]
+fn test_chunk() {
+    test_expect(
+        "tests/support/builtins_chunk.json",
+        r#"{
+  "chunks": [
+    [
+      "e1",
+      "e2"
+    ],
+    [
+      "e3",
+      "e4"
+    ],
+    [
+      "e5"
+    ]
+  ],
+  "windows": [
+    [
+      "e1",
+      "e2",
+      "e3",
+      "e4"
+    ],
+    [
+      "e2",
+      "e3",
+      "e4",
+      "e5"
+    ]
+  ]
+}"#,
+    );
+}
+
+#[test]
+fn test_reverse() {
+    test_expect(
+        "tests/support/builtins_reverse.json",
+        r#"{
+  "heights": [
+    1,
+    2,
+    3
+  ],
+  "hash": "CBA"
+}"#,
+    );
+}
+
+#[test]
+fn test_base64() {
+    test_expect(
+        "tests/support/builtins_base64.json",
+        r#"{
+  "standard": "anNvbmF0cj8/Pg==",
+  "url": "anNvbmF0cj8_Pg==",
+  "decoded": "jsonatr??>",
+  "decoded_url": "jsonatr??>"
+}"#,
+    );
+}
+
+#[test]
+fn test_hex() {
+    test_expect(
+        "tests/support/builtins_hex.json",
+        r#"{
+  "chain_id": "74657374",
+  "chain_id_upper": "74657374",
+  "decoded": "test",
+  "hash_bytes": [
+    94,
+    139,
+    58,
+    255
+  ],
+  "hash_base64": "Xos6/w==",
+  "hash_hex": "5E8B3AFF"
+}"#,
+    );
+}
+
+#[test]
+fn test_hash() {
+    test_expect(
+        "tests/support/builtins_hash.json",
+        r#"{
+  "sha256": "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad",
+  "sha256_base64": "ungWv48Bz+pBQUDeXa4iI7ADYaOWF3qctBD/YfIAFa0=",
+  "sha512": "ddaf35a193617abacc417349ae20413112e6fa4e89a97ea20a9eeee64b55d39a2192992a274fc1a836ba3c23a3feebbd454d4423643ce80e2a9ac94fa54ca49f",
+  "ripemd160": "8eb208f7e05d987a9b044a8e98c6b087f15a0bfc",
+  "header": "06f4845a6056ad2068400b53a77ce00716d0a17394ac3448ca3fce85c8dc4664",
+  "header_reordered": "06f4845a6056ad2068400b53a77ce00716d0a17394ac3448ca3fce85c8dc4664"
+}"#,
+    );
+}
+
+#[test]
+fn test_uuid() {
+    let res: Value =
+        serde_json::from_str(&transform_file("tests/support/builtins_uuid.json")).unwrap();
+    let random = res["random"].as_str().unwrap();
+    assert_eq!(random.len(), 36);
+    assert_eq!(&random[14..15], "4");
+    assert_eq!(res["from_address"], "559aead0-8264-4579-9d39-09718cdd05ab");
+    assert_eq!(res["from_string"], res["from_address"]);
+    assert_eq!(res["from_value"], "96db9c43-b6ec-495a-83cf-834b3925c815");
+}
+
+#[test]
+fn test_register_builtin() {
+    let spec = r#"{
+  "input": [
+    { "name": "heights", "kind": "INLINE", "source": [1, 2, 3] }
+  ],
+  "output": {
+    "scaled": "$heights | scale",
+    "offset": "$heights | shift(10)",
+    "unwrap": "$heights | unwrap"
+  }
+}"#;
+    let factor = 100;
+    let mut spec = Transformer::new(spec, ".").unwrap();
+    spec.register_builtin("scale", move |_, v, _| {
+        let scaled = v.as_array()?.iter().map(|x| x.as_i64().map(|x| x * factor));
+        Some(Value::from(scaled.collect::<Option<Vec<i64>>>()?))
+    })
+    .unwrap();
+    spec.register_builtin("shift", |t, v, args| {
+        let offset = t.eval_arg(args.first()?, &v)?.as_i64()?;
+        let shifted = v.as_array()?.iter().map(|x| x.as_i64().map(|x| x + offset));
+        Some(Value::from(shifted.collect::<Option<Vec<i64>>>()?))
+    })
+    .unwrap();
+    spec.register_builtin("unwrap", |_, _, _| Some(Value::from("custom")))
+        .unwrap();
+    assert!(spec.register_builtin("heights", |_, v, _| Some(v)).is_err());
+    let res: Value = serde_json::from_str(&spec.transform(&Value::Null).unwrap()).unwrap();
+    assert_eq!(
+        res,
+        serde_json::json!({ "scaled": [100, 200, 300], "offset": [11, 12, 13], "unwrap": "custom" })
+    );
+}
+
+#[test]
+fn test_uuid_seed() {
+    let input = std::fs::read_to_string("tests/support/builtins_uuid.json").unwrap();
+    let mut spec = Transformer::new(&input, "tests/support/builtins_uuid.json").unwrap();
+    spec.set_seed(Some(42));
+    let res = spec.transform_value_root(&Value::Null).unwrap();
+    assert_eq!(spec.transform_value_root(&Value::Null).unwrap(), res);
+    assert_eq!(res["from_address"], "559aead0-8264-4579-9d39-09718cdd05ab");
+    spec.set_seed(Some(43));
+    let other = spec.transform_value_root(&Value::Null).unwrap();
+    assert_ne!(other["random"], res["random"]);
+}
+
+#[test]
+fn test_arities() {
+    // a piped value for each standard builtin, and arguments up to the most it accepts
+    let samples: Vec<(&str, Value, &[&str])> = vec![
+        ("unwrap", serde_json::json!([1]), &[]),
+        ("map", serde_json::json!([1]), &["id"]),
+        ("ifelse", serde_json::json!(1), &["id", "id"]),
+        ("regex_extract", serde_json::json!("abc"), &["'(b)'", "1"]),
+        ("regex_replace", serde_json::json!("abc"), &["'b'", "'c'"]),
+        ("format", serde_json::json!(1), &["'{}'", "2"]),
+        ("add", serde_json::json!(6), &["2"]),
+        ("sub", serde_json::json!(6), &["2"]),
+        ("mul", serde_json::json!(6), &["2"]),
+        ("div", serde_json::json!(6), &["2"]),
+        ("mod", serde_json::json!(6), &["2"]),
+        ("sum", serde_json::json!([1, 2]), &[]),
+        ("avg", serde_json::json!([1, 2]), &[]),
+        ("min", serde_json::json!([1, 2]), &[]),
+        ("max", serde_json::json!([1, 2]), &[]),
+        ("min_by", serde_json::json!([{ "a": 1 }]), &["'a'"]),
+        ("max_by", serde_json::json!([{ "a": 1 }]), &["'a'"]),
+        ("round", serde_json::json!(1.5), &["1"]),
+        ("floor", serde_json::json!(1.5), &[]),
+        ("ceil", serde_json::json!(1.5), &[]),
+        ("to_fixed", serde_json::json!(1.5), &["1"]),
+        ("type", serde_json::json!(1), &[]),
+        (
+            "assert_type",
+            serde_json::json!(1),
+            &["'number'", "'string'"],
+        ),
+        ("to_number", serde_json::json!("1"), &[]),
+        ("to_string", serde_json::json!(1), &[]),
+        ("to_bool", serde_json::json!(1), &[]),
+        ("parse_json", serde_json::json!("1"), &[]),
+        ("to_json", serde_json::json!(1), &[]),
+        ("default", serde_json::json!(null), &["1"]),
+        ("contains", serde_json::json!([1]), &["1"]),
+        ("has", serde_json::json!({ "a": 1 }), &["'a'"]),
+        ("find", serde_json::json!([1]), &["id"]),
+        ("index_of", serde_json::json!([1]), &["1"]),
+        ("first", serde_json::json!([1]), &[]),
+        ("last", serde_json::json!([1]), &[]),
+        ("nth", serde_json::json!([1]), &["0"]),
+        ("chunk", serde_json::json!([1, 2]), &["1"]),
+        ("window", serde_json::json!([1, 2]), &["1"]),
+        ("reverse", serde_json::json!([1]), &[]),
+        ("base64_encode", serde_json::json!("a"), &["'url'"]),
+        ("base64_decode", serde_json::json!("YWJj"), &["'url'"]),
+        ("hex_encode", serde_json::json!("a"), &["'upper'"]),
+        ("hex_decode", serde_json::json!("61"), &[]),
+        ("sha256", serde_json::json!("a"), &["'base64'"]),
+        ("sha512", serde_json::json!("a"), &["'base64'"]),
+        ("ripemd160", serde_json::json!("a"), &["'base64'"]),
+        ("uuid", serde_json::json!("a"), &["'a'"]),
+        ("now", serde_json::json!(null), &["'%Y'", "'utc'"]),
+    ];
+    let builtins = Transformer::empty("");
+    assert_eq!(samples.len(), builtins.builtins().len());
+    for (name, value, args) in &samples {
+        let (min, max) = builtins.builtin_arity(name).unwrap();
+        if cfg!(not(feature = "regex")) && name.starts_with("regex_") {
+            continue;
+        }
+        // every number of arguments allowed by the arity should be accepted by the builtin
+        for count in min..=max.unwrap_or(args.len()) {
+            let args = args
+                .get(..count)
+                .unwrap_or_else(|| panic!("'{}' doesn't accept {} arguments", name, count));
+            let expr = format!("$v | {}({})", name, args.join(", "));
+            let mut spec = Transformer::empty("");
+            spec.add_input(Input::inline("v", value.clone())).unwrap();
+            spec.add_input(Input::inline("id", Value::from("$")))
+                .unwrap();
+            spec.set_strict(true);
+            spec.add_output(Value::String(expr.clone())).unwrap();
+            assert!(spec.validate().is_empty(), "{}", expr);
+            assert!(spec.transform_value_root(&Value::Null).is_ok(), "{}", expr);
+        }
+    }
+    let spec = Transformer::new(r#"{ "output": "$ | min(1)" }"#, "").unwrap();
+    assert_eq!(spec.validate().len(), 1);
+    // the arity is checked when the builtin is called as well
+    let mut spec = Transformer::new(r#"{ "output": "$ | min(1)" }"#, "").unwrap();
+    spec.set_strict(true);
+    assert_eq!(
+        spec.transform_value_root(&serde_json::json!([1, 2]))
+            .unwrap_err()
+            .to_string(),
+        "wrong number of arguments (1) of builtin 'min' at '' in expression '$ | min(1)'"
+    );
+}
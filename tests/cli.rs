@@ -0,0 +1,698 @@
+use std::io::Write;
+use std::process::{Command, Output, Stdio};
+
+fn run_cli(args: &[&str], stdin: &str) -> Output {
+    let mut process = Command::new(env!("CARGO_BIN_EXE_jsonatr"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    // the process may exit without reading STDIN, e.g. on wrong arguments
+    let _ = process.stdin.take().unwrap().write_all(stdin.as_bytes());
+    process.wait_with_output().unwrap()
+}
+
+fn stdout_of(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stdout)
+        .trim_end()
+        .to_string()
+}
+
+#[test]
+fn test_stdin_documents() {
+    let expect = r#"{
+  "first": 1,
+  "second": 2,
+  "heights": [
+    1,
+    2,
+    3
+  ]
+}"#;
+    let output = run_cli(
+        &["--use", "tests/support/simple_with_stdin.json"],
+        "{\"height\": 1}\n{\"height\": 2}\n{\"height\": 3}\n",
+    );
+    assert_eq!(stdout_of(&output), expect);
+    let output = run_cli(
+        &["--use", "tests/support/simple_with_stdin.json"],
+        "\u{1e}{\"height\": 1}\n\u{1e}{\"height\": 2}\n\u{1e}{\"height\": 3}\n",
+    );
+    assert_eq!(stdout_of(&output), expect);
+}
+
+#[test]
+fn test_set_inputs() {
+    let output = run_cli(
+        &[
+            "--set",
+            "height=5",
+            "--set",
+            r#"config={"fast": true}"#,
+            r#"{ "height": "$height", "fast": "$config.fast | unwrap" }"#,
+        ],
+        "",
+    );
+    assert_eq!(
+        stdout_of(&output),
+        "{\n  \"height\": 5,\n  \"fast\": true\n}"
+    );
+    let output = run_cli(&["--set", "height", "$height"], "");
+    assert!(!output.status.success());
+    // the values are data, which is never evaluated as expressions
+    let output = run_cli(
+        &[
+            "--set",
+            r#"home="$HOME""#,
+            "--set",
+            r#"path={"x": "$.x"}"#,
+            "--compact",
+            r#"{ "home": "$home", "path": "$path" }"#,
+        ],
+        "",
+    );
+    assert_eq!(stdout_of(&output), r#"{"home":"$HOME","path":{"x":"$.x"}}"#);
+}
+
+#[test]
+fn test_jq_args() {
+    let output = run_cli(
+        &[
+            "--arg",
+            "name",
+            "node0",
+            "--argjson",
+            "peers",
+            r#"["node1", "node2"]"#,
+            r#"{ "name": "$name", "first": "$peers | nth(0)" }"#,
+        ],
+        "",
+    );
+    assert_eq!(
+        stdout_of(&output),
+        "{\n  \"name\": \"node0\",\n  \"first\": \"node1\"\n}"
+    );
+    let output = run_cli(
+        &[
+            "--strict",
+            "--compact",
+            "--arg",
+            "price",
+            "$5",
+            "--arg",
+            "name",
+            "$.x",
+            "--argjson",
+            "list",
+            r#"["$price"]"#,
+            "--stdin",
+            r#"{ "price": "$price", "name": "$name", "list": "$list" }"#,
+        ],
+        r#"{ "x": 42 }"#,
+    );
+    assert!(output.status.success());
+    assert_eq!(
+        stdout_of(&output),
+        r#"{"price":"$5","name":"$.x","list":["$price"]}"#
+    );
+}
+
+#[test]
+fn test_check() {
+    let output = run_cli(&["--check", "--use", "tests/support/simple.json"], "");
+    assert!(output.status.success());
+    assert_eq!(stdout_of(&output), "");
+    let output = run_cli(
+        &[
+            "--validate",
+            r#"{ "a": "$missing", "b": "$ | command_never_run" }"#,
+        ],
+        "",
+    );
+    assert_eq!(output.status.code(), Some(2));
+    assert_eq!(
+        String::from_utf8_lossy(&output.stderr),
+        "output: unknown input 'missing' in '$missing'\n\
+         output: unknown builtin or input 'command_never_run' in '$ | command_never_run'\n\
+         Error: failed to parse spec, found 2 problem(s)\n"
+    );
+}
+
+#[test]
+fn test_formatting() {
+    let spec = r#"{ "b": [1, 2], "a": "$" }"#;
+    let output = run_cli(&["--compact", "--sort-keys", "--stdin", spec], "0");
+    assert_eq!(stdout_of(&output), r#"{"a":0,"b":[1,2]}"#);
+    let output = run_cli(&["--indent", "4", spec], "");
+    assert_eq!(
+        stdout_of(&output),
+        "{\n    \"b\": [\n        1,\n        2\n    ],\n    \"a\": \"$\"\n}"
+    );
+}
+
+#[test]
+fn test_output_format() {
+    let spec = r#"{ "name": "node0", "peers": [1, 2] }"#;
+    let output = run_cli(&["--format", "toml", spec], "");
+    assert_eq!(
+        stdout_of(&output),
+        "name = \"node0\"\npeers = [\n    1,\n    2,\n]"
+    );
+    let output = run_cli(
+        &["--format", "ndjson", "--stdin", r#""$""#],
+        r#"[{"a": 1}, {"b": 2}]"#,
+    );
+    assert_eq!(stdout_of(&output), "{\"a\":1}\n{\"b\":2}");
+    let output = run_cli(&["--format", "yaml", spec], "");
+    assert!(output.status.success());
+    assert_eq!(stdout_of(&output), "name: node0\npeers:\n- 1\n- 2");
+    let output = run_cli(&["--format", "xml", spec], "");
+    assert!(!output.status.success());
+}
+
+#[test]
+fn test_raw_output() {
+    let spec = r#""$.version | unwrap""#;
+    let input = r#"{ "version": "v0.1 \"beta\"" }"#;
+    let output = run_cli(&["-r", "--stdin", spec], input);
+    assert_eq!(stdout_of(&output), "v0.1 \"beta\"");
+    let output = run_cli(&["--raw-output", "--stdin", spec], input);
+    assert_eq!(stdout_of(&output), "v0.1 \"beta\"");
+    let output = run_cli(&["--stdin", spec], input);
+    assert_eq!(stdout_of(&output), r#""v0.1 \"beta\"""#);
+}
+
+#[test]
+fn test_named_inputs() {
+    let output = run_cli(
+        &[
+            "--in",
+            "store=tests/support/store.json",
+            "--in",
+            "tests/support/store.json",
+            "--compact",
+            r#"{ "same": "$store.store.bicycle.color | unwrap", "main": "$.store.bicycle.color | unwrap" }"#,
+        ],
+        "",
+    );
+    assert_eq!(stdout_of(&output), r#"{"same":"red","main":"red"}"#);
+    // the strings in the named input files are data, not expressions
+    let file = std::env::temp_dir().join(format!("jsonatr_named_{}.json", std::process::id()));
+    std::fs::write(&file, r#"{ "note": "$.x costs" }"#).unwrap();
+    let output = run_cli(
+        &[
+            "--in",
+            &format!("d={}", file.display()),
+            "--stdin",
+            "--compact",
+            r#""$d""#,
+        ],
+        r#"{ "x": 42 }"#,
+    );
+    std::fs::remove_file(file).unwrap();
+    assert_eq!(stdout_of(&output), r#"{"note":"$.x costs"}"#);
+    let output = run_cli(
+        &["--stdin", "--in", "tests/support/store.json", r#""$""#],
+        "{}",
+    );
+    assert!(!output.status.success());
+}
+
+#[test]
+fn test_spec_from_stdin() {
+    let spec = r#"{
+        "input": [ { "name": "store", "kind": "FILE", "source": "tests/support/store.json" } ],
+        "output": "$store.store.bicycle.color | unwrap"
+    }"#;
+    let output = run_cli(&["--spec", "-"], spec);
+    assert_eq!(stdout_of(&output), r#""red""#);
+    let output = run_cli(&["--spec", "-", "--stdin"], spec);
+    assert!(!output.status.success());
+}
+
+#[test]
+fn test_exit_codes() {
+    let output = run_cli(&["{ not json"], "");
+    assert_eq!(output.status.code(), Some(2));
+    assert_eq!(stdout_of(&output), "");
+    assert!(String::from_utf8_lossy(&output.stderr).starts_with("Error: failed to parse JSON"));
+    let output = run_cli(&["--in", "tests/support/missing.json", r#""$""#], "");
+    assert_eq!(output.status.code(), Some(4));
+    let output = run_cli(&["--use", "tests/support/simple.json", "--check"], "");
+    assert_eq!(output.status.code(), Some(0));
+}
+
+#[test]
+fn test_error_report() {
+    let output = run_cli(&[r#"{ "a": 1 "b": 2 }"#], "");
+    assert_eq!(
+        String::from_utf8_lossy(&output.stderr),
+        "Error: failed to parse JSON, expected `,` or `}` at line 1 column 10\n  \
+         --> output spec:1:10\n  |\n1 | { \"a\": 1 \"b\": 2 }\n  |          ^\n\
+         hint: a comma may be missing between the elements, or a bracket left unclosed before this place\n"
+    );
+    let output = run_cli(
+        &[
+            "--set",
+            "h=1",
+            "--strict",
+            r#"{ "a": { "b": "$h | add('x')" } }"#,
+        ],
+        "",
+    );
+    assert_eq!(output.status.code(), Some(3));
+    let report = String::from_utf8_lossy(&output.stderr);
+    let report: Vec<&str> = report
+        .lines()
+        .skip_while(|line| !line.starts_with("  -->"))
+        .collect();
+    assert_eq!(
+        report,
+        [
+            "  --> output /a/b",
+            "  |",
+            "  | $h | add('x')",
+            "  |      ^^^",
+            "hint: check the types of the piped value and of the arguments; --explain shows the intermediate values",
+        ]
+    );
+    let output = run_cli(&["--strict", r#"{ "a": "$nothing" }"#], "");
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr)
+        .contains("Error: found reference to unknown input 'nothing'\nhint: define the input"));
+    // the errors in spec files are shown in the file they come from
+    let path = std::env::temp_dir().join(format!("jsonatr-error-{}.json", std::process::id()));
+    std::fs::write(&path, "{\n  \"output\": 1,\n}\n").unwrap();
+    let output = run_cli(&["--spec", path.to_str().unwrap()], "");
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).contains(&format!(
+        "  --> {}:3:1\n  |\n3 | }}\n  | ^\nhint: JSON doesn't allow a comma",
+        path.display()
+    )));
+    let output = run_cli(&["--color", "always", "{"], "");
+    assert!(String::from_utf8_lossy(&output.stderr).starts_with("\x1b[1;31mError\x1b[0m: "));
+}
+
+#[test]
+fn test_assert_type() {
+    let spec = r#"{ "height": "$.height | unwrap | assert_type('string')" }"#;
+    let output = run_cli(&["--stdin", spec], r#"{ "height": 5 }"#);
+    assert_eq!(output.status.code(), Some(3));
+    assert_eq!(stdout_of(&output), "");
+    let output = run_cli(&["--stdin", spec], r#"{ "height": "5" }"#);
+    assert!(output.status.success());
+}
+
+#[test]
+fn test_strict() {
+    let spec = r#"{ "ok": "$.height | unwrap", "missing": "$nothing" }"#;
+    let output = run_cli(&["--stdin", "--compact", spec], r#"{ "height": 5 }"#);
+    assert_eq!(stdout_of(&output), r#"{"ok":5,"missing":"$nothing"}"#);
+    let output = run_cli(&["--stdin", "--strict", spec], r#"{ "height": 5 }"#);
+    assert_eq!(output.status.code(), Some(2));
+    assert_eq!(stdout_of(&output), "");
+}
+
+#[test]
+fn test_timeout() {
+    let spec = r#"{
+  "input": [{ "name": "slow", "kind": "COMMAND", "source": "sleep 10" }],
+  "output": "$slow"
+}"#;
+    let start = std::time::Instant::now();
+    let output = run_cli(&["--spec", "-", "--timeout", "0.2", "--strict"], spec);
+    assert!(start.elapsed() < std::time::Duration::from_secs(5));
+    assert_eq!(output.status.code(), Some(3));
+    for timeout in ["-1", "0", "1e30"] {
+        let output = run_cli(&["--spec", "-", "--timeout", timeout], spec);
+        assert_eq!(output.status.code(), Some(1));
+        assert!(String::from_utf8_lossy(&output.stderr).contains("--timeout expects"));
+    }
+}
+
+#[test]
+fn test_restrictions() {
+    let command = r#"{
+  "input": [{ "name": "id", "kind": "COMMAND", "source": "echo 1" }],
+  "output": "$id"
+}"#;
+    assert_eq!(stdout_of(&run_cli(&["--spec", "-"], command)), "1");
+    let output = run_cli(&["--spec", "-", "--no-commands"], command);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("not allowed in the sandbox"));
+
+    let files = |source: &str, kind: &str| {
+        format!(
+            r#"{{ "input": [{{ "name": "f", "kind": "{}", "source": "{}" }}], "output": "$f" }}"#,
+            kind, source
+        )
+    };
+    let inside = files("tests/support/store.json", "FILE");
+    let output = run_cli(&["--spec", "-", "--no-files", "--strict"], &inside);
+    assert!(output.status.success());
+    for outside in [files("/etc/hostname", "FILE"), files("/etc/host*", "GLOB")] {
+        let output = run_cli(&["--spec", "-", "--no-files", "--strict"], &outside);
+        assert_eq!(output.status.code(), Some(1));
+        assert!(String::from_utf8_lossy(&output.stderr).contains("is outside of the directory"));
+    }
+
+    // the outputs of the spec are confined as well, including the directories yet to be created
+    let writes = |file: &str| {
+        format!(
+            r#"{{ "outputs": [{{ "name": "o", "output": 1, "file": "{}" }}] }}"#,
+            file
+        )
+    };
+    let inside = format!("target/jsonatr-confined-{}/out.json", std::process::id());
+    let output = run_cli(&["--spec", "-", "--no-files"], &writes(&inside));
+    assert!(output.status.success());
+    assert_eq!(std::fs::read_to_string(&inside).unwrap(), "1");
+    std::fs::remove_dir_all(std::path::Path::new(&inside).parent().unwrap()).unwrap();
+    let outside = std::env::temp_dir().join(format!("jsonatr-outside-{}", std::process::id()));
+    let outside = outside.join("out.json");
+    let escaping = format!("target/../../{}", outside.display());
+    for file in [outside.to_str().unwrap(), &escaping] {
+        let output = run_cli(&["--spec", "-", "--no-files"], &writes(file));
+        assert_eq!(output.status.code(), Some(1));
+        assert!(String::from_utf8_lossy(&output.stderr).contains("is outside of the directory"));
+        assert!(!outside.exists());
+    }
+}
+
+#[test]
+fn test_include_path() {
+    let spec = r#"{
+  "input": [{ "name": "store", "kind": "FILE", "source": "store.json" }],
+  "output": "$store.hours.open | unwrap"
+}"#;
+    let output = run_cli(&["--spec", "-", "--strict"], spec);
+    assert_eq!(output.status.code(), Some(4));
+    let output = run_cli(&["--spec", "-", "--include-path", "tests/support"], spec);
+    assert_eq!(stdout_of(&output), "10");
+    let output = run_cli(
+        &[
+            "--include-path",
+            "tests/support",
+            "--use",
+            "simple_with_toml.json",
+            "-r",
+        ],
+        "",
+    );
+    assert!(output.status.success());
+
+    let mut process = Command::new(env!("CARGO_BIN_EXE_jsonatr"))
+        .args(["--spec", "-"])
+        .env("JSONATR_PATH", "tests/support")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    process
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(spec.as_bytes())
+        .unwrap();
+    assert_eq!(stdout_of(&process.wait_with_output().unwrap()), "10");
+}
+
+#[test]
+fn test_verbose_and_trace() {
+    let spec = r#"{ "height": "$height | add(1)" }"#;
+    let output = run_cli(&["--set", "height=5", "--verbose", spec], "");
+    assert_eq!(
+        String::from_utf8_lossy(&output.stderr),
+        "Debug: evaluating input 'height' of kind LITERAL\n"
+    );
+    let output = run_cli(&["--set", "height=5", "--trace", spec], "");
+    assert_eq!(
+        String::from_utf8_lossy(&output.stderr),
+        "Trace: evaluating expression '$height | add(1)'\n\
+         Debug: evaluating input 'height' of kind LITERAL\n\
+         Trace: expression '$height | add(1)' = 6\n"
+    );
+}
+
+#[test]
+fn test_profile() {
+    let spec = r#"{ "a": "$height | add(1)", "b": "$height" }"#;
+    let output = run_cli(&["--set", "height=5", "--profile", spec], "");
+    assert!(output.status.success());
+    let report = String::from_utf8_lossy(&output.stderr);
+    let lines: Vec<&str> = report.lines().collect();
+    assert_eq!(lines.len(), 4);
+    assert_eq!(lines[0], "Profile (slowest first):");
+    for label in [
+        " ms  input 'height' of kind LITERAL",
+        " ms  expression '$height | add(1)'",
+        " ms  expression '$height'",
+    ] {
+        assert!(lines.iter().any(|line| line.ends_with(label)));
+    }
+    // the report is only printed after a successful run
+    let output = run_cli(&["--profile", "--strict", spec], "");
+    assert!(!output.status.success());
+    assert!(!String::from_utf8_lossy(&output.stderr).contains("Profile"));
+}
+
+#[test]
+fn test_explain() {
+    let spec = r#"{ "a": { "b": "$height | add(1)" }, "c": "const" }"#;
+    let output = run_cli(&["--set", "height=5", "--explain", "--compact", spec], "");
+    assert!(output.status.success());
+    assert_eq!(stdout_of(&output), r#"{"a":{"b":6},"c":"const"}"#);
+    assert_eq!(
+        String::from_utf8_lossy(&output.stderr),
+        "/a/b: $height | add(1)\n  input 'height' of kind LITERAL\n    = 5\n  resolved to 5\n  \
+         | add(1) => 6\n  = 6\n"
+    );
+}
+
+#[test]
+fn test_completions() {
+    let output = run_cli(&["completions", "bash"], "");
+    let script = stdout_of(&output);
+    assert!(script.contains(" --use --spec --stdin --in "));
+    assert!(script.contains(" -r --raw-output "));
+    let output = run_cli(&["completions", "fish"], "");
+    assert!(stdout_of(&output).contains("complete -c jsonatr -l raw-output -s r -d"));
+    let output = run_cli(&["completions", "zsh"], "");
+    assert!(stdout_of(&output).starts_with("#compdef jsonatr"));
+    let output = run_cli(&["completions", "cmd"], "");
+    assert!(!output.status.success());
+}
+
+#[test]
+fn test_repl() {
+    let output = run_cli(
+        &["--repl", "--compact", "--in", "tests/support/store.json"],
+        "$.store.bicycle\n\n$.store.bicycle.color | unwrap\n$nothing\n$.store.bicycle.price | unwrap | round\n",
+    );
+    assert!(output.status.success());
+    assert_eq!(
+        stdout_of(&output),
+        "[{\"color\":\"red\",\"price\":19.95}]\n\"red\"\n20"
+    );
+    assert!(String::from_utf8_lossy(&output.stderr)
+        .contains("Error: unknown input 'nothing' in '$nothing'"));
+}
+
+#[test]
+fn test_batch() {
+    let dir = std::env::temp_dir().join(format!("jsonatr_batch_{}", std::process::id()));
+    std::fs::create_dir_all(dir.join("traces")).unwrap();
+    for height in 1..4 {
+        std::fs::write(
+            dir.join(format!("traces/trace{}.json", height)),
+            format!("{{ \"height\": {} }}", height),
+        )
+        .unwrap();
+    }
+    let pattern = dir.join("traces/*.json");
+    let template = dir.join("out/{stem}_test.json");
+    let spec = r#"{ "next": "$.height | unwrap | add(1)" }"#;
+    let output = run_cli(
+        &[
+            "--in-glob",
+            pattern.to_str().unwrap(),
+            "--out-template",
+            template.to_str().unwrap(),
+            "--compact",
+            spec,
+        ],
+        "",
+    );
+    assert!(output.status.success());
+    for height in 1..4 {
+        let file = dir.join(format!("out/trace{}_test.json", height));
+        assert_eq!(
+            std::fs::read_to_string(file).unwrap(),
+            format!("{{\"next\":{}}}", height + 1)
+        );
+    }
+    let output = run_cli(
+        &["--in-glob", pattern.to_str().unwrap(), "--compact", spec],
+        "",
+    );
+    assert_eq!(
+        stdout_of(&output),
+        "{\"next\":2}\n{\"next\":3}\n{\"next\":4}"
+    );
+    let output = run_cli(
+        &[
+            "--in-glob",
+            pattern.to_str().unwrap(),
+            "--jobs",
+            "2",
+            "--compact",
+            spec,
+        ],
+        "",
+    );
+    assert_eq!(
+        stdout_of(&output),
+        "{\"next\":2}\n{\"next\":3}\n{\"next\":4}"
+    );
+    std::fs::write(dir.join("traces/trace2.json"), "{").unwrap();
+    let output = run_cli(
+        &["--in-glob", pattern.to_str().unwrap(), "--jobs", "2", spec],
+        "",
+    );
+    assert!(!output.status.success());
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_query() {
+    let output = run_cli(
+        &[
+            "--query",
+            "$.store.books[*].price | max",
+            "--in",
+            "tests/support/store.json",
+        ],
+        "",
+    );
+    assert_eq!(stdout_of(&output), "22.99");
+    let output = run_cli(
+        &["-r", "--query", "$.name | unwrap", "--stdin"],
+        r#"{"name": "node0"}"#,
+    );
+    assert_eq!(stdout_of(&output), "node0");
+}
+
+#[test]
+fn test_expect() {
+    let dir = std::env::temp_dir().join(format!("jsonatr_expect_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let expected = dir.join("expected.json");
+    let output = run_cli(&["--use", "tests/support/simple.json"], "");
+    std::fs::write(&expected, &output.stdout).unwrap();
+    let args = [
+        "--use",
+        "tests/support/simple.json",
+        "--expect",
+        expected.to_str().unwrap(),
+    ];
+    let output = run_cli(&args, "");
+    assert!(output.status.success());
+    assert_eq!(stdout_of(&output), "");
+
+    let mut value: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(&expected).unwrap()).unwrap();
+    value["extra/key"] = serde_json::json!(1);
+    std::fs::write(&expected, value.to_string()).unwrap();
+    let output = run_cli(&args, "");
+    assert!(!output.status.success());
+    assert_eq!(stdout_of(&output), "/extra~1key: missing 1");
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_out_path_template() {
+    let dir = std::env::temp_dir().join(format!("jsonatr_out_{}", std::process::id()));
+    let template = dir.join("{$.chain_id}-{$.height | unwrap}.json");
+    let output = run_cli(
+        &[
+            "--out",
+            template.to_str().unwrap(),
+            "--compact",
+            r#"{ "chain_id": "test-chain", "height": 5 }"#,
+        ],
+        "",
+    );
+    assert!(output.status.success());
+    assert_eq!(
+        std::fs::read_to_string(dir.join("test-chain-5.json")).unwrap(),
+        r#"{"chain_id":"test-chain","height":5}"#
+    );
+    let mut process = Command::new(env!("CARGO_BIN_EXE_jsonatr"))
+        .args([
+            "--out",
+            "${JSONATR_TEST_OUT}/{$.height | unwrap}.json",
+            "--compact",
+        ])
+        .arg(r#"{ "height": 6 }"#)
+        .env("JSONATR_TEST_OUT", &dir)
+        .stdin(Stdio::null())
+        .spawn()
+        .unwrap();
+    assert!(process.wait().unwrap().success());
+    assert_eq!(
+        std::fs::read_to_string(dir.join("6.json")).unwrap(),
+        r#"{"height":6}"#
+    );
+    let template = dir.join("{$.missing}.json");
+    let output = run_cli(
+        &["--out", template.to_str().unwrap(), r#"{ "height": 5 }"#],
+        "",
+    );
+    assert!(!output.status.success());
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_stream() {
+    let spec = r#"{ "next": "$.height | unwrap | add(1)" }"#;
+    let output = run_cli(
+        &["--stream", spec],
+        "{\"height\": 1}\n\n{\"height\": 2}\n{\"height\": 3}\n",
+    );
+    assert!(output.status.success());
+    assert_eq!(
+        stdout_of(&output),
+        "{\"next\":2}\n{\"next\":3}\n{\"next\":4}"
+    );
+    let output = run_cli(&["--stream", spec], "{\"height\": 1}\n{\n");
+    assert!(!output.status.success());
+    assert_eq!(stdout_of(&output), "{\"next\":2}");
+    let output = run_cli(&["--stream", "--stdin", spec], "");
+    assert!(!output.status.success());
+}
+
+#[test]
+fn test_subcommands() {
+    let output = run_cli(&["transform", "--compact", r#"{ "a": 1 }"#], "");
+    assert_eq!(stdout_of(&output), r#"{"a":1}"#);
+    let output = run_cli(&["validate", r#"{ "a": "$unknown" }"#], "");
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("unknown input 'unknown'"));
+    let output = run_cli(
+        &["query", "$.name | unwrap", "--stdin"],
+        r#"{ "name": "a" }"#,
+    );
+    assert_eq!(stdout_of(&output), r#""a""#);
+    let output = run_cli(&["query", "--stdin"], "{}");
+    assert!(!output.status.success());
+    let output = run_cli(&["fmt", "--sort-keys"], r#"{"b": [1],  "a": 1}"#);
+    assert_eq!(
+        stdout_of(&output),
+        "{\n  \"a\": 1,\n  \"b\": [\n    1\n  ]\n}"
+    );
+    let output = run_cli(&["--help"], "");
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Available commands:"));
+}
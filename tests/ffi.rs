@@ -0,0 +1,24 @@
+#![cfg(feature = "ffi")]
+
+use jsonatr::ffi::*;
+use std::ffi::{CStr, CString};
+
+#[test]
+fn test_ffi_transform() {
+    let spec = CString::new(r#"{ "output": { "height": "$.height | unwrap" } }"#).unwrap();
+    let input = CString::new(r#"{ "height": 5 }"#).unwrap();
+    unsafe {
+        let output = jsonatr_transform(spec.as_ptr(), input.as_ptr());
+        assert!(!output.is_null());
+        assert!(jsonatr_last_error().is_null());
+        let value: serde_json::Value =
+            serde_json::from_str(CStr::from_ptr(output).to_str().unwrap()).unwrap();
+        assert_eq!(value["height"], 5);
+        jsonatr_free(output);
+
+        let output = jsonatr_transform(input.as_ptr(), std::ptr::null());
+        assert!(output.is_null());
+        let error = CStr::from_ptr(jsonatr_last_error()).to_str().unwrap();
+        assert_eq!(error, "no output specified");
+    }
+}
@@ -0,0 +1,697 @@
+use jsonatr::builder::TransformerBuilder;
+use jsonatr::error::*;
+use jsonatr::output::*;
+use jsonatr::resolver::*;
+use jsonatr::transformer::*;
+use serde_json::Value;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpListener;
+
+fn transform_str(spec: &str) -> Value {
+    let spec = Transformer::new(spec, ".").unwrap();
+    serde_json::from_str(&spec.transform(&Value::Null).unwrap()).unwrap()
+}
+
+// serves the given number of HTTP requests, responding to each with a JSON description of the request
+fn serve_echo(requests: usize) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    std::thread::spawn(move || {
+        for stream in listener.incoming().take(requests) {
+            let mut stream = stream.unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut request_line = String::new();
+            reader.read_line(&mut request_line).unwrap();
+            let mut headers = serde_json::Map::new();
+            let mut length = 0;
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                let line = line.trim_end();
+                if line.is_empty() {
+                    break;
+                }
+                let (k, v) = line.split_once(": ").unwrap();
+                if k.eq_ignore_ascii_case("content-length") {
+                    length = v.parse().unwrap();
+                }
+                headers.insert(k.to_lowercase(), Value::String(v.to_string()));
+            }
+            let mut body = vec![0; length];
+            reader.read_exact(&mut body).unwrap();
+            let parts: Vec<&str> = request_line.split(' ').collect();
+            let response = serde_json::json!({
+                "method": parts[0],
+                "path": parts[1],
+                "authorization": headers.get("authorization"),
+                "x-chain": headers.get("x-chain"),
+                "body": String::from_utf8(body).unwrap(),
+            })
+            .to_string();
+            write!(
+                stream,
+                "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                response.len(),
+                response
+            )
+            .unwrap();
+        }
+    });
+    format!("http://{}", addr)
+}
+
+#[test]
+fn test_http() {
+    let url = serve_echo(2);
+    let spec = format!(
+        r#"{{
+  "input": [
+    {{ "name": "chain", "kind": "INLINE", "source": "test-chain" }},
+    {{
+      "name": "status",
+      "kind": "HTTP",
+      "source": "{url}/status",
+      "query": {{ "height": 5 }},
+      "headers": {{ "X-Chain": "$chain" }},
+      "auth": {{ "basic": {{ "user": "alice", "password": "secret" }} }}
+    }},
+    {{
+      "name": "rpc",
+      "kind": "HTTP",
+      "source": "{url}/",
+      "auth": {{ "bearer": "token" }},
+      "body": {{ "jsonrpc": "2.0", "method": "block", "params": {{ "chain": "$chain" }} }}
+    }}
+  ],
+  "output": {{
+    "status": "$status",
+    "rpc": "$rpc"
+  }}
+}}"#,
+        url = url
+    );
+    let res = transform_str(&spec);
+    assert_eq!(res["status"]["method"], "GET");
+    assert_eq!(res["status"]["path"], "/status?height=5");
+    assert_eq!(res["status"]["x-chain"], "test-chain");
+    assert_eq!(res["status"]["authorization"], "Basic YWxpY2U6c2VjcmV0");
+    assert_eq!(res["rpc"]["method"], "POST");
+    assert_eq!(res["rpc"]["authorization"], "Bearer token");
+    assert_eq!(
+        res["rpc"]["body"],
+        r#"{"jsonrpc":"2.0","method":"block","params":{"chain":"test-chain"}}"#
+    );
+}
+
+#[test]
+fn test_env() {
+    std::env::set_var("JSONATR_TEST_CHAIN", "test-chain");
+    let res = transform_str(
+        r#"{
+  "input": [
+    { "name": "all", "kind": "ENV" },
+    { "name": "chain", "kind": "ENV", "source": "JSONATR_TEST_CHAIN" },
+    { "name": "subset", "kind": "ENV", "source": ["JSONATR_TEST_CHAIN", "JSONATR_TEST_UNSET"] }
+  ],
+  "output": {
+    "all": "$all.JSONATR_TEST_CHAIN | unwrap",
+    "chain": "$chain",
+    "subset": "$subset"
+  }
+}"#,
+    );
+    assert_eq!(
+        res,
+        serde_json::json!({
+            "all": "test-chain",
+            "chain": "test-chain",
+            "subset": { "JSONATR_TEST_CHAIN": "test-chain", "JSONATR_TEST_UNSET": null }
+        })
+    );
+}
+
+#[test]
+fn test_command_env_and_cwd() {
+    let res = transform_str(
+        r#"{
+  "input": [
+    { "name": "chain", "kind": "INLINE", "source": "test-chain" },
+    {
+      "name": "env",
+      "kind": "COMMAND",
+      "source": "printenv JSONATR_CHAIN JSONATR_HEIGHT",
+      "env": { "JSONATR_CHAIN": "$chain", "JSONATR_HEIGHT": 42 }
+    },
+    { "name": "cwd", "kind": "COMMAND", "source": "pwd", "cwd": "tests/support" }
+  ],
+  "output": {
+    "env": "$env",
+    "cwd": "$cwd"
+  }
+}"#,
+    );
+    assert_eq!(res["env"], "test-chain\n42");
+    assert!(res["cwd"].as_str().unwrap().ends_with("tests/support"));
+}
+
+#[test]
+fn test_command_capture_full() {
+    let res = transform_str(
+        r#"{
+  "input": [
+    {
+      "name": "ok",
+      "kind": "COMMAND",
+      "source": "sh -c 'echo {\\\"height\\\": 1}'",
+      "capture": "full"
+    },
+    {
+      "name": "failed",
+      "kind": "COMMAND",
+      "source": "sh -c 'echo partial; echo oops >&2; exit 3'",
+      "capture": "full"
+    },
+    { "name": "ok_branch", "kind": "INLINE", "source": "ok" },
+    { "name": "failed_branch", "kind": "INLINE", "source": "failed" }
+  ],
+  "output": {
+    "ok": "$ok",
+    "failed": "$failed",
+    "status": "$failed.code | unwrap | ifelse(failed_branch, ok_branch)"
+  }
+}"#,
+    );
+    assert_eq!(
+        res,
+        serde_json::json!({
+            "ok": { "stdout": { "height": 1 }, "stderr": "", "code": 0 },
+            "failed": { "stdout": "partial", "stderr": "oops", "code": 3 },
+            "status": "failed"
+        })
+    );
+}
+
+#[test]
+fn test_command_timeout() {
+    let start = std::time::Instant::now();
+    let res = transform_str(
+        r#"{
+  "input": [
+    { "name": "fast", "kind": "COMMAND", "source": "echo 1", "timeout": 5 },
+    { "name": "slow", "kind": "COMMAND", "source": "sleep 10", "timeout": "200ms" }
+  ],
+  "output": {
+    "fast": "$fast",
+    "slow": "$slow"
+  }
+}"#,
+    );
+    assert!(start.elapsed() < std::time::Duration::from_secs(5));
+    assert_eq!(res, serde_json::json!({ "fast": 1, "slow": "$slow" }));
+
+    let res = transform_str(
+        r#"{
+  "input": [ { "name": "never", "kind": "COMMAND", "source": "echo 1", "timeout": 1e30 } ],
+  "output": { "never": "$never" }
+}"#,
+    );
+    assert_eq!(res, serde_json::json!({ "never": "$never" }));
+}
+
+#[test]
+fn test_command_lines() {
+    let start = std::time::Instant::now();
+    let res = transform_str(
+        r#"{
+  "input": [
+    {
+      "name": "finite",
+      "kind": "COMMAND",
+      "source": "printf '{\"height\": 1}\\n{\"height\": 2}\\nstarted\\n'",
+      "capture": "lines"
+    },
+    {
+      "name": "limited",
+      "kind": "COMMAND",
+      "source": "sh -c 'i=0; while true; do i=$((i+1)); echo $i; sleep 0.01; done'",
+      "capture": "lines",
+      "max_count": 3
+    },
+    {
+      "name": "timed",
+      "kind": "COMMAND",
+      "source": "sh -c 'echo 1; sleep 10'",
+      "capture": "lines",
+      "timeout": "300ms"
+    }
+  ],
+  "output": {
+    "finite": "$finite",
+    "limited": "$limited",
+    "timed": "$timed"
+  }
+}"#,
+    );
+    assert!(start.elapsed() < std::time::Duration::from_secs(5));
+    assert_eq!(
+        res,
+        serde_json::json!({
+            "finite": [{ "height": 1 }, { "height": 2 }, "started"],
+            "limited": [1, 2, 3],
+            "timed": [1]
+        })
+    );
+}
+
+#[test]
+fn test_git() {
+    let repo = std::env::temp_dir().join(format!("jsonatr-git-{}", std::process::id()));
+    std::fs::create_dir_all(&repo).unwrap();
+    let git = |args: &[&str]| {
+        let status = std::process::Command::new("git")
+            .args(["-c", "user.name=test", "-c", "user.email=test@example.com"])
+            .args(args)
+            .current_dir(&repo)
+            .output()
+            .unwrap()
+            .status;
+        assert!(status.success());
+    };
+    git(&["init", "-q"]);
+    std::fs::write(repo.join("config.json"), r#"{"version": "0.33"}"#).unwrap();
+    git(&["add", "config.json"]);
+    git(&["commit", "-q", "-m", "first"]);
+    git(&["tag", "v0.33"]);
+    std::fs::write(repo.join("config.json"), r#"{"version": "0.34"}"#).unwrap();
+    git(&["commit", "-q", "-a", "-m", "second"]);
+
+    let spec = format!(
+        r#"{{
+  "input": [
+    {{ "name": "old", "kind": "GIT", "source": {{ "repo": {repo}, "rev": "v0.33", "path": "config.json" }} }},
+    {{ "name": "new", "kind": "GIT", "source": {{ "repo": {repo}, "rev": "HEAD", "path": "config.json" }} }}
+  ],
+  "output": {{
+    "old": "$old.version | unwrap",
+    "new": "$new.version | unwrap"
+  }}
+}}"#,
+        repo = Value::String(repo.to_string_lossy().to_string())
+    );
+    let res = transform_str(&spec);
+    assert_eq!(res, serde_json::json!({ "old": "0.33", "new": "0.34" }));
+
+    // a revision from the data can't pass options to git, e.g. one writing a file
+    let written = repo.join("written");
+    let spec = format!(
+        r#"{{
+  "input": [
+    {{ "name": "file", "kind": "GIT", "source": {{ "repo": {repo}, "rev": "{{$.rev}}", "path": "config.json" }} }}
+  ],
+  "output": "$file.version | unwrap"
+}}"#,
+        repo = Value::String(repo.to_string_lossy().to_string())
+    );
+    let mut spec = Transformer::new(&spec, ".").unwrap();
+    spec.set_strict(true);
+    let rev = format!("--output={}", written.display());
+    assert!(spec
+        .transform_value_root(&serde_json::json!({ "rev": rev }))
+        .is_err());
+    assert!(!written.exists());
+    let res = spec.transform_value_root(&serde_json::json!({ "rev": "v0.33" }));
+    std::fs::remove_dir_all(&repo).unwrap();
+    assert_eq!(res.unwrap(), "0.33");
+}
+
+#[test]
+fn test_cache() {
+    let counter = std::env::temp_dir().join(format!("jsonatr-cache-{}", std::process::id()));
+    let spec = format!(
+        r#"{{
+  "input": [
+    {{ "name": "heights", "kind": "INLINE", "source": [1, 2, 1, 2, 1] }},
+    {{ "name": "uncached", "kind": "COMMAND", "source": "sh -c 'echo x >> {counter}.uncached; wc -l < {counter}.uncached'", "stdin": false }},
+    {{ "name": "once", "kind": "COMMAND", "source": "sh -c 'echo x >> {counter}.once; wc -l < {counter}.once'", "stdin": false, "cache": true }},
+    {{ "name": "per_root", "kind": "COMMAND", "source": "sh -c 'echo x >> {counter}.per_root; wc -l < {counter}.per_root'", "stdin": false, "cache": "per-root" }}
+  ],
+  "output": {{
+    "uncached": "$heights | map(uncached) | max",
+    "once": "$heights | map(once) | max",
+    "per_root": "$heights | map(per_root) | max"
+  }}
+}}"#,
+        counter = counter.to_string_lossy()
+    );
+    let res = transform_str(&spec);
+    for suffix in &["uncached", "once", "per_root"] {
+        std::fs::remove_file(format!("{}.{}", counter.to_string_lossy(), suffix)).unwrap();
+    }
+    assert_eq!(
+        res,
+        serde_json::json!({ "uncached": 5, "once": 1, "per_root": 2 })
+    );
+}
+
+#[test]
+fn test_cache_per_transformation() {
+    let file = std::env::temp_dir().join(format!("jsonatr-cache-{}.json", std::process::id()));
+    let spec = format!(
+        r#"{{
+  "input": [ {{ "name": "data", "kind": "FILE", "source": "{}", "cache": true }} ],
+  "output": {{ "height": "$data.height | unwrap", "again": "$data.height | unwrap" }}
+}}"#,
+        file.to_string_lossy()
+    );
+    let spec = Transformer::new(&spec, ".").unwrap();
+    std::fs::write(&file, r#"{ "height": 1 }"#).unwrap();
+    let first = spec.transform_value_root(&Value::Null).unwrap();
+    std::fs::write(&file, r#"{ "height": 2 }"#).unwrap();
+    let second = spec.transform_value_root(&Value::Null).unwrap();
+    let streamed: Vec<Value> = spec
+        .transform_iter(vec![Value::Null])
+        .unwrap()
+        .map(Result::unwrap)
+        .collect();
+    std::fs::remove_file(&file).unwrap();
+    assert_eq!(first, serde_json::json!({ "height": 1, "again": 1 }));
+    assert_eq!(second, serde_json::json!({ "height": 2, "again": 2 }));
+    assert_eq!(streamed, vec![second]);
+}
+
+#[test]
+fn test_input_constructors() {
+    let mut spec = Transformer::empty(".");
+    spec.add_input(Input::inline("heights", serde_json::json!([1, 2, 3])))
+        .unwrap();
+    spec.add_input(Input::file("version", "tests/support/version.json"))
+        .unwrap();
+    spec.add_input(
+        Input::command("greet", "echo hello {$who}")
+            .with_lets(serde_json::json!({ "who": "$heights | first" }))
+            .with_stdin(false)
+            .with_cache(Value::Bool(true)),
+    )
+    .unwrap();
+    spec.add_input(Input::env("home", Value::from("HOME")))
+        .unwrap();
+    spec.add_input(Input::literal(
+        "data",
+        serde_json::json!({ "price": "$5", "file": "{$.name}" }),
+    ))
+    .unwrap();
+    assert!(spec.add_input(Input::inline("greet", Value::Null)).is_err());
+    assert!(spec
+        .add_input(Input::inline("bad", Value::Null).with_lets(Value::from(1)))
+        .is_err());
+    spec.add_output(serde_json::json!({
+        "heights": "$heights",
+        "greeting": "$greet",
+        "home": "$home",
+        "data": "$data"
+    }))
+    .unwrap();
+    assert!(spec.validate().is_empty());
+    let res: Value = serde_json::from_str(&spec.transform(&Value::Null).unwrap()).unwrap();
+    assert_eq!(res["heights"], serde_json::json!([1, 2, 3]));
+    assert_eq!(res["greeting"], "hello 1");
+    assert_eq!(res["home"], std::env::var("HOME").unwrap());
+    assert_eq!(
+        res["data"],
+        serde_json::json!({ "price": "$5", "file": "{$.name}" })
+    );
+}
+
+#[test]
+fn test_resolver() {
+    let resolver = MemoryResolver::new()
+        .with_file(
+            "specs/lib.json",
+            r#"{ "input": [ { "name": "data", "kind": "FILE", "source": "specs/data.json" } ] }"#,
+        )
+        .with_file("specs/data.json", r#"{ "height": 3 }"#);
+    let spec = Transformer::new_with_resolver(
+        r#"{ "use": ["lib.json"], "output": "$data.height | unwrap" }"#,
+        "specs/main.json",
+        std::sync::Arc::new(resolver.with_file("specs/main.json", "{}")),
+    )
+    .unwrap();
+    assert_eq!(spec.transform_value_root(&Value::Null).unwrap(), 3);
+
+    let spec = Transformer::new(r#"{ "use": ["lib.json"] }"#, "specs/main.json");
+    assert!(matches!(spec, Err(JsonatrError::IoError { .. })));
+}
+
+#[test]
+fn test_include_path() {
+    let resolver = MemoryResolver::new()
+        .with_file(
+            "lib/common.json",
+            r#"{ "use": ["data.json"], "output": "$data.height | unwrap" }"#,
+        )
+        .with_file(
+            "lib/data.json",
+            r#"{ "input": [ { "name": "data", "kind": "FILE", "source": "height.json" } ] }"#,
+        )
+        .with_file("lib/height.json", r#"{ "height": 3 }"#);
+    let spec = TransformerBuilder::new()
+        .path("specs/main.json")
+        .resolver(std::sync::Arc::new(resolver.clone()))
+        .include_path("lib")
+        .use_file("common.json")
+        .build()
+        .unwrap();
+    assert_eq!(spec.transform_value_root(&Value::Null).unwrap(), 3);
+
+    let spec = TransformerBuilder::new()
+        .path("specs/main.json")
+        .resolver(std::sync::Arc::new(resolver))
+        .use_file("common.json")
+        .build();
+    assert!(matches!(spec, Err(JsonatrError::IoError { .. })));
+}
+
+#[test]
+fn test_env_expansion() {
+    std::env::set_var("JSONATR_TEST_DIR", "lib");
+    let resolver = MemoryResolver::new()
+        .with_file(
+            "lib/common.json",
+            r#"{ "input": [ { "name": "data", "kind": "FILE", "source": "${JSONATR_TEST_DIR}/data.json" } ] }"#,
+        )
+        .with_file("lib/data.json", r#"{ "height": 3 }"#)
+        .with_file("main.json", "{}");
+    let spec = Transformer::new_with_resolver(
+        r#"{ "use": ["${JSONATR_TEST_DIR}/common.json"], "output": "$data.height | unwrap" }"#,
+        "main.json",
+        std::sync::Arc::new(resolver.clone()),
+    )
+    .unwrap();
+    assert_eq!(spec.transform_value_root(&Value::Null).unwrap(), 3);
+
+    // only the variables written in the spec are expanded, not those in the values of placeholders
+    let mut spec = Transformer::new_with_resolver(
+        r#"{
+  "input": [ { "name": "data", "kind": "FILE", "source": "${JSONATR_TEST_DIR}/{$.file}" } ],
+  "output": "$data.height | unwrap"
+}"#,
+        "main.json",
+        std::sync::Arc::new(resolver.clone()),
+    )
+    .unwrap();
+    spec.set_strict(true);
+    let root = serde_json::json!({ "file": "data.json" });
+    assert_eq!(spec.transform_value_root(&root).unwrap(), 3);
+    let mut spec = Transformer::new_with_resolver(
+        r#"{ "input": [ { "name": "data", "kind": "FILE", "source": "{$.file}" } ], "output": "$data" }"#,
+        "main.json",
+        std::sync::Arc::new(resolver.clone()),
+    )
+    .unwrap();
+    spec.set_strict(true);
+    let root = serde_json::json!({ "file": "${JSONATR_TEST_DIR}/data.json" });
+    assert!(spec.transform_value_root(&root).is_err());
+
+    let spec = Transformer::new_with_resolver(
+        r#"{ "use": ["${JSONATR_TEST_UNSET}/common.json"] }"#,
+        "main.json",
+        std::sync::Arc::new(resolver),
+    );
+    assert!(spec.is_err());
+}
+
+#[test]
+fn test_sandbox() {
+    let command =
+        r#"{ "input": [ { "name": "id", "kind": "COMMAND", "source": "id" } ], "output": "$id" }"#;
+    let file = r#"{ "input": [ { "name": "f", "kind": "FILE", "source": "/etc/hostname" } ], "output": "$f" }"#;
+    let sandbox = Sandbox::default();
+    let strict_sandbox = Sandbox {
+        deny_files: true,
+        deny_http: true,
+        deny_env: true,
+    };
+    assert!(Transformer::new_sandboxed(command, ".", sandbox).is_err());
+    let env = r#"{ "input": [ { "name": "home", "kind": "ENV", "source": "HOME" } ], "output": "$home" }"#;
+    assert!(Transformer::new_sandboxed(env, ".", sandbox).is_err());
+    let env_sandbox = Sandbox {
+        deny_env: false,
+        ..Default::default()
+    };
+    assert!(Transformer::new_sandboxed(env, ".", env_sandbox).is_ok());
+    assert!(Transformer::new_sandboxed(file, ".", sandbox).is_ok());
+    assert!(Transformer::new_sandboxed(file, ".", strict_sandbox).is_err());
+    assert!(Transformer::new_sandboxed(r#"{ "use": ["lib.json"] }"#, ".", strict_sandbox).is_err());
+    assert!(Transformer::new_sandboxed(
+        r#"{ "output": 1, "format": { "pipe": "cat" } }"#,
+        ".",
+        sandbox
+    )
+    .is_err());
+
+    let mut spec = Transformer::new(command, ".").unwrap();
+    assert!(spec.set_sandbox(sandbox).is_err());
+    assert_eq!(spec.transform_value_root(&Value::Null).unwrap(), "$id");
+
+    let mut spec = Transformer::new_sandboxed(r#"{ "output": "$" }"#, ".", sandbox).unwrap();
+    assert!(spec.add_input(Input::command("id", "id")).is_err());
+    assert_eq!(spec.transform_value_root(&Value::from("ok")).unwrap(), "ok");
+    spec.set_format(OutputFormat {
+        pipe: Some("cat".to_string()),
+        ..Default::default()
+    });
+    assert!(spec.transform(&Value::Null).is_err());
+}
+
+#[test]
+fn test_limits() {
+    let recursive = r#"{
+        "input": [ { "name": "loop", "kind": "INLINE", "let": { "x": "$ | loop" }, "source": "$x" } ],
+        "output": "$ | loop"
+    }"#;
+    let mut spec = Transformer::new(recursive, ".").unwrap();
+    spec.set_limits(Limits {
+        max_depth: Some(50),
+        ..Default::default()
+    });
+    let error = spec.transform(&Value::from(1)).unwrap_err().to_string();
+    assert_eq!(
+        error,
+        "nesting depth of input 'loop' exceeds the limit of 50"
+    );
+
+    let spec = r#"{
+        "input": [ { "name": "numbers", "kind": "INLINE", "source": [1, 2, 3] } ],
+        "output": { "a": "$numbers", "b": "$numbers", "c": "$numbers" }
+    }"#;
+    let mut spec = Transformer::new(spec, ".").unwrap();
+    spec.set_limits(Limits {
+        max_expressions: Some(2),
+        ..Default::default()
+    });
+    assert!(spec.transform(&Value::Null).is_err());
+    // the size is that of the compact JSON
+    spec.set_limits(Limits {
+        max_output_size: Some(37),
+        ..Default::default()
+    });
+    assert!(spec.transform(&Value::Null).is_ok());
+    spec.set_limits(Limits {
+        max_output_size: Some(36),
+        ..Default::default()
+    });
+    assert_eq!(
+        spec.transform(&Value::Null).unwrap_err().to_string(),
+        "output size exceeds the limit of 36 bytes"
+    );
+
+    // the transformation stops as soon as the limit is exceeded, without evaluating the rest
+    let spec = r#"{
+        "input": [
+            { "name": "big", "kind": "INLINE", "source": "0123456789" },
+            { "name": "sleep", "kind": "COMMAND", "source": "sleep 5", "capture": "full" }
+        ],
+        "output": [ "$big", "$sleep" ]
+    }"#;
+    let mut spec = Transformer::new(spec, ".").unwrap();
+    spec.set_limits(Limits {
+        max_output_size: Some(10),
+        ..Default::default()
+    });
+    let start = std::time::Instant::now();
+    assert!(spec.transform(&Value::Null).is_err());
+    assert!(start.elapsed() < std::time::Duration::from_secs(2));
+
+    let spec = r#"{
+        "input": [ { "name": "sleep", "kind": "COMMAND", "source": "sleep 5", "capture": "full" } ],
+        "output": "$sleep"
+    }"#;
+    let mut spec = Transformer::new(spec, ".").unwrap();
+    spec.set_limits(Limits {
+        command_timeout: Some(std::time::Duration::from_millis(100)),
+        ..Default::default()
+    });
+    let start = std::time::Instant::now();
+    spec.transform(&Value::Null).unwrap();
+    assert!(start.elapsed() < std::time::Duration::from_secs(2));
+}
+
+// while the transformation waits for the command, the other tasks of the runtime keep running
+#[cfg(feature = "async")]
+async fn check_transform_async() {
+    use std::sync::atomic::{AtomicBool, Ordering};
+    let spec = r#"{
+        "input": [ { "name": "slow", "kind": "COMMAND", "source": "sh -c 'sleep 0.2; echo 1'" } ],
+        "output": { "slow": "$slow" }
+    }"#;
+    let spec = std::sync::Arc::new(Transformer::new(spec, ".").unwrap());
+    let ticked = std::sync::Arc::new(AtomicBool::new(false));
+    let ticker = tokio::spawn({
+        let ticked = ticked.clone();
+        async move { ticked.store(true, Ordering::SeqCst) }
+    });
+    let output = spec.clone().transform_async(Value::Null).await.unwrap();
+    assert!(ticked.load(Ordering::SeqCst));
+    assert_eq!(serde_json::from_str::<Value>(&output).unwrap()["slow"], 1);
+    ticker.await.unwrap();
+    assert_eq!(
+        spec.transform_all_async(Value::Null).await.unwrap().len(),
+        1
+    );
+}
+
+#[cfg(feature = "async")]
+#[tokio::test(flavor = "current_thread")]
+async fn test_transform_async_current_thread() {
+    check_transform_async().await
+}
+
+#[cfg(feature = "async")]
+#[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+async fn test_transform_async_multi_thread() {
+    check_transform_async().await
+}
+
+#[test]
+fn test_cancellation() {
+    let spec = r#"{
+        "input": [ { "name": "slow", "kind": "COMMAND", "source": "sleep 0.3", "capture": "full" } ],
+        "output": [ "$slow", "$slow", "$slow", "$slow" ]
+    }"#;
+    let mut spec = Transformer::new(spec, ".").unwrap();
+    let token = CancellationToken::new();
+    spec.set_cancellation(token.clone());
+    let canceller = token.clone();
+    std::thread::spawn(move || {
+        std::thread::sleep(std::time::Duration::from_millis(100));
+        canceller.cancel();
+    });
+    let start = std::time::Instant::now();
+    let result = spec.transform(&Value::Null);
+    assert!(matches!(result, Err(JsonatrError::Cancelled)));
+    assert!(start.elapsed() < std::time::Duration::from_millis(900));
+    assert!(matches!(
+        spec.transform(&Value::Null),
+        Err(JsonatrError::Cancelled)
+    ));
+}
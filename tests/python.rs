@@ -0,0 +1,42 @@
+#![cfg(feature = "python")]
+
+use jsonatr::python::*;
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+use std::ffi::CString;
+
+fn code(text: &str) -> CString {
+    CString::new(text).unwrap()
+}
+
+#[test]
+fn test_python_transform() {
+    pyo3::prepare_freethreaded_python();
+    Python::with_gil(|py| {
+        let module = PyModule::new(py, "jsonatr").unwrap();
+        module.add_class::<PyTransformer>().unwrap();
+        let locals = PyDict::new(py);
+        locals.set_item("jsonatr", module).unwrap();
+        let result = py
+            .eval(
+                &code(
+                    "jsonatr.Transformer({'output': {'h': '$.height'}}).transform({'height': 5})",
+                ),
+                None,
+                Some(&locals),
+            )
+            .unwrap();
+        let output: std::collections::HashMap<String, Vec<i64>> = result.extract().unwrap();
+        assert_eq!(output["h"], vec![5]);
+
+        let error = py.eval(
+            &code("jsonatr.Transformer('{}').transform()"),
+            None,
+            Some(&locals),
+        );
+        assert_eq!(
+            error.unwrap_err().value(py).to_string(),
+            "no output specified"
+        );
+    });
+}
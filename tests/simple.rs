@@ -1,10 +1,15 @@
+use jsonatr::backend::*;
+use jsonatr::builder::*;
+use jsonatr::error::*;
+use jsonatr::observer::*;
+use jsonatr::output::*;
 use jsonatr::transformer::*;
 use serde_json::Value;
 use std::process::Command;
 
 fn test_expect(file: &str, expect: &str) {
     let input = std::fs::read_to_string(file).unwrap();
-    let mut spec = Transformer::new(&input, file).unwrap();
+    let spec = Transformer::new(&input, file).unwrap();
     let res = spec.transform(&Value::Null).unwrap();
     assert_eq!(res, expect)
 }
@@ -43,7 +48,7 @@ fn test_simple_with_version() {
 
 #[test]
 fn test_simple_with_command() {
-    let output = Command::new("date").args(&["-I"]).output().unwrap();
+    let output = Command::new("date").args(["-I"]).output().unwrap();
     let date = serde_json::Value::String(
         String::from_utf8_lossy(&output.stdout)
             .trim_end()
@@ -66,3 +71,880 @@ fn test_simple_with_command() {
         ),
     );
 }
+
+#[test]
+fn test_simple_with_now() {
+    let output = Command::new("date").args(["-I"]).output().unwrap();
+    let date = serde_json::Value::String(
+        String::from_utf8_lossy(&output.stdout)
+            .trim_end()
+            .to_string(),
+    );
+    test_expect(
+        "tests/support/simple_with_now.json",
+        &format!(
+            r#"{{
+  "tool": "jsonatr",
+  "version": 0.1,
+  "date": {},
+  "stable": false,
+  "features": [
+    "read",
+    "write"
+  ]
+}}"#,
+            date
+        ),
+    );
+}
+
+#[test]
+fn test_simple_with_toml() {
+    test_expect(
+        "tests/support/simple_with_toml.json",
+        r#"{
+  "moniker": "node0",
+  "peers": [
+    "node1@10.0.0.2:26656",
+    "node2@10.0.0.3:26656"
+  ],
+  "consensus": {
+    "timeout_propose": "3s",
+    "create_empty_blocks_interval": 0,
+    "genesis_time": "2020-06-01T12:00:00Z"
+  }
+}"#,
+    );
+}
+
+#[test]
+fn test_simple_shared_between_threads() {
+    let spec = std::sync::Arc::new(
+        Transformer::new(r#"{ "output": { "id": "$.id | unwrap | add(1)" } }"#, ".").unwrap(),
+    );
+    let handles: Vec<_> = (0..4)
+        .map(|id| {
+            let spec = spec.clone();
+            std::thread::spawn(move || {
+                spec.transform_value_root(&serde_json::json!({ "id": id }))
+                    .unwrap()
+            })
+        })
+        .collect();
+    for (id, handle) in handles.into_iter().enumerate() {
+        assert_eq!(handle.join().unwrap()["id"], id + 1);
+    }
+}
+
+#[test]
+fn test_simple_path_engine() {
+    let spec = TransformerBuilder::new()
+        .path_engine(std::sync::Arc::new(jsonatr::path::PointerEngine))
+        .output(serde_json::json!({ "second": "$/items/1 | unwrap", "bad": "$.items" }))
+        .build()
+        .unwrap();
+    let input = serde_json::json!({ "items": ["a", "b"] });
+    assert_eq!(
+        spec.transform_value_root(&input).unwrap(),
+        serde_json::json!({ "second": "b", "bad": "$.items" })
+    );
+    assert_eq!(
+        spec.validate()
+            .iter()
+            .map(|d| d.to_string())
+            .collect::<Vec<_>>(),
+        vec!["output: failed to apply JSONPath '.items'; reason: a JSON Pointer should start with '/' in '$.items'"]
+    );
+}
+
+#[test]
+fn test_simple_error_handler() {
+    let build = |action: ErrorAction| {
+        TransformerBuilder::new()
+            .error_handler(std::sync::Arc::new(move |expr: &str, error: &str| {
+                assert_eq!(expr, "$ | to_fixed(x)");
+                assert!(error.starts_with("failed to apply builtin transform 'to_fixed'"));
+                action
+            }))
+            .output(serde_json::json!({ "ok": "$ | unwrap", "bad": "$ | to_fixed(x)" }))
+            .build()
+            .unwrap()
+    };
+    let input = serde_json::json!([1]);
+    assert_eq!(
+        build(ErrorAction::Keep)
+            .transform_value_root(&input)
+            .unwrap(),
+        serde_json::json!({ "ok": 1, "bad": "$ | to_fixed(x)" })
+    );
+    assert_eq!(
+        build(ErrorAction::Null)
+            .transform_value_root(&input)
+            .unwrap(),
+        serde_json::json!({ "ok": 1, "bad": null })
+    );
+    assert!(build(ErrorAction::Abort)
+        .transform_value_root(&input)
+        .is_err());
+}
+
+#[test]
+fn test_simple_compile() {
+    let spec = Transformer::new(
+        r#"{ "input": [ { "name": "offset", "kind": "INLINE", "source": 10 } ], "output": "$" }"#,
+        ".",
+    )
+    .unwrap();
+    let expr = spec.compile("$.height | unwrap | add($offset)").unwrap();
+    assert_eq!(expr.expr().input, "");
+    assert_eq!(expr.expr().jpath, ".height");
+    assert_eq!(
+        expr.expr().transforms,
+        vec![
+            ("unwrap".to_string(), vec![]),
+            ("add".to_string(), vec!["$offset".to_string()])
+        ]
+    );
+    for height in 1..4 {
+        let value = spec
+            .eval(&expr, &serde_json::json!({ "height": height }))
+            .unwrap();
+        assert_eq!(value, height + 10);
+    }
+    assert!(spec
+        .eval(&expr, &serde_json::json!({ "height": "x" }))
+        .is_err());
+
+    assert!(spec.compile("height").is_err());
+    assert!(spec.compile("$missing").is_err());
+    assert_eq!(Expr::parse("$missing | unwrap").unwrap().input, "missing");
+}
+
+#[test]
+fn test_simple_transform_iter() {
+    let spec = Transformer::new(r#"{ "output": { "id": "$.id | unwrap" } }"#, ".").unwrap();
+    let lines = "{\"id\": 1}\n{\"id\": 2}\n{\"id\": 3}\n";
+    let inputs = lines
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap());
+    let outputs: Vec<Value> = spec
+        .transform_iter(inputs)
+        .unwrap()
+        .map(|output| output.unwrap())
+        .collect();
+    assert_eq!(
+        outputs,
+        vec![
+            serde_json::json!({ "id": 1 }),
+            serde_json::json!({ "id": 2 }),
+            serde_json::json!({ "id": 3 })
+        ]
+    );
+}
+
+#[test]
+fn test_simple_toml_backend() {
+    let spec = Transformer::new(
+        r#"{ "output": { "node": { "name": "$.moniker", "since": "$.genesis_time | unwrap" } } }"#,
+        ".",
+    )
+    .unwrap();
+    let input = "moniker = \"node0\"\ngenesis_time = 2020-06-01T12:00:00Z\n";
+    let output = spec.transform_text(&TomlBackend, input).unwrap();
+    assert_eq!(
+        output,
+        "[node]\nname = [\"node0\"]\nsince = 2020-06-01T12:00:00Z\n"
+    );
+}
+
+#[test]
+fn test_simple_yaml_backend() {
+    let spec = Transformer::new(
+        r#"{ "output": { "id": "$.id | unwrap", "amount": "$.amount | unwrap" } }"#,
+        ".",
+    )
+    .unwrap();
+    let input = "id: 7\namount: !Coin 5\n";
+    let output = spec.transform_text(&YamlBackend, input).unwrap();
+    assert_eq!(output, "id: 7\namount: !Coin 5\n");
+}
+
+#[test]
+fn test_simple_with_csv() {
+    test_expect(
+        "tests/support/simple_with_csv.json",
+        r#"{
+  "validators": [
+    {
+      "address": "A",
+      "power": 30,
+      "jailed": false,
+      "pub_key": {
+        "type": "ed25519",
+        "value": "AAAA"
+      }
+    },
+    {
+      "address": "B",
+      "power": 50,
+      "jailed": true,
+      "pub_key": {
+        "type": "ed25519",
+        "value": "BBBB"
+      }
+    }
+  ],
+  "total_power": 80
+}"#,
+    );
+}
+
+#[test]
+fn test_simple_with_glob() {
+    test_expect(
+        "tests/support/simple_with_glob.json",
+        r#"{
+  "paths": [
+    "tests/support/simple_with_version.json",
+    "tests/support/version.json"
+  ],
+  "descriptions": [
+    "Simple output with external version"
+  ],
+  "version": "0.1"
+}"#,
+    );
+}
+
+#[test]
+fn test_simple_with_binary() {
+    test_expect(
+        "tests/support/simple_with_binary.json",
+        r#"{
+  "cbor": {
+    "height": 42,
+    "hash": [
+      94,
+      139
+    ],
+    "chain": "test"
+  },
+  "msgpack_hash": "5e8b"
+}"#,
+    );
+}
+
+#[test]
+fn test_simple_with_archive() {
+    test_expect(
+        "tests/support/simple_with_archive.json",
+        r#"{
+  "zipped": {
+    "height": 1,
+    "events": [
+      "propose"
+    ]
+  },
+  "tarred": {
+    "height": 2,
+    "events": [
+      "commit"
+    ]
+  }
+}"#,
+    );
+}
+
+#[test]
+fn test_simple_with_placeholders() {
+    test_expect(
+        "tests/support/simple_with_placeholders.json",
+        r#"{
+  "trace": {
+    "height": 1,
+    "events": [
+      "propose",
+      "commit"
+    ]
+  },
+  "label": "test-trace1"
+}"#,
+    );
+}
+
+#[test]
+fn test_simple_compact() {
+    test_expect(
+        "tests/support/simple_compact.json",
+        r#"{"tool":"jsonatr","version":0.1,"stable":false,"features":["read","write"]}"#,
+    );
+}
+
+#[test]
+fn test_simple_set_compact() {
+    let input = std::fs::read_to_string("tests/support/simple.json").unwrap();
+    let mut spec = Transformer::new(&input, "tests/support/simple.json").unwrap();
+    spec.set_compact(true);
+    let res = spec.transform(&Value::Null).unwrap();
+    assert_eq!(
+        res,
+        r#"{"tool":"jsonatr","version":0.1,"stable":false,"features":["read","write"]}"#
+    )
+}
+
+#[test]
+fn test_simple_binary_output() {
+    let file = "tests/support/simple_cbor.json";
+    let input = std::fs::read_to_string(file).unwrap();
+    let mut spec = Transformer::new(&input, file).unwrap();
+    let mut json = Transformer::new(&input, file).unwrap();
+    json.set_format(OutputFormat::default());
+    let expected: Value = serde_json::from_str(&json.transform(&Value::Null).unwrap()).unwrap();
+    let res = spec.transform_bytes(&Value::Null).unwrap();
+    let decoded: Value = ciborium::de::from_reader(&res[..]).unwrap();
+    assert_eq!(decoded, expected);
+    assert!(spec.transform(&Value::Null).is_err());
+
+    let mut format = spec.format().clone();
+    format.encoding = Encoding::MsgPack;
+    spec.set_format(format);
+    let res = spec.transform_bytes(&Value::Null).unwrap();
+    assert_eq!(rmp_serde::from_slice::<Value>(&res).unwrap(), expected);
+}
+
+#[test]
+fn test_simple_indented() {
+    let file = "tests/support/simple_indented.json";
+    let input = std::fs::read_to_string(file).unwrap();
+    let spec = Transformer::new(&input, file).unwrap();
+    let res = spec.transform(&Value::Null).unwrap();
+    let expected =
+        "{\n\t\"alpha\": {\n\t\t\"x\": [\n\t\t\t2\n\t\t],\n\t\t\"y\": true\n\t},\n\t\"zeta\": 1\n}";
+    assert_eq!(res, expected);
+
+    // only the whitespace allowed in JSON can indent it
+    let spec = r#"{ "output": { "a": 1 }, "format": { "indent_char": "x" } }"#;
+    let error = Transformer::new(spec, "").unwrap().transform(&Value::Null);
+    assert_eq!(
+        error.unwrap_err().to_string(),
+        "wrong indentation character 'x': only space, tab, and line breaks are allowed in JSON"
+    );
+}
+
+#[test]
+fn test_simple_named_outputs() {
+    let file = "tests/support/simple_with_outputs.json";
+    let input = std::fs::read_to_string(file).unwrap();
+    let spec = Transformer::new(&input, file).unwrap();
+    let res = spec.transform_all(&Value::Null).unwrap();
+    assert_eq!(res.len(), 3);
+    assert_eq!(res[0].name, None);
+    assert_eq!(res[1].name.as_deref(), Some("report"));
+    assert_eq!(res[1].file.as_deref(), Some("report.json"));
+    assert_eq!(res[2].format.encoding, Encoding::MsgPack);
+
+    let main: Value = serde_json::from_slice(&res[0].content).unwrap();
+    let stamp = main["stamp"].clone();
+    let report = String::from_utf8(res[1].content.clone()).unwrap();
+    assert_eq!(report, format!("{{\"report\":{}}}", stamp));
+    let binary: Value = rmp_serde::from_slice(&res[2].content).unwrap();
+    assert_eq!(binary, Value::Array(vec![stamp]));
+}
+
+#[test]
+fn test_simple_raw() {
+    let file = "tests/support/simple_raw.json";
+    let input = std::fs::read_to_string(file).unwrap();
+    let mut spec = Transformer::new(&input, file).unwrap();
+    assert_eq!(spec.transform(&Value::Null).unwrap(), "v0.1.0\n\"beta\"");
+    spec.set_raw(false);
+    assert_eq!(
+        spec.transform(&Value::Null).unwrap(),
+        "\"v0.1.0\\n\\\"beta\\\"\""
+    );
+}
+
+#[test]
+fn test_simple_with_template() {
+    let expected = "# Validators\n- alice & co: 10\n- bob: 20\nTotal: 30\n";
+    let file = "tests/support/simple_with_template.json";
+    let input = std::fs::read_to_string(file).unwrap();
+    let spec = Transformer::new(&input, file).unwrap();
+    assert_eq!(spec.transform(&Value::Null).unwrap(), expected);
+}
+
+#[test]
+fn test_simple_with_pipe() {
+    test_expect("tests/support/simple_with_pipe.json", "{\"HASH\":\"ABC\"}");
+}
+
+#[test]
+fn test_simple_with_split() {
+    let file = "tests/support/simple_with_split.json";
+    let input = std::fs::read_to_string(file).unwrap();
+    let spec = Transformer::new(&input, file).unwrap();
+    let res = spec.transform_all(&Value::Null).unwrap();
+    let files: Vec<(Option<String>, String)> = res
+        .into_iter()
+        .map(|o| (o.file, String::from_utf8(o.content).unwrap()))
+        .collect();
+    assert_eq!(
+        files,
+        vec![
+            (
+                Some("fixtures/genesis_0.json".to_string()),
+                "{\"name\":\"genesis\",\"height\":1}".to_string()
+            ),
+            (
+                Some("fixtures/next_1.json".to_string()),
+                "{\"name\":\"next\",\"height\":2}".to_string()
+            ),
+        ]
+    );
+}
+
+#[test]
+fn test_split_file_names() {
+    // the values from the data can't make the files go into other directories
+    for name in ["../escaped", "/etc/escaped", "sub/dir", "a\\b", ".."] {
+        let spec = serde_json::json!({
+            "outputs": [{ "name": "o", "output": [{ "name": name }], "split": "out/{.name}.json" }]
+        });
+        let spec = Transformer::new(&spec.to_string(), ".").unwrap();
+        let error = spec.transform_all(&Value::Null).unwrap_err().to_string();
+        assert!(
+            error.contains("can't be a part of a file name"),
+            "{}",
+            error
+        );
+    }
+}
+
+#[test]
+fn test_simple_with_merge() {
+    let file = "tests/support/simple_with_merge.json";
+    let input = std::fs::read_to_string(file).unwrap();
+    let spec = Transformer::new(&input, file).unwrap();
+    let res = spec.transform_all(&Value::Null).unwrap();
+    let path = std::env::temp_dir().join(format!("jsonatr-merge-{}.json", std::process::id()));
+    let path = path.to_str().unwrap();
+    std::fs::write(
+        path,
+        r#"{"blocks":{"genesis":{"height":1}},"latest":1,"chain":"test"}"#,
+    )
+    .unwrap();
+    res[0].write_to(path).unwrap();
+    let merged = std::fs::read_to_string(path).unwrap();
+    std::fs::remove_file(path).unwrap();
+    assert_eq!(
+        merged,
+        r#"{"blocks":{"genesis":{"height":1},"next":{"height":2}},"latest":2,"chain":"test"}"#
+    );
+}
+
+#[test]
+fn test_simple_with_backup() {
+    let file = "tests/support/simple_compact.json";
+    let input = std::fs::read_to_string(file).unwrap();
+    let mut spec = Transformer::new(&input, file).unwrap();
+    let mut format = spec.format().clone();
+    format.backup = true;
+    spec.set_format(format);
+    let res = spec.transform_all(&Value::Null).unwrap();
+    let dir = std::env::temp_dir().join(format!("jsonatr-backup-{}", std::process::id()));
+    let path = dir.join("out.json");
+    let path = path.to_str().unwrap();
+    res[0].write_to(path).unwrap();
+    std::fs::write(path, "previous").unwrap();
+    res[0].write_to(path).unwrap();
+    let written = std::fs::read(path).unwrap();
+    let backup = std::fs::read_to_string(format!("{}.bak", path)).unwrap();
+    let entries = std::fs::read_dir(&dir).unwrap().count();
+    std::fs::remove_dir_all(&dir).unwrap();
+    assert_eq!(written, res[0].content);
+    assert_eq!(backup, "previous");
+    assert_eq!(entries, 2);
+
+    // when the backup can't be made, the file is kept, and no temporary file is left behind
+    let path = dir.join("out.json");
+    std::fs::create_dir_all(dir.join("out.json.bak")).unwrap();
+    std::fs::write(&path, "previous").unwrap();
+    assert!(res[0].write_to(path.to_str().unwrap()).is_err());
+    let kept = std::fs::read_to_string(&path).unwrap();
+    let entries = std::fs::read_dir(&dir).unwrap().count();
+    std::fs::remove_dir_all(&dir).unwrap();
+    assert_eq!(kept, "previous");
+    assert_eq!(entries, 2);
+}
+
+#[test]
+fn test_simple_errors() {
+    match Transformer::new("{ \"output\": ", "") {
+        Err(JsonatrError::ParseError { format, .. }) => assert_eq!(format, "JSON"),
+        other => panic!("unexpected result: {:?}", other.err()),
+    }
+    match Transformer::new(
+        r#"{ "use": ["missing.json"] }"#,
+        "tests/support/simple.json",
+    ) {
+        Err(e @ JsonatrError::IoError { .. }) => assert!(std::error::Error::source(&e).is_some()),
+        other => panic!("unexpected result: {:?}", other.err()),
+    }
+    let spec =
+        Transformer::new(r#"{ "format": { "pipe": "false" }, "output": "hash" }"#, "").unwrap();
+    match spec.transform(&Value::Null) {
+        Err(JsonatrError::CommandFailed { command, .. }) => assert_eq!(command, "false"),
+        other => panic!("unexpected result: {:?}", other),
+    }
+}
+
+#[test]
+fn test_simple_transform_value_root() {
+    let file = "tests/support/simple_with_inline.json";
+    let input = std::fs::read_to_string(file).unwrap();
+    let spec = Transformer::new(&input, file).unwrap();
+    let value = spec.transform_value_root(&Value::Null).unwrap();
+    let string = spec.transform(&Value::Null).unwrap();
+    assert_eq!(value, serde_json::from_str::<Value>(&string).unwrap());
+    assert!(Transformer::empty("")
+        .transform_value_root(&Value::Null)
+        .is_err());
+}
+
+#[test]
+fn test_simple_builder() {
+    let version: Input = serde_json::from_value(serde_json::json!({
+        "name": "version",
+        "kind": "INLINE",
+        "source": "0.1.0"
+    }))
+    .unwrap();
+    let spec = TransformerBuilder::new()
+        .path("tests/support/simple.json")
+        .use_file("../../lib/unix.json")
+        .input(version)
+        .output(serde_json::json!({ "version": "$version", "date": "$date | type" }))
+        .format(serde_json::from_str(r#"{ "compact": true }"#).unwrap())
+        .build()
+        .unwrap();
+    assert_eq!(
+        spec.transform(&Value::Null).unwrap(),
+        r#"{"version":"0.1.0","date":"string"}"#
+    );
+
+    let builder = TransformerBuilder::new().output(serde_json::json!({ "version": "$missing" }));
+    let lenient = builder.clone().build().unwrap();
+    let strict = builder.strict(true).build().unwrap();
+    assert!(lenient.transform(&Value::Null).is_ok());
+    assert!(strict.transform(&Value::Null).is_err());
+}
+
+#[test]
+fn test_simple_strict_errors() {
+    let input = serde_json::json!({ "height": 5 });
+    let strict_error = |output: &str| {
+        let mut spec = Transformer::new(&format!(r#"{{ "output": {} }}"#, output), "").unwrap();
+        assert!(spec.transform(&input).is_ok());
+        spec.set_strict(true);
+        spec.transform(&input).unwrap_err()
+    };
+    assert!(matches!(
+        strict_error(r#"{ "a": "$missing" }"#),
+        JsonatrError::UnknownInput(name) if name == "missing"
+    ));
+    assert!(matches!(
+        strict_error(r#"{ "a": "$.height | nosuch" }"#),
+        JsonatrError::UnknownInput(name) if name == "nosuch"
+    ));
+    assert!(matches!(
+        strict_error(r#"{ "a": "$.[[" }"#),
+        JsonatrError::JsonPathError { .. }
+    ));
+    match strict_error(r#"{ "a": { "b": "$.height | add('x')" } }"#) {
+        JsonatrError::EvaluationError {
+            message,
+            location: Some(location),
+        } => {
+            assert_eq!(message, "failed to apply builtin transform 'add'");
+            assert_eq!(
+                location,
+                ErrorLocation {
+                    pointer: "/a/b".to_string(),
+                    expression: "$.height | add('x')".to_string(),
+                    builtin: Some("add".to_string()),
+                    input: None,
+                }
+            );
+        }
+        error => panic!("unexpected error: {:?}", error),
+    }
+    let mut spec = Transformer::new(r#"{ "output": { "a": "$.height" } }"#, "").unwrap();
+    assert_eq!(
+        spec.transform(&Value::Null).unwrap(),
+        "{\n  \"a\": \"$.height\"\n}"
+    );
+    spec.set_strict(true);
+    let error = spec.transform(&Value::Null).unwrap_err();
+    assert!(matches!(error, JsonatrError::EvaluationError { .. }));
+    assert!(error
+        .to_string()
+        .starts_with("no main input to evaluate against at '/a'"));
+
+    // the failure is of the same kind whichever way the transformation is run
+    let spec = TransformerBuilder::new()
+        .output(serde_json::json!({ "a": "$.height | nosuch" }))
+        .strict(true)
+        .build()
+        .unwrap();
+    let unknown =
+        |e: &JsonatrError| matches!(e, JsonatrError::UnknownInput(name) if name == "nosuch");
+    assert!(unknown(&spec.transform_value_root(&input).unwrap_err()));
+    assert!(unknown(&spec.transform_all(&input).unwrap_err()));
+    let mut results = spec.transform_iter(vec![input.clone()]).unwrap();
+    assert!(unknown(&results.next().unwrap().unwrap_err()));
+}
+
+fn transform_each(spec: &Transformer, roots: &[Value]) -> Vec<Value> {
+    roots
+        .iter()
+        .map(|root| spec.transform_value_root(root).unwrap())
+        .collect()
+}
+
+#[test]
+fn test_simple_shared_spec() {
+    let spec = Transformer::new(
+        r#"{ "output": { "height": "$.height | unwrap | add(1)" } }"#,
+        "",
+    )
+    .unwrap();
+    let roots: Vec<Value> = (1..4).map(|h| serde_json::json!({ "height": h })).collect();
+    let expected: Vec<Value> = (2..5).map(|h| serde_json::json!({ "height": h })).collect();
+    assert_eq!(transform_each(&spec, &roots), expected);
+    assert_eq!(transform_each(&spec, &roots), expected);
+}
+
+#[test]
+fn test_simple_clone_and_serialize() {
+    let file = "tests/support/simple_with_inline.json";
+    let input = std::fs::read_to_string(file).unwrap();
+    let spec = Transformer::new(&input, file).unwrap();
+    let mut copy = spec.clone();
+    copy.set_compact(true);
+    let extra: Input = serde_json::from_value(serde_json::json!({
+        "name": "extra",
+        "kind": "INLINE",
+        "source": [1, 2]
+    }))
+    .unwrap();
+    copy.add_input(extra).unwrap();
+
+    let written = serde_json::to_value(&copy).unwrap();
+    assert_eq!(written["format"], serde_json::json!({ "compact": true }));
+    assert_eq!(written["input"][0]["kind"], "FILE");
+    assert_eq!(written["input"][2]["name"], "extra");
+    assert!(written["input"][0].get("stdin").is_none());
+
+    let reloaded = Transformer::new(&written.to_string(), file).unwrap();
+    assert_eq!(
+        reloaded.transform(&Value::Null).unwrap(),
+        copy.transform(&Value::Null).unwrap()
+    );
+    assert_ne!(
+        spec.transform(&Value::Null).unwrap(),
+        copy.transform(&Value::Null).unwrap()
+    );
+}
+
+#[test]
+fn test_simple_from_value() {
+    let file = "tests/support/simple_with_use.json";
+    let input = std::fs::read_to_string(file).unwrap();
+    let value: Value = serde_json::from_str(&input).unwrap();
+    let from_str = Transformer::new(&input, file).unwrap();
+    let from_value = Transformer::from_value(value, file).unwrap();
+    assert_eq!(
+        serde_json::to_value(&from_value).unwrap(),
+        serde_json::to_value(&from_str).unwrap()
+    );
+    assert!(Transformer::from_value(serde_json::json!({ "input": 1 }), "").is_err());
+}
+
+#[test]
+fn test_simple_validate() {
+    for file in &[
+        "tests/support/simple_with_local.json",
+        "tests/support/apalache_counterexample_to_lightclient_test.json",
+    ] {
+        let spec = Transformer::new(&std::fs::read_to_string(file).unwrap(), file).unwrap();
+        assert_eq!(spec.validate(), vec![]);
+    }
+
+    let spec = Transformer::new(
+        r#"{
+            "input": [
+                { "name": "missing", "kind": "FILE", "source": "/nonexistent/{$nowhere}.json" },
+                { "name": "pair", "kind": "INLINE", "source": ["$ | first"] }
+            ],
+            "output": {
+                "a": "$missing.height | add(1, 2)",
+                "b": "$pair | unknown",
+                "c": "$ | map(nothing)"
+            }
+        }"#,
+        ".",
+    )
+    .unwrap();
+    let diagnostics: Vec<String> = spec.validate().iter().map(|d| d.to_string()).collect();
+    assert_eq!(
+        diagnostics,
+        vec![
+            "output: wrong number of arguments (2) of builtin 'add' in '$missing.height | add(1, 2)'",
+            "output: unknown builtin or input 'unknown' in '$pair | unknown'",
+            "output: unknown input 'nothing' in arguments of 'map' in '$ | map(nothing)'",
+            "input.missing.source: unknown input 'nowhere' in '$nowhere'",
+        ]
+    );
+}
+
+#[test]
+fn test_simple_dependencies() {
+    let file = "tests/support/simple_with_local.json";
+    let spec = Transformer::new(&std::fs::read_to_string(file).unwrap(), file).unwrap();
+    let names: Vec<&str> = spec.inputs().iter().map(|i| i.name()).collect();
+    assert!(names.windows(2).all(|w| w[0] < w[1]));
+    let timestamp = spec.input("timestamp").unwrap();
+    assert_eq!(timestamp.kind(), &InputKind::COMMAND);
+    assert!(spec.input("nothing").is_none());
+
+    let spec = Transformer::new(
+        r#"{
+            "input": [
+                { "name": "a", "kind": "INLINE", "source": 1 },
+                { "name": "b", "kind": "INLINE", "let": { "x": "$a" }, "source": ["$x", "$ | c"] },
+                { "name": "c", "kind": "COMMAND", "source": "echo {$a}" }
+            ],
+            "output": "$b | map(c) | ifelse($ | a, c)"
+        }"#,
+        ".",
+    )
+    .unwrap();
+    assert_eq!(
+        spec.references("$b | map(c) | default($a)"),
+        ["a", "b", "c"].iter().map(|s| s.to_string()).collect()
+    );
+    let dependencies: Vec<(String, Vec<String>)> = spec
+        .dependencies()
+        .into_iter()
+        .map(|(k, v)| (k, v.into_iter().collect()))
+        .collect();
+    let strings = |names: &[&str]| names.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+    assert_eq!(
+        dependencies,
+        vec![
+            ("input.a".to_string(), strings(&[])),
+            ("input.b".to_string(), strings(&["a", "c"])),
+            ("input.c".to_string(), strings(&["a"])),
+            ("output".to_string(), strings(&["a", "b", "c"])),
+        ]
+    );
+}
+
+#[derive(Default)]
+struct Recorder {
+    events: std::sync::Mutex<Vec<String>>,
+}
+
+impl EvaluationObserver for Recorder {
+    fn expression_start(&self, expr: &str) {
+        self.events.lock().unwrap().push(format!("start {}", expr));
+    }
+
+    fn expression_end(&self, expr: &str, result: Option<&Value>) {
+        self.events
+            .lock()
+            .unwrap()
+            .push(format!("end {} = {}", expr, result.unwrap()));
+    }
+
+    fn input_end(&self, input: &Input, _result: Option<&Value>) {
+        self.events
+            .lock()
+            .unwrap()
+            .push(format!("input {}", input.name()));
+    }
+
+    fn builtin_applied(&self, name: &str, args: &[String], _result: Option<&Value>) {
+        self.events
+            .lock()
+            .unwrap()
+            .push(format!("builtin {}({})", name, args.join(", ")));
+    }
+}
+
+#[test]
+fn test_simple_observer() {
+    let mut spec = Transformer::new(
+        r#"{
+            "input": [ { "name": "numbers", "kind": "INLINE", "source": [1, 2] } ],
+            "output": { "sum": "$numbers | sum | add(1)", "text": "plain" }
+        }"#,
+        ".",
+    )
+    .unwrap();
+    let recorder = std::sync::Arc::new(Recorder::default());
+    spec.set_observer(recorder.clone());
+    assert_eq!(spec.transform_value_root(&Value::Null).unwrap()["sum"], 4);
+    assert_eq!(
+        *recorder.events.lock().unwrap(),
+        vec![
+            "start $numbers | sum | add(1)",
+            "input numbers",
+            "builtin sum()",
+            "builtin add(1)",
+            "end $numbers | sum | add(1) = 4",
+        ]
+    );
+}
+
+#[test]
+fn test_simple_errors_per_call() {
+    let spec = Transformer::new(
+        r#"{ "output": { "ok": "$ | unwrap", "missing": "$nothing", "bad": "$ | to_fixed(x)" } }"#,
+        ".",
+    )
+    .unwrap();
+    let (value, errors) = spec
+        .transform_value_with_errors(&serde_json::json!([1]))
+        .unwrap();
+    assert_eq!(value["ok"], 1);
+    assert_eq!(value["missing"], "$nothing");
+    assert_eq!(errors.len(), 2);
+    assert!(errors[0].contains("unknown input 'nothing'"));
+
+    let (_, errors) = spec
+        .transform_value_with_errors(&serde_json::json!([1]))
+        .unwrap();
+    assert_eq!(errors.len(), 2);
+}
+
+#[test]
+fn test_simple_error_locations() {
+    let spec = Transformer::new(
+        r#"{
+            "input": [ { "name": "broken", "kind": "INLINE", "source": "$nothing" } ],
+            "output": { "validators": [ 1, { "a/b": "$ | broken | to_fixed(x)" } ], "top": "$missing" }
+        }"#,
+        ".",
+    )
+    .unwrap();
+    let (_, errors) = spec.transform_value_with_errors(&Value::from(1)).unwrap();
+    assert_eq!(
+        errors,
+        vec![
+            "failed to apply transform; reason: found reference to unknown input 'nothing' at '/validators/1/a~1b' in expression '$ | broken | to_fixed(x)'",
+            "failed to apply builtin transform 'to_fixed' at '/validators/1/a~1b' in expression '$ | broken | to_fixed(x)'",
+            "failed to apply transform; reason: found reference to unknown input 'missing' at '/top' in expression '$missing'",
+        ]
+    );
+}